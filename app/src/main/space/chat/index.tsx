@@ -6,6 +6,7 @@ import { useSpacesContext } from '~/main/user/SpacesProvider';
 import {
 	Message,
 	MessageWithTasksAndPeer,
+	MessagesUpdate,
 	useAuth,
 	useRspcSpaceContext,
 	useSpaceMutation,
@@ -92,6 +93,8 @@ export default () => {
 	);
 
 	const [subMessages, setSubMessages] = useState<MessageWithTasksAndPeer[]>([]);
+	// partial text of responses that are still being generated, by message id
+	const [streamingText, setStreamingText] = useState<Record<string, string>>({});
 
 	useSpaceSubscription(['messages.updates'], {
 		onStarted: () => {
@@ -100,9 +103,13 @@ export default () => {
 		onError: (err) => {
 			console.error('messages.updates error', err);
 		},
-		onData: (newOrUpdatesMessages: MessageWithTasksAndPeer[]) => {
-			console.log('messages.updates data', newOrUpdatesMessages);
-			setSubMessages(newOrUpdatesMessages);
+		onData: (update: MessagesUpdate) => {
+			if (update.type === 'Delta') {
+				setStreamingText((texts) => ({ ...texts, [update.message_id]: update.text }));
+				return;
+			}
+			console.log('messages.updates data', update.messages);
+			setSubMessages(update.messages);
 		}
 	});
 
@@ -153,10 +160,16 @@ export default () => {
 			mapById.set(m.id_str, [...messages, m]);
 		});
 
-		return ([...mapById.values()].map((ms) => ms[0]) as Message[]).sort(
-			(a, b) => new Date(a.date_created).getTime() - new Date(b.date_created).getTime()
-		);
-	}, [queryMessages, subMessages, outboxMesages]);
+		return ([...mapById.values()].map((ms) => ms[0]) as Message[])
+			.map((m) =>
+				m.response_status === 1 && streamingText[m.id_str] !== undefined
+					? { ...m, text: streamingText[m.id_str] }
+					: m
+			)
+			.sort(
+				(a, b) => new Date(a.date_created).getTime() - new Date(b.date_created).getTime()
+			);
+	}, [queryMessages, subMessages, outboxMesages, streamingText]);
 
 	const scrollRef = useRef<HTMLDivElement>(null);
 	const scrollToEnd = () => {
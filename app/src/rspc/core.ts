@@ -3,63 +3,287 @@
 
 export type Procedures = {
     queries: 
+        { key: "backend.health", input: UserArgs<null>, result: BackendHealth } | 
+        { key: "concepts.list", input: SpaceArgs<ConceptFilter>, result: ConceptEntry[] } | 
+        { key: "conversations.list", input: SpaceArgs<null>, result: Conversation[] } | 
         { key: "files.list", input: SpaceArgs<null>, result: FileWithTasks[] } | 
+        { key: "files.summary", input: SpaceArgs<FileSummaryArgs>, result: FileSummary | null } | 
+        { key: "flashcards.due", input: SpaceArgs<FlashcardDueArgs>, result: ScheduledFlashcard[] } | 
+        { key: "flashcards.list", input: SpaceArgs<FlashcardFilter>, result: ScheduledFlashcard[] } | 
         { key: "invalidation.test-invalidate", input: never, result: number } | 
+        { key: "messages.export", input: SpaceArgs<MessageExportArgs>, result: ExportedFile } | 
+        { key: "messages.exportRatings", input: SpaceArgs<null>, result: RatedAnswer[] } | 
         { key: "messages.list", input: SpaceArgs<MessageListArgs>, result: MessagesWrapped } | 
+        { key: "messages.ratings", input: SpaceArgs<null>, result: RatingStats } | 
+        { key: "quizzes.attempts", input: SpaceArgs<QuizArgs>, result: QuizAttemptResult[] } | 
+        { key: "quizzes.get", input: SpaceArgs<QuizArgs>, result: QuizDetails } | 
+        { key: "quizzes.list", input: SpaceArgs<null>, result: QuizOverview[] } | 
+        { key: "search.query", input: UserArgs<SearchQueryArgs>, result: SearchHit[] } | 
+        { key: "spaces.indexStats", input: SpaceArgs<null>, result: IndexStats } | 
         { key: "spaces.list", input: UserArgs<null>, result: SpaceWrapped[] } | 
-        { key: "tasks.list", input: SpaceArgs<null>, result: Task[] },
+        { key: "tasks.list", input: SpaceArgs<null>, result: Task[] } | 
+        { key: "users.sessions", input: UserArgs<null>, result: Session[] },
     mutations: 
+        { key: "conversations.create", input: SpaceArgs<CreateConversationArgs>, result: Conversation } | 
+        { key: "conversations.delete", input: SpaceArgs<DeleteConversationArgs>, result: null } | 
+        { key: "conversations.rename", input: SpaceArgs<RenameConversationArgs>, result: Conversation } | 
+        { key: "files.delete", input: SpaceArgs<DeleteFileArgs>, result: null } | 
+        { key: "files.refresh", input: SpaceArgs<null>, result: string[] } | 
+        { key: "flashcards.delete", input: SpaceArgs<DeleteFlashcardArgs>, result: null } | 
+        { key: "flashcards.grade", input: SpaceArgs<FlashcardGradeArgs>, result: ScheduledFlashcard } | 
         { key: "invalidation.test-invalidate-mutation", input: SpaceArgs<null>, result: null } | 
+        { key: "messages.edit", input: SpaceArgs<MessageEditArgs>, result: MessageWithTasksAndPeer } | 
+        { key: "messages.exportLink", input: SpaceArgs<MessageExportLinkArgs>, result: string } | 
+        { key: "messages.rate", input: SpaceArgs<MessageRateArgs>, result: MessageWithTasksAndPeer } | 
+        { key: "messages.regenerate", input: SpaceArgs<MessageRegenerateArgs>, result: null } | 
+        { key: "messages.select", input: SpaceArgs<MessageSelectArgs>, result: Conversation } | 
         { key: "messages.send", input: SpaceArgs<MessageSendArgs>, result: MessageWithTasksAndPeer } | 
+        { key: "quizzes.delete", input: SpaceArgs<QuizArgs>, result: null } | 
+        { key: "quizzes.submit", input: SpaceArgs<QuizSubmitArgs>, result: QuizAttemptResult } | 
         { key: "spaces.create", input: UserArgs<CreateSpaceArgs>, result: SpaceWrapped } | 
         { key: "spaces.createFirst", input: UserArgs<null>, result: SpaceWrapped } | 
         { key: "spaces.delete", input: UserArgs<DeleteSpaceArgs>, result: null } | 
         { key: "spaces.edit", input: SpaceArgs<EditSpaceArgs>, result: Meta } | 
+        { key: "tasks.extractConcepts", input: SpaceArgs<ExtractConceptsTaskInfo>, result: null } | 
+        { key: "tasks.generateFlashcards", input: SpaceArgs<GenerateFlashcardsTaskInfo>, result: null } | 
+        { key: "tasks.generateQuiz", input: SpaceArgs<GenerateQuizTaskInfo>, result: null } | 
         { key: "tasks.learnFile", input: SpaceArgs<LearnFileTaskInfo>, result: null } | 
+        { key: "tasks.summarizeFile", input: SpaceArgs<SummarizeFileTaskInfo>, result: null } | 
+        { key: "tasks.unlearnFile", input: SpaceArgs<UnlearnFileTaskInfo>, result: null } | 
         { key: "tasks.uploadFile", input: SpaceArgs<FileUploadTaskInfo>, result: null } | 
-        { key: "users.create", input: never, result: UserWithToken },
+        { key: "users.attachAccount", input: UserArgs<SignupArgs>, result: User } | 
+        { key: "users.create", input: never, result: UserWithToken } | 
+        { key: "users.login", input: LoginArgs, result: UserWithToken } | 
+        { key: "users.logout", input: UserArgs<null>, result: null } | 
+        { key: "users.revokeSession", input: UserArgs<RevokeSessionArgs>, result: null } | 
+        { key: "users.signup", input: SignupArgs, result: UserWithToken },
     subscriptions: 
         { key: "files.updates", input: SpaceArgs<null>, result: FileWithTasks[] } | 
         { key: "invalidation.listen", input: never, result: InvalidateOperationEvent[] } | 
-        { key: "messages.updates", input: SpaceArgs<null>, result: MessageWithTasksAndPeer[] } | 
+        { key: "messages.updates", input: SpaceArgs<null>, result: MessagesUpdate } | 
         { key: "tasks.updates", input: SpaceArgs<null>, result: Task[] }
 };
 
+export type BackendHealth = { available: boolean; circuit: CircuitState; supervised: boolean; last_error: string | null }
+
+export type CircuitState = "closed" | "open" | "half_open"
+
+/**
+ * ConceptEntry is a term of the glossary with the files it was found in.
+ */
+export type ConceptEntry = { id: string; term: string; definition: string; sources: ConceptSourceEntry[]; date_modified: string }
+
+/**
+ * Limits the concepts that are listed.
+ */
+export type ConceptFilter = { query?: string | null; file_id?: string | null }
+
+export type ConceptMention = { id: number[]; id_str: string; start: number; end: number; message_id: number[]; concept_id: number[] }
+
+/**
+ * A chunk of a file that explains a concept.
+ */
+export type ConceptSourceEntry = { file_id: string; file_name: string; chunk: number; page: number | null; snippet: string; link: string }
+
+export type Conversation = { id: number[]; id_str: string; name: string; date_created: string; date_modified: string; head_id: number[] | null; summary: string | null; summary_head_id: number[] | null; space_id: number[] }
+
+export type CreateConversationArgs = { name?: string | null }
+
 export type CreateSpaceArgs = { name: string }
 
+export type DeleteConversationArgs = { id: string }
+
+export type DeleteFileArgs = { file_id: string }
+
+export type DeleteFlashcardArgs = { flashcard_id: string }
+
 export type DeleteSpaceArgs = { id: string }
 
-export type EditSpaceArgs = { name: string | null; description: string | null }
+export type AnswerLength = "short" | "medium" | "long"
+
+export type EditSpaceArgs = { name: string | null; description: string | null; retrieval_mode?: RetrievalMode | null; system_prompt?: string | null; answer_length?: AnswerLength | null; answer_language?: string | null; require_citations?: boolean | null; persona?: string | null; summarize_files?: boolean | null; extract_concepts?: boolean | null }
+
+export type ExportFormat = "markdown" | "json" | "html"
+
+/**
+ * A rendered export, ready to be saved.
+ */
+export type ExportedFile = { file_name: string; mime_type: string; content: string }
+
+export type ExtractConceptsTaskInfo = { file_id: string }
+
+/**
+ * FileSummary is the structured summary of a learned file.
+ */
+export type FileSummary = { file_id: string; overview: string; key_points: string[]; sections: SummarySection[]; stale: boolean; date_modified: string }
+
+export type FileSummaryArgs = { file_id: string }
 
 export type FileUploadTaskInfo = { path: string }
 
-export type FileWithTasks = { id: number[]; id_str: string; path: string; name: string; extension: string; learned: boolean; supported: boolean; size: number; date_created: string; date_modified: string; date_indexed: string; space_id: number[]; tasks: Task[] }
+export type FileWithTasks = { id: number[]; id_str: string; path: string; name: string; extension: string; learned: boolean; supported: boolean; stale: boolean; content_hash: string | null; size: number; date_created: string; date_modified: string; date_indexed: string; space_id: number[]; tasks: Task[] }
+
+export type FlashcardDueArgs = { limit?: number | null }
+
+/**
+ * Limits the flashcards that are listed.
+ */
+export type FlashcardFilter = { file_id?: string | null; conversation_id?: string | null }
+
+export type FlashcardGradeArgs = { flashcard_id: string; grade: number }
+
+/**
+ * FlashcardReviewState is when a user reviewed a flashcard last and when it is due again.
+ */
+export type FlashcardReviewState = { ease: number; interval: number; repetitions: number; grade: number; date_reviewed: string; date_due: string }
+
+/**
+ * Makes flashcards from either a learned file or a conversation.
+ */
+export type GenerateFlashcardsTaskInfo = { file_id?: string | null; conversation_id?: string | null }
+
+export type GenerateQuizTaskInfo = { file_ids: string[]; name?: string | null; question_count?: number | null }
+
+/**
+ * GradedAnswer is the user's answer to a question next to the answer key.
+ */
+export type GradedAnswer = { question: number; choice: number | null; text: string | null; correct: boolean; score: number; feedback: string | null; correct_choice: number | null; answer: string; explanation: string | null; sources: QuizSource[] }
+
+export type IndexKind = "Flat" | "Hnsw"
+
+export type IndexStats = { kind: IndexKind; dimensions: number | null; chunks: number; files: number }
 
 export type InvalidateOperationEvent = { key: string; arg: any; result: any | null }
 
 export type LearnFileTaskInfo = { file_id: string }
 
-export type Message = { id: number[]; id_str: string; text: string; is_user_message: boolean; response_status: number; response_message_id: number[] | null; date_created: string; date_finalized: string; space_id: number[] }
+export type LoginArgs = { email: string; password: string }
+
+export type Message = { id: number[]; id_str: string; text: string; is_user_message: boolean; response_status: number; response_message_id: number[] | null; date_created: string; date_finalized: string; space_id: number[]; conversation_id: number[] | null; parent_id: number[] | null; scope: string | null; rating: number | null; rating_comment: string | null; date_rated: string | null }
+
+export type MessageEditArgs = { message_id: string; text: string }
+
+export type MessageExportArgs = { conversation_id?: string | null; format: ExportFormat }
+
+export type MessageExportLinkArgs = { conversation_id: string; format: ExportFormat }
+
+export type MessageListArgs = { take?: number | null; cursor?: number[] | null; conversation_id?: string | null }
+
+export type MessageRateArgs = { message_id: string; rating: Rating | null; comment?: string | null }
+
+export type MessageRegenerateArgs = { message_id: string }
 
-export type MessageListArgs = { take?: number | null; cursor?: number[] | null }
+export type MessageSelectArgs = { message_id: string }
 
-export type MessageSendArgs = { text: string }
+export type MessageScope = { file_ids: string[]; folders: string[] }
 
-export type MessageWithTasksAndPeer = { id: number[]; id_str: string; text: string; is_user_message: boolean; response_status: number; response_message_id: number[] | null; date_created: string; date_finalized: string; space_id: number[]; tasks: Task[]; user_message: Message | null; response_message: Message | null }
+export type MessageSendArgs = { text: string; conversation_id?: string | null; scope?: MessageScope | null }
 
-export type MessagesWrapped = { cursor: number[] | null; messages: MessageWithTasksAndPeer[] }
+export type MessageSource = { id: number[]; id_str: string; rank: number; snippet: string; score: number | null; page: number | null; link: string | null; message_id: number[]; file_id: number[] | null }
 
-export type Meta = { id: number[]; id_str: string; name: string; description: string; color: string | null }
+export type MessageWithTasksAndPeer = { id: number[]; id_str: string; text: string; is_user_message: boolean; response_status: number; response_message_id: number[] | null; date_created: string; date_finalized: string; space_id: number[]; conversation_id: number[] | null; parent_id: number[] | null; scope: string | null; rating: number | null; rating_comment: string | null; date_rated: string | null; tasks: Task[]; user_message: Message | null; response_message: Message | null; sources: MessageSource[]; mentions: ConceptMention[] }
+
+/**
+ * What `messages.updates` delivers: either full messages that changed, or a chunk of text
+ * appended to a response message that is still being generated.
+ */
+export type MessagesUpdate = { type: "Messages"; messages: MessageWithTasksAndPeer[] } | { type: "Delta"; message_id: string; delta: string; text: string }
+
+export type MessagesWrapped = { cursor: number[] | null; messages: MessageWithTasksAndPeer[]; alternatives: { [key: string]: string[] } }
+
+export type Meta = { id: number[]; id_str: string; name: string; description: string; color: string | null; retrieval_mode: string; system_prompt: string | null; answer_length: string; answer_language: string | null; require_citations: boolean; persona: string | null; summarize_files: boolean; extract_concepts: boolean }
 
 /**
  * Can wrap a query argument to require it to contain a `space_id` and provide helpers for working with spaces.
  */
+export type QuestionKind = "multiple_choice" | "short_answer"
+
+export type QuizArgs = { quiz_id: string }
+
+/**
+ * QuizAttemptResult is a graded attempt at a quiz.
+ */
+export type QuizAttemptResult = { id: string; quiz_id: string; score: number; answers: GradedAnswer[]; date_created: string }
+
+/**
+ * QuizDetails is a quiz ready to be taken.
+ */
+export type QuizDetails = { id: string; name: string; questions: QuizQuestionPrompt[]; date_created: string }
+
+/**
+ * QuizOverview is a quiz without its questions.
+ */
+export type QuizOverview = { id: string; name: string; question_count: number; attempt_count: number; best_score: number | null; date_created: string }
+
+/**
+ * A question as it is shown while taking a quiz, without its answer.
+ */
+export type QuizQuestionPrompt = { kind: QuestionKind; question: string; choices: string[] }
+
+/**
+ * The answer given to a question, by its index in the quiz.
+ */
+export type QuizResponse = { question: number; choice?: number | null; text?: string | null }
+
+/**
+ * Where in a file a question was made from.
+ */
+export type QuizSource = { file_id: string; file_name: string; page: number | null; snippet: string }
+
+export type QuizSubmitArgs = { quiz_id: string; answers: QuizResponse[] }
+
+export type RatedAnswer = { message_id: string; question: string; answer: string; rating: Rating; comment: string | null; context: RatedContext[] }
+
+export type RatedContext = { file_id: string | null; page: number | null; snippet: string }
+
+export type Rating = "up" | "down"
+
+export type RatingStats = { answered: number; up: number; down: number }
+
+export type RenameConversationArgs = { id: string; name: string }
+
+export type RetrievalMode = "vector" | "keyword" | "hybrid"
+
+export type RevokeSessionArgs = { session_id: number }
+
+/**
+ * ScheduledFlashcard is a flashcard with the review state of the user, unset for new cards.
+ */
+export type ScheduledFlashcard = { id: string; question: string; answer: string; page: number | null; file_id: string | null; conversation_id: string | null; date_created: string; review: FlashcardReviewState | null }
+
+export type SearchHit = { kind: SearchHitKind; space_id: string; id: string; conversation_id: string | null; file_name: string | null; page: number | null; snippet: SnippetPart[]; score: number }
+
+export type SearchHitKind = "message" | "file"
+
+export type SearchQueryArgs = { query: string; space_ids?: string[] | null; limit?: number | null }
+
+/**
+ * A token of the user, one for every device it logged in from.
+ */
+export type Session = { id: number; date_created: string; date_used: string; date_expires: string | null; current: boolean }
+
+export type SignupArgs = { name: string; email: string; password: string }
+
+/**
+ * A piece of a snippet, `highlighted` parts matched the query.
+ */
+export type SnippetPart = { text: string; highlighted: boolean }
+
 export type SpaceArgs<T> = { jwt: string; space_id: string; arg: T }
 
 export type SpaceWrapped = { id: string; meta: Meta }
 
+export type SummarizeFileTaskInfo = { file_id: string }
+
+/**
+ * A part of a summarized file.
+ */
+export type SummarySection = { title: string; summary: string; page: number | null }
+
 export type Task = { id: number[]; id_str: string; hash: string; status: number; task_type: string; space_id: number[]; file_id: number[] | null; message_id: number[] | null; date_modified: string }
 
+export type UnlearnFileTaskInfo = { file_id: string; delete: boolean }
+
 export type User = { id: number[]; id_str: string; account_attached: boolean }
 
 /**
@@ -0,0 +1,20 @@
+import json
+import os
+import unittest
+
+from yerba.protocol import PROTOCOL_VERSION
+
+
+class ProtocolVersionTest(unittest.TestCase):
+    def test_matches_the_generated_schema(self):
+        schema_path = os.path.join(
+            os.path.dirname(__file__), "..", "yerba", "protocol.schema.json"
+        )
+        with open(schema_path) as f:
+            schema = json.load(f)
+
+        self.assertEqual(PROTOCOL_VERSION, schema["x-protocol-version"])
+
+
+if __name__ == "__main__":
+    unittest.main()
@@ -1,3 +1,4 @@
+import asyncio
 import os
 import glob
 import time
@@ -5,7 +6,9 @@ from typing import List, Optional
 from multiprocessing import Pool
 from tqdm import tqdm
 from fastapi import FastAPI, Body, Header
+from fastapi.responses import StreamingResponse
 from pydantic import BaseModel
+from langchain.callbacks import AsyncIteratorCallbackHandler
 from langchain.chains import RetrievalQA
 from langchain.embeddings import OpenAIEmbeddings
 from langchain.vectorstores import Chroma
@@ -25,10 +28,19 @@ from langchain.document_loaders import (
 )
 from langchain.text_splitter import RecursiveCharacterTextSplitter
 from langchain.docstore.document import Document
+from langchain.prompts import PromptTemplate
+from langchain.schema import (
+    AIMessage,
+    BaseMessage,
+    BaseRetriever,
+    HumanMessage,
+    SystemMessage,
+)
 from chromadb.config import Settings
 from langchain.chat_models import ChatOpenAI
 from yerba.chain import ConversationalRetrievalChain
 from yerba.pdf_loaders import MathpixPDFLoader, PyMuPDFLoader
+from yerba.protocol import PROTOCOL_VERSION
 
 import json
 
@@ -133,6 +145,10 @@ chunk_size = 500
 chunk_overlap = 50
 
 
+class VersionResponse(BaseModel):
+    protocol_version: int
+
+
 class LearnRequest(BaseModel):
     vector_db_path: str
     file_path: str
@@ -143,53 +159,198 @@ class LearnResponse(BaseModel):
     error: Optional[str] = None
 
 
+class ContextChunk(BaseModel):
+    text: str
+    file_id: str
+    page: Optional[int] = None
+
+
 class AskRequest(BaseModel):
     vector_db_path: str
     question: str
 
     chat_history: str
+    # chunks retrieved by the Rust node's native index. When set, the Chroma store is not used.
+    context: Optional[List[ContextChunk]] = None
+    # limits retrieval from the Chroma store to documents loaded from these paths
+    source_paths: Optional[List[str]] = None
+    # the space's answer style, replaces the default answer prompt when set
+    instructions: Optional[str] = None
+
+
+class AskSource(BaseModel):
+    file_id: Optional[str] = None
+    source: Optional[str] = None
+    # 1-based
+    page: Optional[int] = None
+    snippet: str
+    score: Optional[float] = None
 
 
 class AskResponse(BaseModel):
     success: bool
     error: Optional[str] = None
     result: Optional[str] = None
+    sources: List[AskSource] = []
+
+
+class UnlearnRequest(BaseModel):
+    vector_db_path: str
+    file_path: str
+
+
+class UnlearnResponse(BaseModel):
+    success: bool
+    error: Optional[str] = None
+    removed: Optional[int] = None
+
+
+class ExtractRequest(BaseModel):
+    file_path: str
+
+
+class ExtractedChunk(BaseModel):
+    text: str
+    page: Optional[int] = None
+
+
+class ExtractResponse(BaseModel):
+    success: bool
+    error: Optional[str] = None
+    chunks: Optional[List[ExtractedChunk]] = None
+
+
+class CompleteMessage(BaseModel):
+    # "system", "user" or "assistant"
+    role: str
+    content: str
+
+
+class CompleteRequest(BaseModel):
+    messages: List[CompleteMessage]
+
+
+class CompleteResponse(BaseModel):
+    success: bool
+    error: Optional[str] = None
+    text: Optional[str] = None
+
+
+class StaticRetriever(BaseRetriever):
+    """Retriever returning documents that were already retrieved by the caller."""
+
+    def __init__(self, documents: List[Document]):
+        self.documents = documents
+
+    def get_relevant_documents(self, query: str) -> List[Document]:
+        return self.documents
+
+    async def aget_relevant_documents(self, query: str) -> List[Document]:
+        return self.documents
 
 
 app = FastAPI()
 
 
-@app.post("/ask", response_model=AskResponse)
-async def ask(request: AskRequest):
-    try:
-        question = request.question
-        persist_directory = request.vector_db_path
+@app.get("/version", response_model=VersionResponse)
+async def version():
+    return VersionResponse(protocol_version=PROTOCOL_VERSION)
+
 
-        history = json.loads(request.chat_history or "[]")
+def build_retriever(request: AskRequest) -> BaseRetriever:
+    if request.context is not None:
+        return StaticRetriever(
+            [
+                Document(
+                    page_content=chunk.text,
+                    metadata={"file_id": chunk.file_id, "page": chunk.page},
+                )
+                for chunk in request.context
+            ]
+        )
 
-        if history == "":
-            history = []
-        chat_history = [(x["HUMAN"], x["AI"]) for x in history]
+    search_kwargs = {"k": 10}
+    if request.source_paths is not None:
+        if not request.source_paths:
+            return StaticRetriever([])
+        # chroma 0.3 has no $in operator
+        filters = [{"source": path} for path in request.source_paths]
+        search_kwargs["filter"] = filters[0] if len(filters) == 1 else {"$or": filters}
 
-        openai_embeddings = OpenAIEmbeddings()
+    openai_embeddings = OpenAIEmbeddings()
 
-        chroma_settings = Settings(
-            chroma_db_impl="duckdb+parquet",
-            persist_directory=persist_directory,
-            anonymized_telemetry=False,
+    chroma_settings = Settings(
+        chroma_db_impl="duckdb+parquet",
+        persist_directory=request.vector_db_path,
+        anonymized_telemetry=False,
+    )
+    db = Chroma(
+        persist_directory=request.vector_db_path,
+        embedding_function=openai_embeddings,
+        client_settings=chroma_settings,
+    )
+    return db.as_retriever(search_kwargs=search_kwargs)
+
+
+def build_qa_kwargs(request: AskRequest) -> dict:
+    if not request.instructions:
+        return {}
+
+    # braces in the instructions must not be read as template variables
+    instructions = request.instructions.replace("{", "{{").replace("}", "}}")
+    template = (
+        instructions
+        + "\n\nContext:\n{context}\n\nQuestion: {question}\nHelpful Answer:"
+    )
+    return {
+        "prompt": PromptTemplate(
+            template=template, input_variables=["context", "question"]
         )
-        db = Chroma(
-            persist_directory=persist_directory,
-            embedding_function=openai_embeddings,
-            client_settings=chroma_settings,
+    }
+
+
+def parse_chat_history(request: AskRequest):
+    history = json.loads(request.chat_history or "[]")
+
+    if history == "":
+        history = []
+    return [(x["HUMAN"], x["AI"]) for x in history]
+
+
+def collect_sources(request: AskRequest, result) -> List[AskSource]:
+    sources = []
+    for doc in result.get("source_documents", []):
+        page = doc.metadata.get("page")
+        # the document loaders number pages from 0, context sent by the node is already 1-based
+        if page is not None and request.context is None:
+            page += 1
+        sources.append(
+            AskSource(
+                file_id=doc.metadata.get("file_id"),
+                source=doc.metadata.get("source"),
+                page=page,
+                snippet=doc.page_content,
+            )
         )
-        retriever = db.as_retriever(search_kwargs={"k": 10})
+    return sources
 
-        openai_embeddings = OpenAIEmbeddings()
+
+@app.post("/ask", response_model=AskResponse)
+async def ask(request: AskRequest):
+    try:
+        question = request.question
+        chat_history = parse_chat_history(request)
+        retriever = build_retriever(request)
 
         llm = ChatOpenAI()
 
-        qa = ConversationalRetrievalChain.from_llm(llm, retriever, verbose=True)
+        qa = ConversationalRetrievalChain.from_llm(
+            llm,
+            retriever,
+            verbose=True,
+            return_source_documents=True,
+            combine_docs_chain_kwargs=build_qa_kwargs(request),
+        )
 
         start = time.time()
         result = qa({"question": question, "chat_history": chat_history})
@@ -200,12 +361,66 @@ async def ask(request: AskRequest):
 
         print(f"Answer: {result} generated in {end - start} seconds")
 
-        return AskResponse(success=True, result=answer)
+        return AskResponse(
+            success=True, result=answer, sources=collect_sources(request, result)
+        )
     except Exception as e:
         print(f"Error: {e}")
         return AskResponse(success=False, error=str(e))
 
 
+def sse_event(event: str, data) -> str:
+    return f"event: {event}\ndata: {json.dumps(data)}\n\n"
+
+
+@app.post("/ask/stream")
+async def ask_stream(request: AskRequest):
+    """Same as /ask, but streams the answer as server-sent events.
+
+    Emits `token` events ({"text": ...}) while the answer is generated, then a single
+    `done` event ({"result": ..., "sources": [...]}) or `error` event ({"error": ...}).
+    """
+
+    async def generate():
+        try:
+            chat_history = parse_chat_history(request)
+            retriever = build_retriever(request)
+
+            handler = AsyncIteratorCallbackHandler()
+            # only the answer is streamed, the condensed question stays internal
+            qa = ConversationalRetrievalChain.from_llm(
+                ChatOpenAI(streaming=True, callbacks=[handler]),
+                retriever,
+                verbose=True,
+                return_source_documents=True,
+                condense_question_llm=ChatOpenAI(),
+                combine_docs_chain_kwargs=build_qa_kwargs(request),
+            )
+
+            run = asyncio.create_task(
+                qa.acall({"question": request.question, "chat_history": chat_history})
+            )
+            # stop iterating if the chain fails before the answer is generated
+            run.add_done_callback(lambda _: handler.done.set())
+            async for token in handler.aiter():
+                yield sse_event("token", {"text": token})
+
+            result = await run
+            sources = collect_sources(request, result)
+            yield sse_event(
+                "done",
+                {
+                    "result": result["answer"],
+                    "sources": [source.dict() for source in sources],
+                },
+            )
+        except Exception as e:
+            print(f"Error: {e}")
+            yield sse_event("error", {"error": str(e)})
+
+    return StreamingResponse(generate(), media_type="text/event-stream")
+
+
 @app.post("/learn", response_model=LearnResponse)
 async def learn(request: LearnRequest):
     try:
@@ -255,6 +470,82 @@ async def learn(request: LearnRequest):
         return LearnResponse(success=False, error=str(e))
 
 
+@app.post("/unlearn", response_model=UnlearnResponse)
+async def unlearn(request: UnlearnRequest):
+    try:
+        persist_directory = request.vector_db_path
+        if not does_vectorstore_exist(persist_directory):
+            return UnlearnResponse(success=True, removed=0)
+
+        chroma_settings = Settings(
+            chroma_db_impl="duckdb+parquet",
+            persist_directory=persist_directory,
+            anonymized_telemetry=False,
+        )
+        db = Chroma(
+            persist_directory=persist_directory,
+            embedding_function=OpenAIEmbeddings(),
+            client_settings=chroma_settings,
+        )
+
+        # chunks are tagged with the path they were loaded from by the document loaders
+        existing = db._collection.get(where={"source": request.file_path})
+        removed = len(existing["ids"])
+        if removed > 0:
+            db._collection.delete(ids=existing["ids"])
+            db.persist()
+        db = None
+
+        return UnlearnResponse(success=True, removed=removed)
+    except Exception as e:
+        print(f"Error: {e}")
+        return UnlearnResponse(success=False, error=str(e))
+
+
+@app.post("/extract", response_model=ExtractResponse)
+async def extract(request: ExtractRequest):
+    try:
+        documents = load_single_document(request.file_path)
+        text_splitter = RecursiveCharacterTextSplitter(
+            chunk_size=chunk_size, chunk_overlap=chunk_overlap
+        )
+        texts = text_splitter.split_documents(documents)
+        chunks = [
+            ExtractedChunk(
+                text=doc.page_content,
+                # the document loaders number pages from 0
+                page=doc.metadata["page"] + 1
+                if doc.metadata.get("page") is not None
+                else None,
+            )
+            for doc in texts
+        ]
+        return ExtractResponse(success=True, chunks=chunks)
+    except Exception as e:
+        print(f"Error: {e}")
+        return ExtractResponse(success=False, error=str(e))
+
+
+def to_chat_message(message: CompleteMessage) -> BaseMessage:
+    if message.role == "system":
+        return SystemMessage(content=message.content)
+    if message.role == "assistant":
+        return AIMessage(content=message.content)
+    return HumanMessage(content=message.content)
+
+
+@app.post("/complete", response_model=CompleteResponse)
+async def complete(request: CompleteRequest):
+    try:
+        llm = ChatOpenAI()
+        messages = [to_chat_message(message) for message in request.messages]
+        result = await llm.agenerate([messages])
+        return CompleteResponse(success=True, text=result.generations[0][0].text)
+    except Exception as e:
+        print(f"Error: {e}")
+        return CompleteResponse(success=False, error=str(e))
+
+
 if __name__ == "__main__":
     import uvicorn
 
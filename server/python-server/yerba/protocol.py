@@ -0,0 +1,4 @@
+# Version of the protocol spoken with the Rust node, it must match PROTOCOL_VERSION in
+# server/src/sidecar/protocol.rs. tests/test_protocol.py checks it against protocol.schema.json,
+# which is generated from the node's types.
+PROTOCOL_VERSION = 4
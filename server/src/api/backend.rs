@@ -0,0 +1,10 @@
+use rspc::alpha::AlphaRouter;
+
+use super::{utils::user, Ctx, R};
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+    R.router().procedure("health", {
+        R.with2(user())
+            .query(|(ctx, _user), _: ()| async move { Ok::<_, rspc::Error>(ctx.sidecar_health()) })
+    })
+}
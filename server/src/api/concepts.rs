@@ -0,0 +1,15 @@
+use rspc::alpha::AlphaRouter;
+
+use crate::space::glossary::ConceptFilter;
+
+use super::{utils::space, Ctx, R};
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+    R.router().procedure("list", {
+        R.with2(space())
+            .query(|(_ctx, space), args: ConceptFilter| async move {
+                let concepts = space.concepts(args).await?;
+                Ok(concepts)
+            })
+    })
+}
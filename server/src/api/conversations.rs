@@ -0,0 +1,111 @@
+use custom_prisma::prisma::{conversation, SortOrder};
+use rspc::alpha::AlphaRouter;
+use serde::Deserialize;
+use specta::Type;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{invalidate_query, utils::u2b};
+
+use super::{utils::space, Ctx, R};
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+    R.router()
+        .procedure("list", {
+            R.with2(space()).query(|(_ctx, space), _: ()| async move {
+                let conversations = space
+                    .db
+                    .conversation()
+                    .find_many(vec![conversation::space_id::equals(u2b(space.id))])
+                    .order_by(conversation::date_modified::order(SortOrder::Desc))
+                    .exec()
+                    .await?;
+
+                Ok(conversations)
+            })
+        })
+        .procedure("create", {
+            #[derive(Deserialize, Type)]
+            pub struct CreateConversationArgs {
+                #[specta(optional)]
+                name: Option<String>,
+            }
+            R.with2(space())
+                .mutation(|(_ctx, space), args: CreateConversationArgs| async move {
+                    let name = args.name.filter(|name| !name.trim().is_empty());
+                    let conversation = space.create_conversation(name).await?;
+                    Ok(conversation)
+                })
+        })
+        .procedure("rename", {
+            #[derive(Deserialize, Type)]
+            pub struct RenameConversationArgs {
+                id: Uuid,
+                name: String,
+            }
+            R.with2(space())
+                .mutation(|(_ctx, space), args: RenameConversationArgs| async move {
+                    let name = args.name.trim();
+                    if name.is_empty() {
+                        return Err(rspc::Error::new(
+                            rspc::ErrorCode::BadRequest,
+                            "The name of a conversation can't be empty".to_string(),
+                        ));
+                    }
+
+                    let conversation = space.conversation(args.id).await?;
+                    let conversation = space
+                        .db
+                        .conversation()
+                        .update(
+                            conversation::id::equals(conversation.id),
+                            vec![conversation::name::set(name.to_string())],
+                        )
+                        .exec()
+                        .await?;
+
+                    invalidate_query!(space, "conversations.list");
+
+                    Ok(conversation)
+                })
+        })
+        .procedure("delete", {
+            #[derive(Deserialize, Type)]
+            pub struct DeleteConversationArgs {
+                id: Uuid,
+            }
+            R.with2(space())
+                .mutation(|(_ctx, space), args: DeleteConversationArgs| async move {
+                    let conversation = space.conversation(args.id).await?;
+                    // its messages are deleted with it
+                    space
+                        .db
+                        .conversation()
+                        .delete(conversation::id::equals(conversation.id))
+                        .exec()
+                        .await?;
+                    debug!("Deleted conversation {}", args.id);
+                    // there is always a conversation to pick
+                    let left = space
+                        .db
+                        .conversation()
+                        .count(vec![conversation::space_id::equals(u2b(space.id))])
+                        .exec()
+                        .await?;
+                    if left == 0 {
+                        space.create_conversation(None).await?;
+                    }
+                    if let Err(e) = space.search().remove_conversation(args.id).await {
+                        warn!(
+                            "Failed to remove conversation from the search index: {:?}",
+                            e
+                        );
+                    }
+
+                    invalidate_query!(space, "conversations.list");
+                    invalidate_query!(space, "messages.list");
+
+                    Ok(())
+                })
+        })
+}
@@ -1,11 +1,15 @@
-use custom_prisma::prisma::{file, task};
+use custom_prisma::prisma::{file, file_summary, task};
 use rspc::alpha::AlphaRouter;
 use serde::{Deserialize, Serialize};
 use specta::Type;
 
 use uuid::Uuid;
 
-use crate::{api::CoreEvent, utils::u2b};
+use crate::{
+    api::CoreEvent,
+    tasks::{summarize_file::FileSummary, unlearn_file::UnlearnFileTaskInfo, IntoTask},
+    utils::u2b,
+};
 
 use super::{file_with_tasks, utils::space, Ctx, R};
 
@@ -33,6 +37,70 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
                 Ok(files)
             })
         })
+        .procedure("refresh", {
+            R.with2(space())
+                .mutation(|(_ctx, space), _: ()| async move {
+                    let stale = space.refresh_stale_files().await?;
+                    Ok(stale)
+                })
+        })
+        .procedure("delete", {
+            #[derive(Deserialize, Type)]
+            pub struct DeleteFileArgs {
+                file_id: Uuid,
+            }
+            R.with2(space())
+                .mutation(|(_ctx, space), args: DeleteFileArgs| async move {
+                    // the file is removed from disk and db once its chunks are gone from the index
+                    let task = UnlearnFileTaskInfo {
+                        file_id: args.file_id,
+                        delete: true,
+                    };
+                    space
+                        .clone()
+                        .dispatcher
+                        .dispatch(&space, task.runnable())
+                        .await?;
+                    Ok(())
+                })
+        })
+        .procedure("summary", {
+            #[derive(Deserialize, Type)]
+            pub struct FileSummaryArgs {
+                file_id: Uuid,
+            }
+            R.with2(space())
+                .query(|(_ctx, space), args: FileSummaryArgs| async move {
+                    let Some(file) = space
+                        .db
+                        .file()
+                        .find_first(vec![
+                            file::id::equals(u2b(args.file_id)),
+                            file::space_id::equals(u2b(space.id)),
+                        ])
+                        .exec()
+                        .await?
+                    else {
+                        return Err(rspc::Error::new(
+                            rspc::ErrorCode::NotFound,
+                            "File not found".into(),
+                        ));
+                    };
+
+                    let summary = space
+                        .db
+                        .file_summary()
+                        .find_unique(file_summary::file_id::equals(file.id.clone()))
+                        .exec()
+                        .await?;
+
+                    let summary = match summary {
+                        Some(summary) => Some(FileSummary::from_data(&file, summary)?),
+                        None => None,
+                    };
+                    Ok(summary)
+                })
+        })
         .procedure("updates", {
             R.with2(space()).subscription(|(ctx, _), _: ()| async move {
                 let mut event_bus_rx = ctx.event_bus.0.subscribe();
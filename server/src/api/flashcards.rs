@@ -0,0 +1,79 @@
+use rspc::alpha::AlphaRouter;
+use serde::Deserialize;
+use specta::Type;
+use uuid::Uuid;
+
+use crate::{
+    invalidate_query,
+    space::flashcards::{FlashcardFilter, MAX_GRADE},
+};
+
+use super::{utils::space, Ctx, R};
+
+const DEFAULT_DUE_FLASHCARDS: usize = 20;
+
+// the space middleware only lets the owner of a space through, so reviews are kept for the owner
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+    R.router()
+        .procedure("list", {
+            R.with2(space())
+                .query(|(_ctx, space), args: FlashcardFilter| async move {
+                    let cards = space.flashcards(space.owner_id, args).await?;
+                    Ok(cards)
+                })
+        })
+        .procedure("due", {
+            #[derive(Deserialize, Type)]
+            pub struct FlashcardDueArgs {
+                #[specta(optional)]
+                limit: Option<u32>,
+            }
+            R.with2(space())
+                .query(|(_ctx, space), args: FlashcardDueArgs| async move {
+                    let limit = args
+                        .limit
+                        .map_or(DEFAULT_DUE_FLASHCARDS, |limit| limit as usize);
+                    let cards = space.due_flashcards(space.owner_id, limit).await?;
+                    Ok(cards)
+                })
+        })
+        .procedure("grade", {
+            #[derive(Deserialize, Type)]
+            pub struct FlashcardGradeArgs {
+                flashcard_id: Uuid,
+                /// from 0 = forgotten to 5 = perfect recall
+                grade: u8,
+            }
+            R.with2(space())
+                .mutation(|(_ctx, space), args: FlashcardGradeArgs| async move {
+                    if args.grade > MAX_GRADE {
+                        return Err(rspc::Error::new(
+                            rspc::ErrorCode::BadRequest,
+                            format!("Grades go from 0 to {}", MAX_GRADE),
+                        ));
+                    }
+                    let card = space
+                        .grade_flashcard(space.owner_id, args.flashcard_id, args.grade)
+                        .await?;
+
+                    invalidate_query!(space, "flashcards.due");
+
+                    Ok(card)
+                })
+        })
+        .procedure("delete", {
+            #[derive(Deserialize, Type)]
+            pub struct DeleteFlashcardArgs {
+                flashcard_id: Uuid,
+            }
+            R.with2(space())
+                .mutation(|(_ctx, space), args: DeleteFlashcardArgs| async move {
+                    space.delete_flashcard(args.flashcard_id).await?;
+
+                    invalidate_query!(space, "flashcards.list");
+                    invalidate_query!(space, "flashcards.due");
+
+                    Ok(())
+                })
+        })
+}
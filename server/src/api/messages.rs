@@ -17,12 +17,16 @@ use prisma_client_rust::schema::constants::ordering::SORT_ORDER;
 use rspc::alpha::AlphaRouter;
 use serde::{Deserialize, Serialize};
 use specta::Type;
+use std::collections::HashMap;
 
 use tracing::debug;
 use uuid::Uuid;
 
+use crate::custom_uri::export_link;
 use crate::invalidate_query;
-use crate::tasks::reply::ReplyTaskInfo;
+use crate::space::export::ExportFormat;
+use crate::space::ratings::{rated_answer, rating_comment, RatedAnswer, Rating, RatingStats};
+use crate::tasks::reply::{MessageScope, ReplyTaskInfo};
 use crate::utils::u2s;
 
 use super::{message_with_tasks_and_peer, utils::space, Ctx, R};
@@ -31,6 +35,23 @@ use super::{message_with_tasks_and_peer, utils::space, Ctx, R};
 struct MessagesWrapped {
     cursor: Option<Vec<u8>>,
     messages: Vec<message_with_tasks_and_peer::Data>,
+    /// ids of the alternatives of a message, including itself, for listed messages that have any
+    alternatives: HashMap<String, Vec<String>>,
+}
+
+/// What `messages.updates` delivers: either full messages that changed, or a chunk of text
+/// appended to a response message that is still being generated.
+#[derive(Serialize, Type, Debug, Clone)]
+#[serde(tag = "type")]
+enum MessagesUpdate {
+    Messages {
+        messages: Vec<message_with_tasks_and_peer::Data>,
+    },
+    Delta {
+        message_id: String,
+        delta: String,
+        text: String,
+    },
 }
 
 pub(crate) fn mount() -> AlphaRouter<Ctx> {
@@ -39,12 +60,20 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
             #[derive(Deserialize, Type)]
             pub struct MessageSendArgs {
                 text: String,
+                /// defaults to the most recently used conversation
+                #[specta(optional)]
+                conversation_id: Option<Uuid>,
+                /// answer only from these files or folders
+                #[specta(optional)]
+                scope: Option<MessageScope>,
             }
             R.with2(space())
                 .mutation(|(ctx, space), args: MessageSendArgs| async move {
                     debug!("Received message send request");
                     // find latest message in db
-                    let send_res = space.receieve_msg_from_user(args.text.clone()).await?;
+                    let send_res = space
+                        .receieve_msg_from_user(args.text.clone(), args.conversation_id, args.scope)
+                        .await?;
                     Ok(send_res)
                 })
         })
@@ -55,49 +84,240 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
                 take: Option<i32>,
                 #[specta(optional)]
                 cursor: Option<Vec<u8>>,
+                /// defaults to the most recently used conversation
+                #[specta(optional)]
+                conversation_id: Option<Uuid>,
             }
             R.with2(space())
                 .query(|(_ctx, space), args: MessageListArgs| async move {
                     let take = args.take.unwrap_or(100);
+                    let conversation = space.resolve_conversation(args.conversation_id).await?;
+
+                    // only the selected branch is listed, newest first
+                    let thread = space.thread(&conversation).await?;
+                    let mut messages = thread.messages;
+                    messages.reverse();
+
+                    let start = match args.cursor {
+                        Some(cursor) => messages
+                            .iter()
+                            .position(|message| message.id == cursor)
+                            .unwrap_or(messages.len()),
+                        None => 0,
+                    };
+                    let mut messages = messages.split_off(start);
+                    let cursor = messages
+                        .get(take as usize)
+                        .map(|message| message.id.clone());
+                    messages.truncate(take as usize);
+
+                    let alternatives = thread
+                        .alternatives
+                        .into_iter()
+                        .filter(|(id, _)| messages.iter().any(|message| &message.id_str == id))
+                        .collect();
+
+                    Ok(MessagesWrapped {
+                        cursor,
+                        messages,
+                        alternatives,
+                    })
+                })
+        })
+        .procedure("rate", {
+            #[derive(Deserialize, Type)]
+            pub struct MessageRateArgs {
+                message_id: Uuid,
+                /// unset to remove the rating
+                rating: Option<Rating>,
+                #[specta(optional)]
+                comment: Option<String>,
+            }
+            R.with2(space())
+                .mutation(|(_ctx, space), args: MessageRateArgs| async move {
+                    let answer = space
+                        .db
+                        .message()
+                        .find_first(vec![
+                            message::id::equals(u2b(args.message_id)),
+                            message::space_id::equals(u2b(space.id)),
+                            message::is_user_message::equals(false),
+                            message::response_status::equals(2),
+                        ])
+                        .exec()
+                        .await?
+                        .ok_or_else(|| {
+                            rspc::Error::new(
+                                rspc::ErrorCode::NotFound,
+                                "Only finished answers can be rated".to_string(),
+                            )
+                        })?;
 
-                    let mut query = space
+                    let comment = rating_comment(args.comment).map_err(|e| {
+                        rspc::Error::new(rspc::ErrorCode::BadRequest, e.to_string())
+                    })?;
+                    let rated = args.rating.is_some();
+                    let answer = space
                         .db
                         .message()
-                        .find_many(vec![message::space_id::equals(u2b(space.id))])
-                        .order_by(message::date_created::order(
-                            custom_prisma::prisma::SortOrder::Desc,
-                        ));
-                    if let Some(cursor) = args.cursor {
-                        query = query.cursor(message::id::equals(cursor));
-                    }
-                    let messages = query
-                        .take(take as i64 + 1)
+                        .update(
+                            message::id::equals(answer.id),
+                            vec![
+                                message::rating::set(args.rating.map(Rating::score)),
+                                message::rating_comment::set(comment.filter(|_| rated)),
+                                message::date_rated::set(rated.then(|| chrono::Utc::now().into())),
+                            ],
+                        )
                         .include(message_with_tasks_and_peer::include())
                         .exec()
                         .await?;
 
-                    // .cursor(args.cursor)
-                    // .take(args.take)
-                    // .exec()
-                    // .await?;
+                    invalidate_query!(space, "messages.ratings");
 
-                    Ok(MessagesWrapped {
-                        cursor: messages.last().map(|x| x.id.clone()),
-                        messages: messages.into_iter().take(take as usize).collect(),
-                    })
+                    Ok(answer)
+                })
+        })
+        .procedure("ratings", {
+            R.with2(space()).query(|(_ctx, space), _: ()| async move {
+                let answers = || {
+                    vec![
+                        message::space_id::equals(u2b(space.id)),
+                        message::is_user_message::equals(false),
+                        message::response_status::equals(2),
+                    ]
+                };
+                let rated = |rating: Rating| {
+                    let mut filter = answers();
+                    filter.push(message::rating::equals(Some(rating.score())));
+                    filter
+                };
+
+                let answered = space.db.message().count(answers()).exec().await?;
+                let up = space.db.message().count(rated(Rating::Up)).exec().await?;
+                let down = space.db.message().count(rated(Rating::Down)).exec().await?;
+
+                Ok(RatingStats { answered, up, down })
+            })
+        })
+        .procedure("export", {
+            #[derive(Deserialize, Type)]
+            pub struct MessageExportArgs {
+                /// defaults to the most recently used conversation
+                #[specta(optional)]
+                conversation_id: Option<Uuid>,
+                format: ExportFormat,
+            }
+            R.with2(space())
+                .query(|(_ctx, space), args: MessageExportArgs| async move {
+                    let export = space.export_conversation(args.conversation_id).await?;
+                    Ok(export.render(args.format)?)
+                })
+        })
+        .procedure("exportLink", {
+            #[derive(Deserialize, Type)]
+            pub struct MessageExportLinkArgs {
+                conversation_id: Uuid,
+                format: ExportFormat,
+            }
+            R.with2(space())
+                .mutation(|(ctx, space), args: MessageExportLinkArgs| async move {
+                    space.conversation(args.conversation_id).await?;
+                    let token = ctx
+                        .user_manager
+                        .export_token(space.owner_id, args.conversation_id)?;
+                    Ok(export_link(
+                        space.id,
+                        args.conversation_id,
+                        args.format,
+                        &token,
+                    ))
+                })
+        })
+        .procedure("exportRatings", {
+            R.with2(space()).query(|(_ctx, space), _: ()| async move {
+                let answers = space
+                    .db
+                    .message()
+                    .find_many(vec![
+                        message::space_id::equals(u2b(space.id)),
+                        message::rating::not(None),
+                    ])
+                    .order_by(message::date_rated::order(
+                        custom_prisma::prisma::SortOrder::Asc,
+                    ))
+                    .include(rated_answer::include())
+                    .exec()
+                    .await?;
+
+                let rated = answers
+                    .into_iter()
+                    .filter_map(RatedAnswer::from_answer)
+                    .collect::<Vec<_>>();
+
+                Ok(rated)
+            })
+        })
+        .procedure("regenerate", {
+            #[derive(Deserialize, Type)]
+            pub struct MessageRegenerateArgs {
+                message_id: Uuid,
+            }
+            R.with2(space())
+                .mutation(|(_ctx, space), args: MessageRegenerateArgs| async move {
+                    space.regenerate_reply(args.message_id).await?;
+                    Ok(())
+                })
+        })
+        .procedure("edit", {
+            #[derive(Deserialize, Type)]
+            pub struct MessageEditArgs {
+                message_id: Uuid,
+                text: String,
+            }
+            R.with2(space())
+                .mutation(|(_ctx, space), args: MessageEditArgs| async move {
+                    let message = space.edit_user_message(args.message_id, args.text).await?;
+                    Ok(message)
+                })
+        })
+        .procedure("select", {
+            #[derive(Deserialize, Type)]
+            pub struct MessageSelectArgs {
+                message_id: Uuid,
+            }
+            R.with2(space())
+                .mutation(|(_ctx, space), args: MessageSelectArgs| async move {
+                    let conversation = space.select_message(args.message_id).await?;
+                    Ok(conversation)
                 })
         })
         .procedure("updates", {
-            R.with2(space()).subscription(|(ctx, _), _: ()| async move {
-                let mut event_bus_rx = ctx.event_bus.0.subscribe();
-                async_stream::stream! {
-                    while let Ok(event) = event_bus_rx.recv().await {
-                        match event {
-                            CoreEvent::MessageUpdate { messages } => yield messages,
-                            _ => {}
+            R.with2(space())
+                .subscription(|(ctx, space), _: ()| async move {
+                    let mut event_bus_rx = ctx.event_bus.0.subscribe();
+                    let space_id = u2b(space.id);
+                    // the event bus is shared by all spaces, only updates of the subscribed one are sent
+                    async_stream::stream! {
+                        while let Ok(event) = event_bus_rx.recv().await {
+                            match event {
+                                CoreEvent::MessageUpdate { mut messages } => {
+                                    messages.retain(|message| message.space_id == space_id);
+                                    if !messages.is_empty() {
+                                        yield MessagesUpdate::Messages { messages }
+                                    }
+                                }
+                                CoreEvent::MessageDelta {
+                                    space_id: delta_space_id,
+                                    message_id,
+                                    delta,
+                                    text,
+                                } if delta_space_id == space.id => {
+                                    yield MessagesUpdate::Delta { message_id, delta, text }
+                                }
+                                _ => {}
+                            }
                         }
                     }
-                }
-            })
+                })
         })
 }
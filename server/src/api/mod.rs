@@ -3,6 +3,7 @@ use rspc::{alpha::Rspc, Config};
 use serde::Serialize;
 use specta::Type;
 use std::sync::Arc;
+use uuid::Uuid;
 
 use crate::Node;
 use utils::{InvalidRequests, InvalidateOperationEvent};
@@ -15,7 +16,7 @@ pub type Router = rspc::Router<Ctx>;
 
 file::include!(file_with_tasks { tasks });
 task::include!(task_with_file { file });
-message::include!(message_with_tasks_and_peer { tasks user_message response_message });
+message::include!(message_with_tasks_and_peer { tasks user_message response_message sources mentions });
 
 #[derive(Debug, Clone, Serialize, Type)]
 pub enum CoreEvent {
@@ -28,11 +29,24 @@ pub enum CoreEvent {
     MessageUpdate {
         messages: Vec<message_with_tasks_and_peer::Data>,
     },
+    /// Partial text of a response message that is still being generated.
+    MessageDelta {
+        space_id: Uuid,
+        message_id: String,
+        delta: String,
+        text: String,
+    },
     InvalidateOperation(InvalidateOperationEvent),
 }
 
+mod backend;
+mod concepts;
+mod conversations;
 mod files;
+mod flashcards;
 mod messages;
+mod quizzes;
+mod search;
 mod spaces;
 mod tasks;
 mod users;
@@ -46,7 +60,13 @@ pub(crate) fn mount() -> Arc<Router> {
         .merge("spaces.", spaces::mount())
         .merge("tasks.", tasks::mount())
         .merge("files.", files::mount())
+        .merge("conversations.", conversations::mount())
         .merge("messages.", messages::mount())
+        .merge("flashcards.", flashcards::mount())
+        .merge("quizzes.", quizzes::mount())
+        .merge("concepts.", concepts::mount())
+        .merge("search.", search::mount())
+        .merge("backend.", backend::mount())
         .merge("invalidation.", utils::mount_invalidate())
         .build(
             #[allow(clippy::let_and_return)]
@@ -0,0 +1,66 @@
+use rspc::alpha::AlphaRouter;
+use serde::Deserialize;
+use specta::Type;
+use uuid::Uuid;
+
+use crate::{invalidate_query, space::quiz::QuizResponse};
+
+use super::{utils::space, Ctx, R};
+
+#[derive(Deserialize, Type)]
+pub struct QuizArgs {
+    quiz_id: Uuid,
+}
+
+// the space middleware only lets the owner of a space through, so attempts are kept for the owner
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+    R.router()
+        .procedure("list", {
+            R.with2(space()).query(|(_ctx, space), _: ()| async move {
+                let quizzes = space.quizzes(space.owner_id).await?;
+                Ok(quizzes)
+            })
+        })
+        .procedure("get", {
+            R.with2(space())
+                .query(|(_ctx, space), args: QuizArgs| async move {
+                    let quiz = space.quiz_details(args.quiz_id).await?;
+                    Ok(quiz)
+                })
+        })
+        .procedure("attempts", {
+            R.with2(space())
+                .query(|(_ctx, space), args: QuizArgs| async move {
+                    let attempts = space.quiz_attempts(space.owner_id, args.quiz_id).await?;
+                    Ok(attempts)
+                })
+        })
+        .procedure("submit", {
+            #[derive(Deserialize, Type)]
+            pub struct QuizSubmitArgs {
+                quiz_id: Uuid,
+                answers: Vec<QuizResponse>,
+            }
+            R.with2(space())
+                .mutation(|(_ctx, space), args: QuizSubmitArgs| async move {
+                    let attempt = space
+                        .submit_quiz(space.owner_id, args.quiz_id, args.answers)
+                        .await?;
+
+                    invalidate_query!(space, "quizzes.list");
+                    invalidate_query!(space, "quizzes.attempts");
+
+                    Ok(attempt)
+                })
+        })
+        .procedure("delete", {
+            R.with2(space())
+                .mutation(|(_ctx, space), args: QuizArgs| async move {
+                    space.delete_quiz(args.quiz_id).await?;
+
+                    invalidate_query!(space, "quizzes.list");
+
+                    Ok(())
+                })
+        })
+}
@@ -0,0 +1,45 @@
+use rspc::alpha::AlphaRouter;
+use serde::Deserialize;
+use specta::Type;
+use uuid::Uuid;
+
+use crate::search::MAX_SEARCH_HITS;
+
+use super::{utils::user, Ctx, R};
+
+const DEFAULT_SEARCH_HITS: usize = 20;
+
+pub(crate) fn mount() -> AlphaRouter<Ctx> {
+    R.router().procedure("query", {
+        #[derive(Deserialize, Type)]
+        pub struct SearchQueryArgs {
+            query: String,
+            /// search only these spaces, defaults to all spaces of the user
+            #[specta(optional)]
+            space_ids: Option<Vec<Uuid>>,
+            #[specta(optional)]
+            limit: Option<u32>,
+        }
+        R.with2(user())
+            .query(|(ctx, user), args: SearchQueryArgs| async move {
+                let mut space_ids = ctx
+                    .space_manager
+                    .get_all_spaces_for_user(user)
+                    .await
+                    .into_iter()
+                    .map(|space| space.id)
+                    .collect::<Vec<_>>();
+                if let Some(selected) = args.space_ids {
+                    space_ids.retain(|id| selected.contains(id));
+                }
+
+                let limit = args
+                    .limit
+                    .map_or(DEFAULT_SEARCH_HITS, |limit| limit as usize)
+                    .min(MAX_SEARCH_HITS);
+                let hits = ctx.search.query(&space_ids, &args.query, limit).await?;
+
+                Ok(hits)
+            })
+    })
+}
@@ -1,6 +1,8 @@
 use crate::{
     api::utils::{space, user},
+    index::{IndexBackend, RetrievalMode},
     space::SpaceWrapped,
+    tasks::reply::AnswerLength,
 };
 
 use custom_prisma::prisma::meta::{self, SetParam};
@@ -11,6 +13,14 @@ use tracing::debug;
 
 use super::{Ctx, R};
 
+const MAX_SYSTEM_PROMPT_LENGTH: usize = 4000;
+
+/// non_empty trims a setting, an empty value unsets it.
+fn non_empty(value: String) -> Option<String> {
+    let value = value.trim();
+    (!value.is_empty()).then(|| value.to_string())
+}
+
 pub(crate) fn mount() -> AlphaRouter<Ctx> {
     R.router()
         .procedure("list", {
@@ -57,6 +67,25 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
             pub struct EditSpaceArgs {
                 pub name: Option<String>,
                 pub description: Option<String>,
+                #[specta(optional)]
+                pub retrieval_mode: Option<RetrievalMode>,
+                /// an empty string resets the text settings below to their default
+                #[specta(optional)]
+                pub system_prompt: Option<String>,
+                #[specta(optional)]
+                pub answer_length: Option<AnswerLength>,
+                #[specta(optional)]
+                pub answer_language: Option<String>,
+                #[specta(optional)]
+                pub require_citations: Option<bool>,
+                #[specta(optional)]
+                pub persona: Option<String>,
+                /// summarize files automatically once they are learned
+                #[specta(optional)]
+                pub summarize_files: Option<bool>,
+                /// add the terms of files to the glossary once they are learned
+                #[specta(optional)]
+                pub extract_concepts: Option<bool>,
             }
 
             R.with2(space())
@@ -75,6 +104,50 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
                             updates.push(meta::description::set(description));
                         }
                     }
+                    if let Some(retrieval_mode) = args.retrieval_mode {
+                        let retrieval_mode = retrieval_mode.to_string();
+                        // the Python server always searches by vector
+                        if retrieval_mode != space.meta.retrieval_mode
+                            && IndexBackend::from_env() != IndexBackend::Native
+                        {
+                            return Err(rspc::Error::new(
+                                rspc::ErrorCode::BadRequest,
+                                "Retrieval modes can only be changed with the native index"
+                                    .to_string(),
+                            ));
+                        }
+                        updates.push(meta::retrieval_mode::set(retrieval_mode));
+                    }
+                    if let Some(system_prompt) = args.system_prompt {
+                        if system_prompt.len() > MAX_SYSTEM_PROMPT_LENGTH {
+                            return Err(rspc::Error::new(
+                                rspc::ErrorCode::BadRequest,
+                                format!(
+                                    "The system prompt can be at most {} characters long",
+                                    MAX_SYSTEM_PROMPT_LENGTH
+                                ),
+                            ));
+                        }
+                        updates.push(meta::system_prompt::set(non_empty(system_prompt)));
+                    }
+                    if let Some(answer_length) = args.answer_length {
+                        updates.push(meta::answer_length::set(answer_length.to_string()));
+                    }
+                    if let Some(answer_language) = args.answer_language {
+                        updates.push(meta::answer_language::set(non_empty(answer_language)));
+                    }
+                    if let Some(require_citations) = args.require_citations {
+                        updates.push(meta::require_citations::set(require_citations));
+                    }
+                    if let Some(persona) = args.persona {
+                        updates.push(meta::persona::set(non_empty(persona)));
+                    }
+                    if let Some(summarize_files) = args.summarize_files {
+                        updates.push(meta::summarize_files::set(summarize_files));
+                    }
+                    if let Some(extract_concepts) = args.extract_concepts {
+                        updates.push(meta::extract_concepts::set(extract_concepts));
+                    }
 
                     let updated_space = space
                         .db
@@ -88,6 +161,13 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
                     Ok(updated_space)
                 })
         })
+        .procedure("indexStats", {
+            R.with2(space()).query(|(_ctx, space), _: ()| async move {
+                let index = space.index().await?;
+                let stats = index.read().await.stats();
+                Ok(stats)
+            })
+        })
         .procedure("delete", {
             #[derive(Deserialize, Type)]
             pub struct DeleteSpaceArgs {
@@ -1,7 +1,12 @@
 use crate::{
     api::CoreEvent,
     tasks::{
+        extract_concepts::ExtractConceptsTaskInfo,
+        generate_flashcards::GenerateFlashcardsTaskInfo,
+        generate_quiz::GenerateQuizTaskInfo,
         learn_file::{LearnFileTask, LearnFileTaskInfo},
+        summarize_file::SummarizeFileTaskInfo,
+        unlearn_file::UnlearnFileTaskInfo,
         upload_file::FileUploadTaskInfo,
         IntoTask,
     },
@@ -54,6 +59,66 @@ pub(crate) fn mount() -> AlphaRouter<Ctx> {
                     Ok(())
                 })
         })
+        .procedure("extractConcepts", {
+            R.with2(space())
+                .mutation(|(_, space), args: ExtractConceptsTaskInfo| async move {
+                    debug!("Beginning concept extraction");
+                    space
+                        .clone()
+                        .dispatcher
+                        .dispatch(&space, args.clone().runnable())
+                        .await?;
+                    Ok(())
+                })
+        })
+        .procedure("generateFlashcards", {
+            R.with2(space())
+                .mutation(|(_, space), args: GenerateFlashcardsTaskInfo| async move {
+                    debug!("Beginning flashcards");
+                    space
+                        .clone()
+                        .dispatcher
+                        .dispatch(&space, args.clone().runnable())
+                        .await?;
+                    Ok(())
+                })
+        })
+        .procedure("generateQuiz", {
+            R.with2(space())
+                .mutation(|(_, space), args: GenerateQuizTaskInfo| async move {
+                    debug!("Beginning quiz");
+                    space
+                        .clone()
+                        .dispatcher
+                        .dispatch(&space, args.clone().runnable())
+                        .await?;
+                    Ok(())
+                })
+        })
+        .procedure("summarizeFile", {
+            R.with2(space())
+                .mutation(|(_, space), args: SummarizeFileTaskInfo| async move {
+                    debug!("Beginning summary");
+                    space
+                        .clone()
+                        .dispatcher
+                        .dispatch(&space, args.clone().runnable())
+                        .await?;
+                    Ok(())
+                })
+        })
+        .procedure("unlearnFile", {
+            R.with2(space())
+                .mutation(|(_, space), args: UnlearnFileTaskInfo| async move {
+                    debug!("Beginning unlearning");
+                    space
+                        .clone()
+                        .dispatcher
+                        .dispatch(&space, args.clone().runnable())
+                        .await?;
+                    Ok(())
+                })
+        })
         .procedure("updates", {
             R.with2(space()).subscription(|(ctx, _), _: ()| async move {
                 let mut event_bus_rx = ctx.event_bus.0.subscribe();
@@ -1,28 +1,101 @@
-use anyhow::{Context, Result};
 use rspc::alpha::AlphaRouter;
+use serde::Deserialize;
+use specta::Type;
 use tracing::debug;
 
-use super::{Ctx, R};
-use uuid::Uuid;
+use crate::user::AccountError;
+
+use super::{
+    utils::{session, user},
+    Ctx, R,
+};
+
+#[derive(Deserialize, Type)]
+pub struct SignupArgs {
+    name: String,
+    email: String,
+    password: String,
+}
+
+#[derive(Deserialize, Type)]
+pub struct LoginArgs {
+    email: String,
+    password: String,
+}
+
+#[derive(Deserialize, Type)]
+pub struct RevokeSessionArgs {
+    session_id: i32,
+}
+
+/// account_error keeps the code of account errors, which are otherwise reported as internal.
+fn account_error(err: anyhow::Error) -> rspc::Error {
+    match err.downcast::<AccountError>() {
+        Ok(err) => err.into(),
+        Err(err) => err.into(),
+    }
+}
 
 pub(crate) fn mount() -> AlphaRouter<Ctx> {
-    R.router().procedure("create", {
-        R.mutation(|ctx, _: ()| async move {
-            debug!("Creating user");
-
-            let user_with_token = ctx.user_manager.create_detached().await?;
-            // let uuid = Uuid::parse_str(&user_with_token.user.id_str);
-            // if uuid.is_err() {
-            //     return Err(anyhow::anyhow!("Invalid UUID"));
-            // }
-
-            // let user = ctx
-            //     .user_manager
-            //     .get_user((user_with_token.user.id_str))
-            //     .await?;
-            Ok(user_with_token)
+    R.router()
+        .procedure("create", {
+            R.mutation(|ctx, _: ()| async move {
+                debug!("Creating user");
+
+                let user_with_token = ctx.user_manager.create_detached().await?;
+                Ok(user_with_token)
+            })
+        })
+        .procedure("signup", {
+            R.mutation(|ctx, args: SignupArgs| async move {
+                debug!("Signing up user");
+
+                ctx.user_manager
+                    .signup(args.name, args.email, args.password)
+                    .await
+                    .map_err(account_error)
+            })
+        })
+        .procedure("login", {
+            R.mutation(|ctx, args: LoginArgs| async move {
+                ctx.user_manager
+                    .login(args.email, args.password)
+                    .await
+                    .map_err(account_error)
+            })
+        })
+        .procedure("attachAccount", {
+            R.with2(user())
+                .mutation(|(ctx, user), args: SignupArgs| async move {
+                    ctx.user_manager
+                        .attach_account(user.id, args.name, args.email, args.password)
+                        .await
+                        .map_err(account_error)
+                })
+        })
+        .procedure("logout", {
+            R.with2(session())
+                .mutation(|(ctx, user, token), _: ()| async move {
+                    ctx.user_manager.logout(user.id, token).await?;
+                    Ok(())
+                })
+        })
+        .procedure("sessions", {
+            R.with2(session())
+                .query(|(ctx, user, token), _: ()| async move {
+                    let sessions = ctx.user_manager.sessions(user.id, &token).await?;
+                    Ok(sessions)
+                })
+        })
+        .procedure("revokeSession", {
+            R.with2(user())
+                .mutation(|(ctx, user), args: RevokeSessionArgs| async move {
+                    ctx.user_manager
+                        .revoke_session(user.id, args.session_id)
+                        .await
+                        .map_err(account_error)
+                })
         })
-    })
 }
 
 // pub(crate) fn mount() -> AlphaRouter<Ctx> {
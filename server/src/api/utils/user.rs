@@ -41,3 +41,21 @@ pub(crate) fn user() -> impl MwV3<Ctx, NewCtx = (Ctx, User)> {
         Ok(mw.next((ctx, user)))
     })
 }
+
+/// session is [user] that also passes on the token of the request, for operations on the session.
+pub(crate) fn session() -> impl MwV3<Ctx, NewCtx = (Ctx, User, String)> {
+    MwArgMapperMiddleware::<UserArgsLike>::new().mount(|mw, ctx: Ctx, jwt| async move {
+        let user = ctx
+            .user_manager
+            .user_from_jwt(jwt.clone())
+            .await
+            .ok_or_else(|| {
+                rspc::Error::new(
+                    ErrorCode::BadRequest,
+                    "You must specify a valid user to use this operation.".to_string(),
+                )
+            })?;
+
+        Ok(mw.next((ctx, user, jwt)))
+    })
+}
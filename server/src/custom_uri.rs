@@ -1,4 +1,4 @@
-use crate::{get_spaces_dir, utils::u2b, Node};
+use crate::{get_spaces_dir, space::export::ExportFormat, utils::u2b, Node};
 
 use custom_prisma::prisma::file;
 use http_range::HttpRange;
@@ -62,6 +62,7 @@ async fn handler(node: Arc<Node>, req: Request) -> Result<Response<Vec<u8>>, Han
 
     match path.first() {
         Some(&"file") => handle_file(&node, &path, &req).await,
+        Some(&"export") => handle_export(&node, &path, &req).await,
         _ => Err(HandleCustomUriError::BadRequest("Invalid operation!")),
     }
 }
@@ -252,6 +253,104 @@ async fn handle_file(
         .body(buf)?)
 }
 
+/// handle_export serves a conversation at `/export/<space>/<conversation>/<format>?token=<token>` as
+/// a download. The link is opened by the browser directly, so it carries a short-lived export token
+/// from `messages.exportLink` instead of the session token, see [export_link].
+async fn handle_export(
+    node: &Node,
+    path: &[&str],
+    req: &Request,
+) -> Result<Response<Vec<u8>>, HandleCustomUriError> {
+    let mut builder = Response::builder();
+    if let Some(response) = cors(req.method(), &mut builder) {
+        return Ok(response?);
+    }
+
+    let space_id = path
+        .get(1)
+        .and_then(|id| id.parse::<Uuid>().ok())
+        .ok_or_else(|| {
+            HandleCustomUriError::BadRequest("Invalid number of parameters. Missing space_id!")
+        })?;
+    let conversation_id = path
+        .get(2)
+        .and_then(|id| id.parse::<Uuid>().ok())
+        .ok_or_else(|| {
+            HandleCustomUriError::BadRequest(
+                "Invalid number of parameters. Missing conversation_id!",
+            )
+        })?;
+    let format = path
+        .get(3)
+        .and_then(|format| ExportFormat::parse(format))
+        .ok_or_else(|| {
+            HandleCustomUriError::BadRequest("Invalid export format, use md, json or html!")
+        })?;
+
+    let token = req
+        .uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .find_map(|param| param.strip_prefix("token="))
+        .ok_or(HandleCustomUriError::Unauthorized)?;
+    let user_id = node
+        .user_manager
+        .export_user(token, conversation_id)
+        .ok_or(HandleCustomUriError::Unauthorized)?;
+    let space = node
+        .space_manager
+        .get_space(space_id)
+        .await
+        .filter(|space| space.owner_id == user_id)
+        .ok_or_else(|| HandleCustomUriError::NotFound("space"))?;
+
+    let export = space
+        .export_conversation(Some(conversation_id))
+        .await
+        .map_err(|e| {
+            error!("Failed to export conversation {}: {:?}", conversation_id, e);
+            HandleCustomUriError::NotFound("conversation")
+        })?;
+    let file = export.render(format).map_err(|e| {
+        error!("Failed to render conversation {}: {:?}", conversation_id, e);
+        HandleCustomUriError::BadRequest("Failed to render the conversation")
+    })?;
+
+    Ok(builder
+        .header("Content-Type", file.mime_type)
+        .header(
+            "Content-Disposition",
+            format!("attachment; filename=\"{}\"", file.file_name),
+        )
+        .status(StatusCode::OK)
+        .body(file.content.into_bytes())?)
+}
+
+/// file_link returns the path the file is served at, optionally anchored to a 1-based page.
+pub fn file_link(space_id: Uuid, file_id: Uuid, page: Option<i32>) -> String {
+    match page {
+        Some(page) => format!("/yerb/file/{}/{}#page={}", space_id, file_id, page),
+        None => format!("/yerb/file/{}/{}", space_id, file_id),
+    }
+}
+
+/// export_link returns the path the conversation is downloaded from with an export token.
+pub fn export_link(
+    space_id: Uuid,
+    conversation_id: Uuid,
+    format: ExportFormat,
+    token: &str,
+) -> String {
+    format!(
+        "/yerb/export/{}/{}/{}?token={}",
+        space_id,
+        conversation_id,
+        format.extension(),
+        token
+    )
+}
+
 pub fn create_custom_uri_endpoint(node: Arc<Node>) -> Endpoint<impl HttpEndpoint> {
     GenericEndpoint::new(
         "/*any",
@@ -277,6 +376,8 @@ pub enum HandleCustomUriError {
     RangeNotSatisfiable(&'static str),
     #[error("resource '{0}' not found")]
     NotFound(&'static str),
+    #[error("a valid jwt is required")]
+    Unauthorized,
 }
 
 impl From<HandleCustomUriError> for Response<Vec<u8>> {
@@ -319,7 +420,46 @@ impl From<HandleCustomUriError> for Response<Vec<u8>> {
                     .as_bytes()
                     .to_vec(),
             ),
+            HandleCustomUriError::Unauthorized => builder
+                .status(StatusCode::UNAUTHORIZED)
+                .body(b"A valid jwt is required".to_vec()),
         })
         .expect("internal error building hardcoded HTTP error response")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn links_files_with_and_without_a_page() {
+        let space_id = Uuid::new_v4();
+        let file_id = Uuid::new_v4();
+
+        assert_eq!(
+            file_link(space_id, file_id, None),
+            format!("/yerb/file/{}/{}", space_id, file_id)
+        );
+        assert_eq!(
+            file_link(space_id, file_id, Some(4)),
+            format!("/yerb/file/{}/{}#page=4", space_id, file_id)
+        );
+    }
+
+    #[test]
+    fn links_exports_by_extension() {
+        let space_id = Uuid::new_v4();
+        let conversation_id = Uuid::new_v4();
+
+        let link = export_link(space_id, conversation_id, ExportFormat::Markdown, "a.b.c");
+        assert_eq!(
+            link,
+            format!(
+                "/yerb/export/{}/{}/md?token=a.b.c",
+                space_id, conversation_id
+            )
+        );
+        assert_eq!(ExportFormat::parse("md"), Some(ExportFormat::Markdown));
+    }
+}
@@ -0,0 +1,210 @@
+use std::collections::HashMap;
+
+use super::ScoredId;
+
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+const STOPWORDS: [&str; 24] = [
+    "a", "an", "and", "are", "as", "at", "be", "by", "for", "from", "in", "is", "it", "of", "on",
+    "or", "that", "the", "this", "to", "was", "what", "with", "how",
+];
+
+/// Bm25Index is an in-memory inverted index used for keyword retrieval. It is not persisted, the
+/// [`super::SpaceIndex`] rebuilds it from the stored chunk texts when it is opened.
+///
+/// The `file_text` table of [`crate::search::SearchIndex`] holds the same text but can't replace it:
+/// its rows have no chunk id to fuse with the vector hits, and it is only written after learning,
+/// on a best-effort basis. This index changes together with the vectors, under the same lock.
+#[derive(Debug, Default, Clone)]
+pub struct Bm25Index {
+    postings: HashMap<String, Vec<(u32, u32)>>,
+    lengths: HashMap<u32, u32>,
+    /// the distinct terms of every chunk, so removing it only touches its own postings
+    terms: HashMap<u32, Vec<String>>,
+    total_length: u64,
+}
+
+impl Bm25Index {
+    pub fn insert(&mut self, id: u32, text: &str) {
+        self.remove(id);
+
+        let tokens = tokenize(text);
+        let mut frequencies: HashMap<String, u32> = HashMap::new();
+        for token in &tokens {
+            *frequencies.entry(token.clone()).or_default() += 1;
+        }
+
+        let mut terms = Vec::with_capacity(frequencies.len());
+        for (term, tf) in frequencies {
+            self.postings
+                .entry(term.clone())
+                .or_default()
+                .push((id, tf));
+            terms.push(term);
+        }
+        self.terms.insert(id, terms);
+        self.lengths.insert(id, tokens.len() as u32);
+        self.total_length += tokens.len() as u64;
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        let Some(length) = self.lengths.remove(&id) else {
+            return;
+        };
+        self.total_length -= length as u64;
+
+        for term in self.terms.remove(&id).unwrap_or_default() {
+            let Some(postings) = self.postings.get_mut(&term) else {
+                continue;
+            };
+            postings.retain(|(doc, _)| *doc != id);
+            if postings.is_empty() {
+                self.postings.remove(&term);
+            }
+        }
+    }
+
+    pub fn search(&self, query: &str, k: usize, filter: &dyn Fn(u32) -> bool) -> Vec<ScoredId> {
+        if self.lengths.is_empty() {
+            return vec![];
+        }
+
+        let docs = self.lengths.len() as f32;
+        let avg_length = self.total_length as f32 / docs;
+        let mut scores: HashMap<u32, f32> = HashMap::new();
+
+        let mut terms = tokenize(query);
+        terms.sort();
+        terms.dedup();
+
+        for term in terms {
+            let Some(postings) = self.postings.get(&term) else {
+                continue;
+            };
+
+            let df = postings.len() as f32;
+            let idf = ((docs - df + 0.5) / (df + 0.5) + 1.0).ln();
+
+            for &(id, tf) in postings {
+                if !filter(id) {
+                    continue;
+                }
+                let tf = tf as f32;
+                let length = self.lengths.get(&id).copied().unwrap_or_default() as f32;
+                let norm = K1 * (1.0 - B + B * length / avg_length);
+                *scores.entry(id).or_default() += idf * tf * (K1 + 1.0) / (tf + norm);
+            }
+        }
+
+        let mut scored = scores
+            .into_iter()
+            .map(|(id, score)| ScoredId { id, score })
+            .collect::<Vec<_>>();
+        scored.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.id.cmp(&b.id)));
+        scored.truncate(k);
+        scored
+    }
+}
+
+/// tokenize lowercases the text and splits it into alphanumeric terms, dropping possessive `'s` and stopwords.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric() && c != '\'' && c != '’')
+        .map(|token| {
+            token
+                .trim_matches(|c| c == '\'' || c == '’')
+                .trim_end_matches("'s")
+                .trim_end_matches("’s")
+        })
+        .filter(|token| !token.is_empty() && !STOPWORDS.contains(token))
+        .map(str::to_string)
+        .collect()
+}
+
+/// reciprocal_rank_fusion merges ranked lists, scoring each id by the sum of `1 / (k + rank)` over the lists it appears in.
+pub fn reciprocal_rank_fusion(lists: &[Vec<ScoredId>], k: f32) -> Vec<ScoredId> {
+    let mut scores: HashMap<u32, f32> = HashMap::new();
+
+    for list in lists {
+        for (rank, scored) in list.iter().enumerate() {
+            *scores.entry(scored.id).or_default() += 1.0 / (k + rank as f32 + 1.0);
+        }
+    }
+
+    let mut fused = scores
+        .into_iter()
+        .map(|(id, score)| ScoredId { id, score })
+        .collect::<Vec<_>>();
+    fused.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.id.cmp(&b.id)));
+    fused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(scored: &[ScoredId]) -> Vec<u32> {
+        scored.iter().map(|s| s.id).collect()
+    }
+
+    fn ranked(ids: &[u32]) -> Vec<ScoredId> {
+        ids.iter().map(|&id| ScoredId { id, score: 0.0 }).collect()
+    }
+
+    #[test]
+    fn tokenizes_without_stopwords_and_possessives() {
+        assert_eq!(
+            tokenize("Jensen's inequality: how's it used?"),
+            vec!["jensen", "inequality", "used"]
+        );
+    }
+
+    #[test]
+    fn ranks_chunks_by_term_frequency() {
+        let mut index = Bm25Index::default();
+        index.insert(1, "the gradient of the loss function");
+        index.insert(2, "gradient descent follows the gradient");
+        index.insert(3, "a chunk about something else entirely");
+
+        let scored = index.search("gradient", 10, &|_| true);
+        assert_eq!(ids(&scored), vec![2, 1]);
+        assert!(scored[0].score > scored[1].score);
+
+        let filtered = index.search("gradient", 10, &|id| id != 2);
+        assert_eq!(ids(&filtered), vec![1]);
+    }
+
+    #[test]
+    fn removed_chunks_are_not_found() {
+        let mut index = Bm25Index::default();
+        index.insert(1, "stochastic gradient descent");
+        index.insert(2, "gradient descent minimizes the loss");
+
+        index.remove(2);
+        assert_eq!(ids(&index.search("gradient", 10, &|_| true)), vec![1]);
+        assert!(index.search("minimizes", 10, &|_| true).is_empty());
+        assert!(!index.postings.contains_key("minimizes"));
+        assert_eq!(index.total_length, 3);
+
+        index.remove(1);
+        assert!(index.postings.is_empty());
+        assert_eq!(index.total_length, 0);
+    }
+
+    #[test]
+    fn fuses_disjoint_lists_by_rank() {
+        let fused = reciprocal_rank_fusion(&[ranked(&[1, 2]), ranked(&[3, 4])], 60.0);
+
+        assert_eq!(ids(&fused), vec![1, 3, 2, 4]);
+        assert!((fused[0].score - 1.0 / 61.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn fuses_overlapping_lists() {
+        let fused = reciprocal_rank_fusion(&[ranked(&[1, 2, 3]), ranked(&[2, 3, 5])], 60.0);
+
+        assert_eq!(ids(&fused), vec![2, 3, 1, 5]);
+        assert!((fused[0].score - (1.0 / 62.0 + 1.0 / 61.0)).abs() < f32::EPSILON);
+    }
+}
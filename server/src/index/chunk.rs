@@ -0,0 +1,151 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tokio::fs;
+
+use crate::sidecar::{ExtractRequest, ExtractResponse, SidecarClient};
+
+/// Same splitting parameters the Python server uses for the Chroma store.
+pub const CHUNK_SIZE: usize = 500;
+pub const CHUNK_OVERLAP: usize = 50;
+
+/// Extensions the node can read and split without going through the Python server.
+pub const NATIVE_EXTENSIONS: [&str; 4] = ["txt", "md", "json", "csv"];
+
+/// extracts_natively tells whether files with the extension are read without the Python server.
+pub fn extracts_natively(extension: &str) -> bool {
+    NATIVE_EXTENSIONS.contains(&extension.to_lowercase().as_str())
+}
+
+/// A piece of text extracted from a file, before it is embedded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TextChunk {
+    pub index: u32,
+    pub page: Option<u32>,
+    pub text: String,
+}
+
+impl TextChunk {
+    pub fn hash(&self) -> String {
+        blake3::hash(self.text.as_bytes()).to_hex().to_string()
+    }
+}
+
+/// extract_chunks reads the file and splits it into chunks ready to be embedded. Plain text formats are
+/// handled natively; everything else (pdf, docx, ...) is loaded by the Python server's `/extract`.
+pub async fn extract_chunks(
+    sidecar: &SidecarClient,
+    path: impl AsRef<Path>,
+    extension: &str,
+) -> Result<Vec<TextChunk>> {
+    let path = path.as_ref();
+
+    if extracts_natively(extension) {
+        let text = fs::read_to_string(path)
+            .await
+            .with_context(|| format!("Failed to read {:?}", path))?;
+
+        return Ok(split_text(&text, CHUNK_SIZE, CHUNK_OVERLAP)
+            .into_iter()
+            .enumerate()
+            .map(|(index, text)| TextChunk {
+                index: index as u32,
+                page: None,
+                text,
+            })
+            .collect());
+    }
+
+    let request = ExtractRequest {
+        file_path: path.to_string_lossy().into_owned(),
+    };
+    let res = sidecar
+        .post::<_, ExtractResponse>("/extract", &request)
+        .await
+        .context("Failed to extract file")?;
+
+    Ok(res
+        .chunks
+        .unwrap_or_default()
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| TextChunk {
+            index: index as u32,
+            page: chunk.page,
+            text: chunk.text,
+        })
+        .collect())
+}
+
+/// split_text splits on paragraph, line, then word boundaries so that every chunk is at most
+/// `chunk_size` characters, with roughly `overlap` characters repeated between neighbouring chunks.
+pub fn split_text(text: &str, chunk_size: usize, overlap: usize) -> Vec<String> {
+    let mut pieces = vec![];
+    split_recursive(text, chunk_size, &["\n\n", "\n", " "], &mut pieces);
+
+    let mut chunks: Vec<String> = vec![];
+    let mut current = String::new();
+
+    for piece in pieces {
+        if !current.is_empty() && char_len(&current) + char_len(&piece) > chunk_size {
+            chunks.push(current.trim().to_string());
+
+            // carry the tail of the previous chunk over, starting on a word boundary
+            let tail = tail_chars(&current, overlap);
+            current = match tail.find(char::is_whitespace) {
+                Some(i) => tail[i..].to_string(),
+                None => String::new(),
+            };
+            if char_len(&current) + char_len(&piece) > chunk_size {
+                current.clear();
+            }
+        }
+        current.push_str(&piece);
+    }
+
+    if !current.trim().is_empty() {
+        chunks.push(current.trim().to_string());
+    }
+
+    chunks.retain(|chunk| !chunk.is_empty());
+    chunks
+}
+
+fn split_recursive(text: &str, chunk_size: usize, separators: &[&str], out: &mut Vec<String>) {
+    if char_len(text) <= chunk_size {
+        out.push(text.to_string());
+        return;
+    }
+
+    let Some((separator, rest)) = separators.split_first() else {
+        // no separator left, hard split on characters
+        let chars = text.chars().collect::<Vec<_>>();
+        out.extend(chars.chunks(chunk_size).map(|c| c.iter().collect()));
+        return;
+    };
+
+    let mut parts = text.split_inclusive(separator).peekable();
+    if parts.peek().map(|p| p.len()) == Some(text.len()) {
+        split_recursive(text, chunk_size, rest, out);
+        return;
+    }
+
+    for part in parts {
+        split_recursive(part, chunk_size, rest, out);
+    }
+}
+
+fn char_len(text: &str) -> usize {
+    text.chars().count()
+}
+
+fn tail_chars(text: &str, n: usize) -> &str {
+    let len = char_len(text);
+    if len <= n {
+        return text;
+    }
+    match text.char_indices().nth(len - n) {
+        Some((i, _)) => &text[i..],
+        None => text,
+    }
+}
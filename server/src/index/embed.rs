@@ -0,0 +1,101 @@
+use anyhow::{bail, Context, Result};
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use std::env;
+use tracing::debug;
+
+/// Number of inputs sent per embeddings request.
+const EMBED_BATCH_SIZE: usize = 64;
+
+/// Embedder turns text into vectors using an OpenAI-compatible `/embeddings` endpoint.
+#[derive(Debug, Clone)]
+pub struct Embedder {
+    client: Client,
+    base_url: String,
+    model: String,
+    api_key: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct EmbeddingsRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize, Debug)]
+struct EmbeddingsResponse {
+    data: Vec<EmbeddingData>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+impl Embedder {
+    /// from_env reads `EMBEDDINGS_BASE_URL`, `EMBEDDINGS_MODEL` and `OPENAI_API_KEY`.
+    pub fn from_env() -> Self {
+        Self {
+            client: Client::new(),
+            base_url: env::var("EMBEDDINGS_BASE_URL")
+                .unwrap_or("https://api.openai.com/v1".to_string()),
+            model: env::var("EMBEDDINGS_MODEL").unwrap_or("text-embedding-ada-002".to_string()),
+            api_key: env::var("OPENAI_API_KEY").ok(),
+        }
+    }
+
+    pub async fn embed(&self, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut vectors = Vec::with_capacity(inputs.len());
+
+        for batch in inputs.chunks(EMBED_BATCH_SIZE) {
+            debug!("Embedding batch of {} inputs", batch.len());
+
+            let mut req = self
+                .client
+                .post(format!(
+                    "{}/embeddings",
+                    self.base_url.trim_end_matches('/')
+                ))
+                .json(&EmbeddingsRequest {
+                    model: &self.model,
+                    input: batch,
+                });
+            if let Some(api_key) = &self.api_key {
+                req = req.bearer_auth(api_key);
+            }
+
+            let res = req
+                .send()
+                .await
+                .context("Failed to send embeddings request")?;
+            if !res.status().is_success() {
+                bail!("Embeddings request failed: {}", res.status());
+            }
+
+            let mut res: EmbeddingsResponse = res
+                .json()
+                .await
+                .context("Failed to parse embeddings response")?;
+            if res.data.len() != batch.len() {
+                bail!(
+                    "Embeddings response has {} vectors for {} inputs",
+                    res.data.len(),
+                    batch.len()
+                );
+            }
+
+            res.data.sort_by_key(|d| d.index);
+            vectors.extend(res.data.into_iter().map(|d| d.embedding));
+        }
+
+        Ok(vectors)
+    }
+
+    pub async fn embed_one(&self, input: &str) -> Result<Vec<f32>> {
+        self.embed(&[input.to_string()])
+            .await?
+            .pop()
+            .context("Embeddings response was empty")
+    }
+}
@@ -0,0 +1,58 @@
+use serde::{Deserialize, Serialize};
+
+use super::{dot, ScoredId};
+
+/// FlatIndex is an exact (brute force) vector store. Every query scans all vectors, which is
+/// perfectly fine for the few thousand chunks a typical space holds.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct FlatIndex {
+    entries: Vec<(u32, Vec<f32>)>,
+}
+
+impl FlatIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn insert(&mut self, id: u32, vector: Vec<f32>) {
+        self.remove(id);
+        self.entries.push((id, vector));
+    }
+
+    pub fn remove(&mut self, id: u32) -> bool {
+        let before = self.entries.len();
+        self.entries.retain(|(entry_id, _)| *entry_id != id);
+        before != self.entries.len()
+    }
+
+    pub fn get(&self, id: u32) -> Option<&[f32]> {
+        self.entries
+            .iter()
+            .find(|(entry_id, _)| *entry_id == id)
+            .map(|(_, vector)| vector.as_slice())
+    }
+
+    pub fn search(&self, query: &[f32], k: usize, filter: &dyn Fn(u32) -> bool) -> Vec<ScoredId> {
+        let mut scored = self
+            .entries
+            .iter()
+            .filter(|(id, _)| filter(*id))
+            .map(|(id, vector)| ScoredId {
+                id: *id,
+                score: dot(query, vector),
+            })
+            .collect::<Vec<_>>();
+
+        scored.sort_by(|a, b| b.score.total_cmp(&a.score));
+        scored.truncate(k);
+        scored
+    }
+}
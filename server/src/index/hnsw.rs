@@ -0,0 +1,320 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, HashMap, HashSet},
+};
+
+use super::{dot, ScoredId};
+
+/// Max neighbours kept per node on the upper layers (layer 0 keeps twice as many).
+const M: usize = 16;
+const EF_CONSTRUCTION: usize = 100;
+const EF_SEARCH: usize = 64;
+
+/// HnswIndex is an approximate nearest neighbour store based on Hierarchical Navigable Small World graphs.
+/// Removed vectors are tombstoned and stay in the graph for navigation until more than half of the nodes
+/// are dead, at which point the graph is rebuilt from the live vectors.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HnswIndex {
+    nodes: Vec<HnswNode>,
+    slots: HashMap<u32, usize>,
+    entry: Option<usize>,
+    max_level: usize,
+    tombstones: usize,
+    rng_state: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct HnswNode {
+    id: u32,
+    vector: Vec<f32>,
+    neighbours: Vec<Vec<usize>>,
+    deleted: bool,
+}
+
+/// Candidate ordered by distance (smaller is closer).
+#[derive(Clone, Copy, PartialEq)]
+struct Candidate {
+    distance: f32,
+    slot: usize,
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.slot.cmp(&other.slot))
+    }
+}
+
+impl Default for HnswIndex {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            slots: HashMap::new(),
+            entry: None,
+            max_level: 0,
+            tombstones: 0,
+            rng_state: 0x2545_f491_4f6c_dd1d,
+        }
+    }
+}
+
+impl HnswIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.nodes.len() - self.tombstones
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn insert(&mut self, id: u32, vector: Vec<f32>) {
+        self.remove(id);
+
+        let level = self.random_level();
+        let slot = self.nodes.len();
+        self.nodes.push(HnswNode {
+            id,
+            vector,
+            neighbours: vec![Vec::new(); level + 1],
+            deleted: false,
+        });
+        self.slots.insert(id, slot);
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(slot);
+            self.max_level = level;
+            return;
+        };
+
+        let query = self.nodes[slot].vector.clone();
+        let mut entry_points = vec![entry];
+
+        for layer in (level + 1..=self.max_level).rev() {
+            entry_points = self
+                .search_layer(&query, &entry_points, 1, layer)
+                .into_iter()
+                .map(|c| c.slot)
+                .take(1)
+                .collect();
+        }
+
+        for layer in (0..=level.min(self.max_level)).rev() {
+            let candidates = self.search_layer(&query, &entry_points, EF_CONSTRUCTION, layer);
+            let max_neighbours = Self::max_neighbours(layer);
+
+            let selected = candidates
+                .iter()
+                .filter(|c| c.slot != slot)
+                .take(max_neighbours)
+                .map(|c| c.slot)
+                .collect::<Vec<_>>();
+
+            for &neighbour in &selected {
+                self.nodes[neighbour].neighbours[layer].push(slot);
+                if self.nodes[neighbour].neighbours[layer].len() > max_neighbours {
+                    self.prune(neighbour, layer, max_neighbours);
+                }
+            }
+            self.nodes[slot].neighbours[layer] = selected;
+
+            entry_points = candidates.into_iter().map(|c| c.slot).collect();
+        }
+
+        if level > self.max_level {
+            self.max_level = level;
+            self.entry = Some(slot);
+        }
+    }
+
+    pub fn remove(&mut self, id: u32) -> bool {
+        let Some(slot) = self.slots.remove(&id) else {
+            return false;
+        };
+
+        self.nodes[slot].deleted = true;
+        self.tombstones += 1;
+
+        if self.tombstones * 2 > self.nodes.len() {
+            self.rebuild();
+        }
+
+        true
+    }
+
+    pub fn get(&self, id: u32) -> Option<&[f32]> {
+        self.slots
+            .get(&id)
+            .map(|&slot| self.nodes[slot].vector.as_slice())
+    }
+
+    pub fn search(&self, query: &[f32], k: usize, filter: &dyn Fn(u32) -> bool) -> Vec<ScoredId> {
+        let Some(entry) = self.entry else {
+            return vec![];
+        };
+
+        let mut entry_points = vec![entry];
+        for layer in (1..=self.max_level).rev() {
+            entry_points = self
+                .search_layer(query, &entry_points, 1, layer)
+                .into_iter()
+                .map(|c| c.slot)
+                .take(1)
+                .collect();
+        }
+
+        let mut results = self
+            .search_layer(query, &entry_points, EF_SEARCH.max(k), 0)
+            .into_iter()
+            .map(|c| &self.nodes[c.slot])
+            .filter(|node| !node.deleted && filter(node.id))
+            .map(|node| ScoredId {
+                id: node.id,
+                score: dot(query, &node.vector),
+            })
+            .take(k)
+            .collect::<Vec<_>>();
+
+        // A restrictive filter can starve the graph walk, so fall back to an exact scan.
+        if results.len() < k && results.len() < self.len() {
+            results = self
+                .nodes
+                .iter()
+                .filter(|node| !node.deleted && filter(node.id))
+                .map(|node| ScoredId {
+                    id: node.id,
+                    score: dot(query, &node.vector),
+                })
+                .collect();
+            results.sort_by(|a, b| b.score.total_cmp(&a.score));
+            results.truncate(k);
+        }
+
+        results
+    }
+
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entry_points: &[usize],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Candidate> {
+        let mut visited: HashSet<usize> = entry_points.iter().copied().collect();
+        // min-heap of candidates to expand and max-heap of the best results found so far
+        let mut candidates = BinaryHeap::new();
+        let mut results = BinaryHeap::new();
+
+        for &slot in entry_points {
+            let candidate = Candidate {
+                distance: self.distance(query, slot),
+                slot,
+            };
+            candidates.push(std::cmp::Reverse(candidate));
+            results.push(candidate);
+        }
+
+        while let Some(std::cmp::Reverse(current)) = candidates.pop() {
+            let furthest = results.peek().map(|c| c.distance).unwrap_or(f32::MAX);
+            if current.distance > furthest && results.len() >= ef {
+                break;
+            }
+
+            let Some(neighbours) = self.nodes[current.slot].neighbours.get(layer) else {
+                continue;
+            };
+
+            for &neighbour in neighbours {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+
+                let candidate = Candidate {
+                    distance: self.distance(query, neighbour),
+                    slot: neighbour,
+                };
+                let furthest = results.peek().map(|c| c.distance).unwrap_or(f32::MAX);
+
+                if results.len() < ef || candidate.distance < furthest {
+                    candidates.push(std::cmp::Reverse(candidate));
+                    results.push(candidate);
+                    if results.len() > ef {
+                        results.pop();
+                    }
+                }
+            }
+        }
+
+        results.into_sorted_vec()
+    }
+
+    fn prune(&mut self, slot: usize, layer: usize, max_neighbours: usize) {
+        let vector = self.nodes[slot].vector.clone();
+        let mut neighbours = self.nodes[slot].neighbours[layer]
+            .iter()
+            .map(|&n| Candidate {
+                distance: self.distance(&vector, n),
+                slot: n,
+            })
+            .collect::<Vec<_>>();
+
+        neighbours.sort();
+        neighbours.truncate(max_neighbours);
+        self.nodes[slot].neighbours[layer] = neighbours.into_iter().map(|c| c.slot).collect();
+    }
+
+    fn rebuild(&mut self) {
+        let live = std::mem::take(&mut self.nodes)
+            .into_iter()
+            .filter(|node| !node.deleted)
+            .collect::<Vec<_>>();
+
+        let rng_state = self.rng_state;
+        *self = Self {
+            rng_state,
+            ..Self::default()
+        };
+
+        for node in live {
+            self.insert(node.id, node.vector);
+        }
+    }
+
+    fn distance(&self, query: &[f32], slot: usize) -> f32 {
+        1.0 - dot(query, &self.nodes[slot].vector)
+    }
+
+    fn max_neighbours(layer: usize) -> usize {
+        if layer == 0 {
+            M * 2
+        } else {
+            M
+        }
+    }
+
+    // xorshift64*, we only need a cheap deterministic source for picking levels
+    fn random_level(&mut self) -> usize {
+        let mut x = self.rng_state;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.rng_state = x;
+
+        let uniform = (x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 11) as f64 / (1u64 << 53) as f64;
+        let level_mult = 1.0 / (M as f64).ln();
+        (-(uniform.max(f64::MIN_POSITIVE)).ln() * level_mult) as usize
+    }
+}
@@ -0,0 +1,79 @@
+use anyhow::Result;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+use tokio::sync::RwLock;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::get_spaces_dir;
+
+use super::{Embedder, IndexKind, RetrievalMode, ScoredChunk, SpaceIndex};
+
+/// IndexManager lazily loads and caches the [`SpaceIndex`] of every space that has been touched.
+pub struct IndexManager {
+    indexes: RwLock<HashMap<Uuid, Arc<RwLock<SpaceIndex>>>>,
+    pub embedder: Embedder,
+}
+
+impl IndexManager {
+    pub fn new() -> Arc<Self> {
+        debug!("IndexManager initialized");
+        Arc::new(Self {
+            indexes: RwLock::new(HashMap::new()),
+            embedder: Embedder::from_env(),
+        })
+    }
+
+    /// get returns the index of the space, loading it from disk on first use.
+    pub async fn get(&self, space_id: Uuid) -> Result<Arc<RwLock<SpaceIndex>>> {
+        if let Some(index) = self.indexes.read().await.get(&space_id) {
+            return Ok(index.clone());
+        }
+
+        let mut indexes = self.indexes.write().await;
+        // another caller may have loaded it while we were waiting for the write lock
+        if let Some(index) = indexes.get(&space_id) {
+            return Ok(index.clone());
+        }
+
+        let dir = get_spaces_dir()
+            .await
+            .join(space_id.to_string())
+            .join("index");
+        let index = Arc::new(RwLock::new(
+            SpaceIndex::open(dir, IndexKind::from_env()).await?,
+        ));
+        indexes.insert(space_id, index.clone());
+
+        Ok(index)
+    }
+
+    /// evict forgets the cached index of a space, e.g. after the space has been deleted.
+    pub async fn evict(&self, space_id: Uuid) {
+        self.indexes.write().await.remove(&space_id);
+    }
+
+    /// search returns the `k` best chunks of the space for the query, embedding it when the mode needs it.
+    /// With `files` only chunks of those files are searched.
+    pub async fn search(
+        &self,
+        space_id: Uuid,
+        query: &str,
+        k: usize,
+        mode: RetrievalMode,
+        files: Option<&HashSet<Uuid>>,
+    ) -> Result<Vec<ScoredChunk>> {
+        let vector = match mode {
+            RetrievalMode::Keyword => None,
+            RetrievalMode::Vector | RetrievalMode::Hybrid => {
+                Some(self.embedder.embed_one(query).await?)
+            }
+        };
+
+        let index = self.get(space_id).await?;
+        let index = index.read().await;
+        Ok(index.retrieve(query, vector.as_deref(), k, mode, files))
+    }
+}
@@ -0,0 +1,533 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use tokio::fs;
+use uuid::Uuid;
+
+mod bm25;
+mod chunk;
+mod embed;
+mod flat;
+mod hnsw;
+mod manager;
+
+pub use bm25::*;
+pub use chunk::*;
+pub use embed::*;
+pub use flat::FlatIndex;
+pub use hnsw::HnswIndex;
+pub use manager::*;
+
+/// Bumped whenever the on-disk layout of [`SpaceIndex`] changes.
+const INDEX_FORMAT_VERSION: u32 = 1;
+const INDEX_FILE_NAME: &str = "space.idx";
+
+/// Which component owns the vectors of a space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexBackend {
+    /// Chunks are embedded by the node and stored in the space's [`SpaceIndex`].
+    Native,
+    /// Chunks live in the Chroma store (`vector_db`) managed by the Python server.
+    Sidecar,
+}
+
+impl IndexBackend {
+    pub fn from_env() -> Self {
+        match env::var("INDEX_BACKEND").as_deref() {
+            Ok("native") => IndexBackend::Native,
+            _ => IndexBackend::Sidecar,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+pub enum IndexKind {
+    Flat,
+    Hnsw,
+}
+
+impl IndexKind {
+    pub fn from_env() -> Self {
+        match env::var("INDEX_KIND").as_deref() {
+            Ok("flat") => IndexKind::Flat,
+            _ => IndexKind::Hnsw,
+        }
+    }
+}
+
+/// How chunks are retrieved for a question, configured per space (stored on `Meta.retrieval_mode`).
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Serialize,
+    Deserialize,
+    Type,
+    strum::Display,
+    strum::EnumString,
+)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum RetrievalMode {
+    /// embedding similarity only
+    Vector,
+    /// BM25 keyword search only
+    Keyword,
+    /// both, merged with reciprocal rank fusion
+    Hybrid,
+}
+
+impl RetrievalMode {
+    /// parse falls back to [`RetrievalMode::Hybrid`] for unknown values.
+    pub fn parse(value: &str) -> Self {
+        RetrievalMode::from_str(value).unwrap_or(RetrievalMode::Hybrid)
+    }
+}
+
+/// Constant used by reciprocal rank fusion, 60 is the value from the original paper.
+const RRF_K: f32 = 60.0;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ScoredId {
+    pub id: u32,
+    pub score: f32,
+}
+
+/// VectorStore holds normalized vectors keyed by chunk id. Scores are cosine similarities.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum VectorStore {
+    Flat(FlatIndex),
+    Hnsw(HnswIndex),
+}
+
+impl VectorStore {
+    pub fn new(kind: IndexKind) -> Self {
+        match kind {
+            IndexKind::Flat => VectorStore::Flat(FlatIndex::new()),
+            IndexKind::Hnsw => VectorStore::Hnsw(HnswIndex::new()),
+        }
+    }
+
+    pub fn kind(&self) -> IndexKind {
+        match self {
+            VectorStore::Flat(_) => IndexKind::Flat,
+            VectorStore::Hnsw(_) => IndexKind::Hnsw,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            VectorStore::Flat(store) => store.len(),
+            VectorStore::Hnsw(store) => store.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn insert(&mut self, id: u32, mut vector: Vec<f32>) {
+        normalize(&mut vector);
+        match self {
+            VectorStore::Flat(store) => store.insert(id, vector),
+            VectorStore::Hnsw(store) => store.insert(id, vector),
+        }
+    }
+
+    pub fn remove(&mut self, id: u32) -> bool {
+        match self {
+            VectorStore::Flat(store) => store.remove(id),
+            VectorStore::Hnsw(store) => store.remove(id),
+        }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&[f32]> {
+        match self {
+            VectorStore::Flat(store) => store.get(id),
+            VectorStore::Hnsw(store) => store.get(id),
+        }
+    }
+
+    pub fn search(&self, query: &[f32], k: usize, filter: &dyn Fn(u32) -> bool) -> Vec<ScoredId> {
+        let mut query = query.to_vec();
+        normalize(&mut query);
+        match self {
+            VectorStore::Flat(store) => store.search(&query, k, filter),
+            VectorStore::Hnsw(store) => store.search(&query, k, filter),
+        }
+    }
+}
+
+/// A piece of a learned file, as stored in the index.
+#[derive(Debug, Clone, Serialize, Deserialize, Type)]
+pub struct Chunk {
+    pub id: u32,
+    pub file_id: Uuid,
+    /// position of the chunk inside its file
+    pub index: u32,
+    /// page the chunk starts on, for paginated formats
+    pub page: Option<u32>,
+    pub text: String,
+    /// blake3 hash of `text`, used to reuse embeddings when a file is re-learned
+    #[serde(default)]
+    pub hash: String,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct ScoredChunk {
+    pub chunk: Chunk,
+    pub score: f32,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct IndexStats {
+    pub kind: IndexKind,
+    pub dimensions: Option<u32>,
+    pub chunks: u32,
+    pub files: u32,
+}
+
+/// SpaceIndex is the native vector index of a single space. It is persisted as MessagePack under
+/// `<space>/index/space.idx`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SpaceIndex {
+    version: u32,
+    dimensions: Option<usize>,
+    next_id: u32,
+    chunks: HashMap<u32, Chunk>,
+    store: VectorStore,
+    #[serde(skip)]
+    keywords: Bm25Index,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl SpaceIndex {
+    pub fn new(path: impl AsRef<Path>, kind: IndexKind) -> Self {
+        Self {
+            version: INDEX_FORMAT_VERSION,
+            dimensions: None,
+            next_id: 0,
+            chunks: HashMap::new(),
+            store: VectorStore::new(kind),
+            keywords: Bm25Index::default(),
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// open loads the index stored in `dir`, or creates an empty one if none exists yet.
+    pub async fn open(dir: impl AsRef<Path>, kind: IndexKind) -> Result<Self> {
+        let path = dir.as_ref().join(INDEX_FILE_NAME);
+
+        if !path.exists() {
+            return Ok(Self::new(path, kind));
+        }
+
+        let bytes = fs::read(&path)
+            .await
+            .with_context(|| format!("Failed to read index at {:?}", path))?;
+        let mut index: SpaceIndex = rmp_serde::from_slice(&bytes)
+            .with_context(|| format!("Failed to decode index at {:?}", path))?;
+
+        if index.version != INDEX_FORMAT_VERSION {
+            bail!(
+                "Index at {:?} has format version {} but {} is expected",
+                path,
+                index.version,
+                INDEX_FORMAT_VERSION
+            );
+        }
+
+        for chunk in index.chunks.values() {
+            index.keywords.insert(chunk.id, &chunk.text);
+        }
+
+        index.path = path;
+        Ok(index)
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir).await?;
+        }
+
+        let bytes = rmp_serde::to_vec_named(self).context("Failed to encode index")?;
+
+        // write to a temporary file first so a crash never leaves a truncated index behind
+        let tmp_path = self.path.with_extension("idx.tmp");
+        fs::write(&tmp_path, bytes).await?;
+        fs::rename(&tmp_path, &self.path).await?;
+
+        Ok(())
+    }
+
+    /// add_chunks stores the embedded chunks of a file. Returns the ids assigned to the chunks.
+    pub fn add_chunks(
+        &mut self,
+        file_id: Uuid,
+        chunks: impl IntoIterator<Item = (TextChunk, Vec<f32>)>,
+    ) -> Result<Vec<u32>> {
+        let mut ids = vec![];
+
+        for (chunk, vector) in chunks {
+            match self.dimensions {
+                Some(dimensions) if dimensions != vector.len() => bail!(
+                    "Embedding has {} dimensions but the index expects {}",
+                    vector.len(),
+                    dimensions
+                ),
+                None => self.dimensions = Some(vector.len()),
+                _ => {}
+            }
+
+            let id = self.next_id;
+            self.next_id += 1;
+
+            self.store.insert(id, vector);
+            self.keywords.insert(id, &chunk.text);
+            self.chunks.insert(
+                id,
+                Chunk {
+                    id,
+                    file_id,
+                    index: chunk.index,
+                    page: chunk.page,
+                    hash: chunk.hash(),
+                    text: chunk.text,
+                },
+            );
+            ids.push(id);
+        }
+
+        Ok(ids)
+    }
+
+    /// remove_file drops every chunk belonging to the file. Returns the number of chunks removed.
+    pub fn remove_file(&mut self, file_id: Uuid) -> usize {
+        let ids = self
+            .chunks
+            .values()
+            .filter(|chunk| chunk.file_id == file_id)
+            .map(|chunk| chunk.id)
+            .collect::<Vec<_>>();
+
+        for id in &ids {
+            self.store.remove(*id);
+            self.keywords.remove(*id);
+            self.chunks.remove(id);
+        }
+
+        ids.len()
+    }
+
+    /// vectors_by_hash returns the stored vectors of the file's chunks keyed by chunk hash, so that
+    /// unchanged chunks don't need to be embedded again when the file is re-learned.
+    pub fn vectors_by_hash(&self, file_id: Uuid) -> HashMap<String, Vec<f32>> {
+        self.chunks
+            .values()
+            .filter(|chunk| chunk.file_id == file_id && !chunk.hash.is_empty())
+            .filter_map(|chunk| {
+                self.store
+                    .get(chunk.id)
+                    .map(|vector| (chunk.hash.clone(), vector.to_vec()))
+            })
+            .collect()
+    }
+
+    /// query returns the `k` chunks closest to the given embedding, best match first.
+    pub fn query(&self, vector: &[f32], k: usize) -> Vec<ScoredChunk> {
+        self.retrieve("", Some(vector), k, RetrievalMode::Vector, None)
+    }
+
+    /// retrieve returns the `k` best chunks for the question using the given mode. The embedding of the
+    /// question is only needed for the vector and hybrid modes. With `files` only chunks of those files
+    /// are considered.
+    pub fn retrieve(
+        &self,
+        text: &str,
+        vector: Option<&[f32]>,
+        k: usize,
+        mode: RetrievalMode,
+        files: Option<&HashSet<Uuid>>,
+    ) -> Vec<ScoredChunk> {
+        let filter = |id| match files {
+            Some(files) => self
+                .chunks
+                .get(&id)
+                .is_some_and(|chunk| files.contains(&chunk.file_id)),
+            None => true,
+        };
+
+        let semantic = || match vector {
+            Some(vector) if self.dimensions == Some(vector.len()) => {
+                self.store.search(vector, k, &filter)
+            }
+            _ => vec![],
+        };
+        let keyword = || self.keywords.search(text, k, &filter);
+
+        let scored = match mode {
+            RetrievalMode::Vector => semantic(),
+            RetrievalMode::Keyword => keyword(),
+            RetrievalMode::Hybrid => {
+                let mut fused = reciprocal_rank_fusion(&[semantic(), keyword()], RRF_K);
+                fused.truncate(k);
+                fused
+            }
+        };
+
+        scored
+            .into_iter()
+            .filter_map(|scored| {
+                self.chunks.get(&scored.id).map(|chunk| ScoredChunk {
+                    chunk: chunk.clone(),
+                    score: scored.score,
+                })
+            })
+            .collect()
+    }
+
+    pub fn chunks_for_file(&self, file_id: Uuid) -> Vec<&Chunk> {
+        let mut chunks = self
+            .chunks
+            .values()
+            .filter(|chunk| chunk.file_id == file_id)
+            .collect::<Vec<_>>();
+        chunks.sort_by_key(|chunk| chunk.index);
+        chunks
+    }
+
+    pub fn stats(&self) -> IndexStats {
+        let files = self
+            .chunks
+            .values()
+            .map(|chunk| chunk.file_id)
+            .collect::<HashSet<_>>();
+
+        IndexStats {
+            kind: self.store.kind(),
+            dimensions: self.dimensions.map(|d| d as u32),
+            chunks: self.store.len() as u32,
+            files: files.len() as u32,
+        }
+    }
+}
+
+pub(crate) fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+fn normalize(vector: &mut [f32]) {
+    let norm = dot(vector, vector).sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(seed: u64, dimensions: usize) -> Vec<f32> {
+        let mut x = seed.wrapping_add(1).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        (0..dimensions)
+            .map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                (x % 2000) as f32 / 1000.0 - 1.0
+            })
+            .collect()
+    }
+
+    #[test]
+    fn hnsw_matches_flat_top_hit() {
+        let mut flat = VectorStore::new(IndexKind::Flat);
+        let mut hnsw = VectorStore::new(IndexKind::Hnsw);
+        for id in 0..500 {
+            flat.insert(id, vector(id.into(), 32));
+            hnsw.insert(id, vector(id.into(), 32));
+        }
+
+        let mut hits = 0;
+        for seed in 1000..1050 {
+            let query = vector(seed, 32);
+            let expected = flat.search(&query, 1, &|_| true)[0].id;
+            if hnsw
+                .search(&query, 5, &|_| true)
+                .iter()
+                .any(|s| s.id == expected)
+            {
+                hits += 1;
+            }
+        }
+        assert!(hits >= 45, "hnsw recall too low: {hits}/50");
+    }
+
+    #[test]
+    fn remove_file_drops_its_chunks() {
+        let mut index = SpaceIndex::new("space.idx", IndexKind::Hnsw);
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let chunk = |index| TextChunk {
+            index,
+            page: None,
+            text: format!("chunk {index}"),
+        };
+
+        index
+            .add_chunks(a, (0..20).map(|i| (chunk(i), vector(i.into(), 8))))
+            .expect("dimensions match");
+        index
+            .add_chunks(
+                b,
+                (0..20).map(|i| (chunk(i), vector(100 + u64::from(i), 8))),
+            )
+            .expect("dimensions match");
+
+        assert_eq!(index.remove_file(a), 20);
+        assert_eq!(index.stats().chunks, 20);
+        assert!(index
+            .query(&vector(3, 8), 10)
+            .iter()
+            .all(|hit| hit.chunk.file_id == b));
+    }
+
+    #[test]
+    fn retrieve_only_searches_selected_files() {
+        let mut index = SpaceIndex::new("space.idx", IndexKind::Flat);
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        let chunk = |index| TextChunk {
+            index,
+            page: None,
+            text: format!("osmosis chunk {index}"),
+        };
+
+        for (file, offset) in [(a, 0), (b, 100)] {
+            index
+                .add_chunks(
+                    file,
+                    (0..10).map(|i| (chunk(i), vector(offset + u64::from(i), 8))),
+                )
+                .expect("dimensions match");
+        }
+
+        let files = HashSet::from([b]);
+        for mode in [
+            RetrievalMode::Vector,
+            RetrievalMode::Keyword,
+            RetrievalMode::Hybrid,
+        ] {
+            let hits = index.retrieve("osmosis", Some(&vector(3, 8)), 5, mode, Some(&files));
+            assert_eq!(hits.len(), 5);
+            assert!(hits.iter().all(|hit| hit.chunk.file_id == b));
+        }
+    }
+}
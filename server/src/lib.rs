@@ -4,6 +4,10 @@ use crate::api::{CoreEvent, Router};
 
 use axum::body::Bytes;
 use custom_prisma::prisma::{self, PrismaClient};
+use index::IndexManager;
+use llm::LlmProvider;
+use search::SearchIndex;
+use sidecar::{BackendHealth, SidecarClient, SidecarSupervisor};
 use space::SpaceManager;
 use std::{
     env,
@@ -25,6 +29,10 @@ pub mod api;
 pub mod custom_uri;
 pub mod utils;
 
+pub(crate) mod index;
+pub(crate) mod llm;
+pub(crate) mod search;
+pub(crate) mod sidecar;
 pub(crate) mod space;
 pub(crate) mod tasks;
 pub(crate) mod user;
@@ -34,16 +42,26 @@ pub struct NodeContext {
     pub event_bus_tx: broadcast::Sender<CoreEvent>,
     pub db: Arc<PrismaClient>,
     pub dispatcher: Arc<Dispatcher>,
+    pub index_manager: Arc<IndexManager>,
+    /// generates replies from the node itself, `None` when the Python server answers
+    pub llm: Option<Arc<dyn LlmProvider>>,
+    /// shared client for the Python server
+    pub sidecar: Arc<SidecarClient>,
+    /// full-text index over the messages and files of all spaces
+    pub search: Arc<SearchIndex>,
 }
 
 pub struct Node {
     pub spaces_dir: PathBuf,
     pub space_manager: Arc<SpaceManager>,
     pub user_manager: Arc<user::UserManager>,
+    pub search: Arc<SearchIndex>,
 
     event_bus: (broadcast::Sender<CoreEvent>, broadcast::Receiver<CoreEvent>),
     db: Arc<PrismaClient>,
     dispatcher: Arc<Dispatcher>,
+    sidecar: Arc<SidecarClient>,
+    sidecar_supervisor: Option<Arc<SidecarSupervisor>>,
 }
 
 pub async fn get_db() -> Result<Arc<PrismaClient>> {
@@ -85,15 +103,28 @@ impl Node {
 
         let _ = fs::create_dir_all(&spaces_dir).await?;
 
+        // fails before anything starts when the node would sign tokens with a known secret
+        let jwt_keys = user::JwtKeys::from_env()?;
+
         let event_bus = broadcast::channel(1024);
 
         let db = get_db().await?;
-        let dispatcher = Dispatcher::new();
+        let sidecar_supervisor = SidecarSupervisor::from_env();
+        let supervisor_health = sidecar_supervisor.as_ref().map(|s| s.health());
+        let sidecar = Arc::new(SidecarClient::new(supervisor_health.clone())?);
+        let dispatcher = Dispatcher::new(supervisor_health);
+        let index_manager = IndexManager::new();
+        let llm = llm::provider_from_env();
+        let search = SearchIndex::open(SearchIndex::path(spaces_dir))?;
 
         let space_manager = SpaceManager::new(NodeContext {
             event_bus_tx: event_bus.0.clone(),
             db: db.clone(),
             dispatcher: dispatcher.clone(),
+            index_manager: index_manager.clone(),
+            llm: llm.clone(),
+            sidecar: sidecar.clone(),
+            search: search.clone(),
         })
         .await?;
 
@@ -102,21 +133,38 @@ impl Node {
                 event_bus_tx: event_bus.0.clone(),
                 db: db.clone(),
                 dispatcher: dispatcher.clone(),
+                index_manager: index_manager.clone(),
+                llm: llm.clone(),
+                sidecar: sidecar.clone(),
+                search: search.clone(),
             },
             space_manager.clone(),
+            jwt_keys,
         )
         .await?;
 
+        if search.created() {
+            space_manager.reindex_messages().await;
+        }
+
+        match &sidecar_supervisor {
+            Some(supervisor) => supervisor.clone().start(sidecar.clone()),
+            None => sidecar.handshake().await?,
+        }
+
         let router = api::mount();
         let node = Node {
             spaces_dir: spaces_dir.to_path_buf(),
 
             space_manager,
             user_manager,
+            search,
 
             event_bus,
             db,
             dispatcher,
+            sidecar,
+            sidecar_supervisor,
         };
 
         info!("Yerb online.");
@@ -172,9 +220,18 @@ impl Node {
         Ok(())
     }
 
+    /// sidecar_health reports whether the Python server can currently be used.
+    pub fn sidecar_health(&self) -> BackendHealth {
+        self.sidecar.health()
+    }
+
     pub async fn shutdown(&self) {
         info!("shutting down...");
 
+        if let Some(supervisor) = &self.sidecar_supervisor {
+            supervisor.shutdown().await;
+        }
+
         info!("shutdown complete");
     }
 }
@@ -0,0 +1,113 @@
+use anyhow::{Context, Result};
+use std::{collections::VecDeque, sync::Mutex};
+
+use super::{ChatMessage, LlmProvider, Role};
+
+/// MockProvider answers with scripted replies, in order, and records the prompts it was sent.
+/// Once the script is exhausted (or when built with [`MockProvider::echo`]) it echoes the last user message.
+#[derive(Debug, Default)]
+pub struct MockProvider {
+    replies: Mutex<VecDeque<String>>,
+    requests: Mutex<Vec<Vec<ChatMessage>>>,
+}
+
+impl MockProvider {
+    pub fn new(replies: impl IntoIterator<Item = impl Into<String>>) -> Self {
+        Self {
+            replies: Mutex::new(replies.into_iter().map(Into::into).collect()),
+            requests: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn echo() -> Self {
+        Self::default()
+    }
+
+    /// requests returns every prompt sent so far.
+    pub fn requests(&self) -> Vec<Vec<ChatMessage>> {
+        self.requests
+            .lock()
+            .map(|requests| requests.clone())
+            .unwrap_or_default()
+    }
+
+    fn reply(&self, messages: &[ChatMessage]) -> Result<String> {
+        self.requests
+            .lock()
+            .ok()
+            .context("Mock provider lock poisoned")?
+            .push(messages.to_vec());
+
+        let scripted = self
+            .replies
+            .lock()
+            .ok()
+            .context("Mock provider lock poisoned")?
+            .pop_front();
+
+        Ok(scripted.unwrap_or_else(|| {
+            messages
+                .iter()
+                .rev()
+                .find(|message| message.role == Role::User)
+                .map(|message| message.content.clone())
+                .unwrap_or_default()
+        }))
+    }
+}
+
+#[async_trait::async_trait]
+impl LlmProvider for MockProvider {
+    fn name(&self) -> String {
+        "mock".to_string()
+    }
+
+    async fn complete(&self, messages: &[ChatMessage]) -> Result<String> {
+        self.reply(messages)
+    }
+
+    /// stream reports the reply word by word.
+    async fn stream(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut (dyn for<'t> FnMut(&'t str) + Send),
+    ) -> Result<String> {
+        let text = self.reply(messages)?;
+        for token in text.split_inclusive(' ') {
+            on_token(token);
+        }
+        Ok(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn replies_in_script_order_then_echoes() {
+        let provider = MockProvider::new(["Hello there", "Second"]);
+        let prompt = [
+            ChatMessage::system("Be brief."),
+            ChatMessage::user("What is a cell?"),
+        ];
+
+        let mut tokens = vec![];
+        let text = provider
+            .stream(&prompt, &mut |token| tokens.push(token.to_string()))
+            .await
+            .expect("mock stream failed");
+        assert_eq!(text, "Hello there");
+        assert_eq!(tokens, vec!["Hello ", "there"]);
+
+        assert_eq!(
+            provider.complete(&prompt).await.expect("mock failed"),
+            "Second"
+        );
+        assert_eq!(
+            provider.complete(&prompt).await.expect("mock failed"),
+            "What is a cell?"
+        );
+        assert_eq!(provider.requests().len(), 3);
+    }
+}
@@ -0,0 +1,87 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::{env, sync::Arc};
+use tracing::warn;
+
+mod mock;
+mod openai;
+mod sidecar;
+pub mod sse;
+pub mod tokens;
+
+pub use mock::MockProvider;
+pub use openai::OpenAiProvider;
+pub use sidecar::SidecarProvider;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    System,
+    User,
+    Assistant,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+}
+
+impl ChatMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::System,
+            content: content.into(),
+        }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::User,
+            content: content.into(),
+        }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self {
+            role: Role::Assistant,
+            content: content.into(),
+        }
+    }
+}
+
+/// LlmProvider generates chat completions.
+#[async_trait::async_trait]
+pub trait LlmProvider: Send + Sync {
+    /// name identifies the provider and model in logs.
+    fn name(&self) -> String;
+
+    async fn complete(&self, messages: &[ChatMessage]) -> Result<String>;
+
+    /// stream generates the completion, calling `on_token` with every piece of text as it arrives.
+    /// Returns the full completion. Providers that cannot stream report the whole text as one token.
+    async fn stream(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut (dyn for<'t> FnMut(&'t str) + Send),
+    ) -> Result<String> {
+        let text = self.complete(messages).await?;
+        on_token(&text);
+        Ok(text)
+    }
+}
+
+/// provider_from_env returns the provider chosen by `LLM_PROVIDER`: `openai` for any
+/// OpenAI-compatible server, `mock` for a provider echoing the question. When unset, answers are
+/// generated by the Python server.
+pub fn provider_from_env() -> Option<Arc<dyn LlmProvider>> {
+    match env::var("LLM_PROVIDER").as_deref() {
+        Ok("openai") => Some(Arc::new(OpenAiProvider::from_env())),
+        Ok("mock") => Some(Arc::new(MockProvider::echo())),
+        Ok("sidecar") | Err(_) => None,
+        Ok(other) => {
+            warn!("Unknown LLM_PROVIDER {:?}, using the Python server", other);
+            None
+        }
+    }
+}
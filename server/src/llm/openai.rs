@@ -0,0 +1,177 @@
+use anyhow::{bail, Context, Result};
+use reqwest::{Client, RequestBuilder};
+use serde::{Deserialize, Serialize};
+use std::env;
+use tracing::debug;
+
+use super::{sse::SseStream, ChatMessage, LlmProvider};
+
+/// OpenAiProvider talks to an OpenAI-compatible `/chat/completions` endpoint, which covers OpenAI
+/// itself as well as local model servers (llama.cpp, vLLM, Ollama, ...).
+#[derive(Debug, Clone)]
+pub struct OpenAiProvider {
+    client: Client,
+    base_url: String,
+    model: String,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+    api_key: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct CompletionRequest<'a> {
+    model: &'a str,
+    messages: &'a [ChatMessage],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    stream: bool,
+}
+
+#[derive(Deserialize, Debug)]
+struct CompletionResponse {
+    choices: Vec<CompletionChoice>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CompletionChoice {
+    message: ChatMessage,
+}
+
+#[derive(Deserialize, Debug)]
+struct CompletionChunk {
+    choices: Vec<ChunkChoice>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChunkChoice {
+    delta: ChunkDelta,
+}
+
+#[derive(Deserialize, Debug)]
+struct ChunkDelta {
+    content: Option<String>,
+}
+
+impl OpenAiProvider {
+    pub fn new(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        Self {
+            client: Client::new(),
+            base_url: base_url.into(),
+            model: model.into(),
+            temperature: None,
+            max_tokens: None,
+            api_key: None,
+        }
+    }
+
+    /// from_env reads `LLM_BASE_URL`, `LLM_MODEL`, `LLM_TEMPERATURE`, `LLM_MAX_TOKENS` and `OPENAI_API_KEY`.
+    pub fn from_env() -> Self {
+        Self {
+            temperature: env::var("LLM_TEMPERATURE")
+                .ok()
+                .and_then(|t| t.parse().ok()),
+            max_tokens: env::var("LLM_MAX_TOKENS").ok().and_then(|t| t.parse().ok()),
+            api_key: env::var("OPENAI_API_KEY").ok(),
+            ..Self::new(
+                env::var("LLM_BASE_URL").unwrap_or("https://api.openai.com/v1".to_string()),
+                env::var("LLM_MODEL").unwrap_or("gpt-3.5-turbo".to_string()),
+            )
+        }
+    }
+
+    fn request(&self, messages: &[ChatMessage], stream: bool) -> RequestBuilder {
+        let req = self
+            .client
+            .post(format!(
+                "{}/chat/completions",
+                self.base_url.trim_end_matches('/')
+            ))
+            .json(&CompletionRequest {
+                model: &self.model,
+                messages,
+                temperature: self.temperature,
+                max_tokens: self.max_tokens,
+                stream,
+            });
+
+        match &self.api_key {
+            Some(api_key) => req.bearer_auth(api_key),
+            None => req,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl LlmProvider for OpenAiProvider {
+    fn name(&self) -> String {
+        format!("openai:{}", self.model)
+    }
+
+    async fn complete(&self, messages: &[ChatMessage]) -> Result<String> {
+        debug!(
+            "Sending completion request with {} messages",
+            messages.len()
+        );
+
+        let res = self
+            .request(messages, false)
+            .send()
+            .await
+            .context("Failed to send completion request")?;
+        if !res.status().is_success() {
+            bail!("Completion request failed: {}", res.status());
+        }
+
+        let res: CompletionResponse = res
+            .json()
+            .await
+            .context("Failed to parse completion response")?;
+
+        res.choices
+            .into_iter()
+            .next()
+            .map(|choice| choice.message.content)
+            .context("Completion response has no choices")
+    }
+
+    async fn stream(
+        &self,
+        messages: &[ChatMessage],
+        on_token: &mut (dyn for<'t> FnMut(&'t str) + Send),
+    ) -> Result<String> {
+        debug!(
+            "Sending streaming completion request with {} messages",
+            messages.len()
+        );
+
+        let res = self
+            .request(messages, true)
+            .send()
+            .await
+            .context("Failed to send completion request")?;
+        if !res.status().is_success() {
+            bail!("Completion request failed: {}", res.status());
+        }
+
+        let mut events = SseStream::new(res);
+        let mut text = String::new();
+        while let Some(event) = events.next().await? {
+            if event.data == "[DONE]" {
+                break;
+            }
+
+            let chunk: CompletionChunk =
+                serde_json::from_str(&event.data).context("Failed to parse completion chunk")?;
+            for choice in chunk.choices {
+                if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+                    on_token(&content);
+                    text.push_str(&content);
+                }
+            }
+        }
+
+        Ok(text)
+    }
+}
@@ -0,0 +1,48 @@
+use anyhow::{Context, Result};
+use std::sync::Arc;
+
+use crate::sidecar::{CompleteMessage, CompleteRequest, CompleteResponse, SidecarClient};
+
+use super::{ChatMessage, LlmProvider, Role};
+
+/// SidecarProvider generates completions with the Python server's chat model. It is used for
+/// generation outside of replies when the node has no provider of its own.
+pub struct SidecarProvider {
+    client: Arc<SidecarClient>,
+}
+
+impl SidecarProvider {
+    pub fn new(client: Arc<SidecarClient>) -> Self {
+        Self { client }
+    }
+}
+
+#[async_trait::async_trait]
+impl LlmProvider for SidecarProvider {
+    fn name(&self) -> String {
+        "sidecar".to_string()
+    }
+
+    async fn complete(&self, messages: &[ChatMessage]) -> Result<String> {
+        let request = CompleteRequest {
+            messages: messages
+                .iter()
+                .map(|message| CompleteMessage {
+                    role: match message.role {
+                        Role::System => "system",
+                        Role::User => "user",
+                        Role::Assistant => "assistant",
+                    }
+                    .to_string(),
+                    content: message.content.clone(),
+                })
+                .collect(),
+        };
+
+        let res = self
+            .client
+            .post::<_, CompleteResponse>("/complete", &request)
+            .await?;
+        res.text.context("The Python server returned no completion")
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::VecDeque;
+
+use anyhow::{Context, Result};
+use futures::{stream::BoxStream, StreamExt};
+
+/// A single server-sent event.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseEvent {
+    pub event: String,
+    pub data: String,
+}
+
+/// SseParser incrementally decodes a `text/event-stream` body. Chunks of the body can split events
+/// (and lines) anywhere, so incomplete input is buffered until the blank line that ends the event.
+#[derive(Debug, Default)]
+pub struct SseParser {
+    buffer: String,
+    event: Option<String>,
+    data: Vec<String>,
+}
+
+impl SseParser {
+    /// push feeds the next chunk of the body and returns the events it completed.
+    pub fn push(&mut self, chunk: &str) -> Vec<SseEvent> {
+        self.buffer.push_str(chunk);
+
+        let mut events = vec![];
+        while let Some(end) = self.buffer.find('\n') {
+            let line = self.buffer[..end].trim_end_matches('\r').to_string();
+            self.buffer.drain(..=end);
+
+            if line.is_empty() {
+                if let Some(event) = self.dispatch() {
+                    events.push(event);
+                }
+                continue;
+            }
+            // comment, used as keep-alive
+            if line.starts_with(':') {
+                continue;
+            }
+
+            let (field, value) = match line.split_once(':') {
+                Some((field, value)) => (field, value.strip_prefix(' ').unwrap_or(value)),
+                None => (line.as_str(), ""),
+            };
+            match field {
+                "event" => self.event = Some(value.to_string()),
+                "data" => self.data.push(value.to_string()),
+                _ => {}
+            }
+        }
+
+        events
+    }
+
+    /// finish returns the last event if the body ended without a trailing blank line.
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        let rest = std::mem::take(&mut self.buffer) + "\n";
+        let mut events = self.push(&rest);
+        events.extend(self.dispatch());
+        events.pop()
+    }
+
+    fn dispatch(&mut self) -> Option<SseEvent> {
+        let event = self.event.take();
+        if self.data.is_empty() {
+            return None;
+        }
+
+        Some(SseEvent {
+            event: event.unwrap_or_else(|| "message".to_string()),
+            data: std::mem::take(&mut self.data).join("\n"),
+        })
+    }
+}
+
+/// SseStream reads the server-sent events of a streaming response body.
+pub struct SseStream {
+    body: BoxStream<'static, reqwest::Result<Vec<u8>>>,
+    parser: SseParser,
+    // a multi-byte character can be split between two chunks of the body
+    pending: Vec<u8>,
+    events: VecDeque<SseEvent>,
+    ended: bool,
+}
+
+impl SseStream {
+    pub fn new(res: reqwest::Response) -> Self {
+        Self::from_body(
+            res.bytes_stream()
+                .map(|chunk| chunk.map(|b| b.to_vec()))
+                .boxed(),
+        )
+    }
+
+    fn from_body(body: BoxStream<'static, reqwest::Result<Vec<u8>>>) -> Self {
+        Self {
+            body,
+            parser: SseParser::default(),
+            pending: vec![],
+            events: VecDeque::new(),
+            ended: false,
+        }
+    }
+
+    /// next returns the next event, or `None` once the body has ended.
+    pub async fn next(&mut self) -> Result<Option<SseEvent>> {
+        loop {
+            if let Some(event) = self.events.pop_front() {
+                return Ok(Some(event));
+            }
+            if self.ended {
+                return Ok(None);
+            }
+
+            match self.body.next().await {
+                Some(chunk) => {
+                    self.pending
+                        .extend_from_slice(&chunk.context("Failed to read event stream")?);
+                    let decoded = decode_utf8(&mut self.pending);
+                    self.events.extend(self.parser.push(&decoded));
+                }
+                None => {
+                    self.ended = true;
+                    let rest = String::from_utf8_lossy(&self.pending).into_owned();
+                    self.pending.clear();
+                    self.events.extend(self.parser.push(&rest));
+                    self.events.extend(self.parser.finish());
+                }
+            }
+        }
+    }
+}
+
+/// decode_utf8 takes the decodable start of `pending`, replacing invalid bytes with U+FFFD, and
+/// leaves a character that is cut off at the end for the next chunk.
+fn decode_utf8(pending: &mut Vec<u8>) -> String {
+    let mut decoded = String::new();
+    let mut start = 0;
+    while start < pending.len() {
+        match std::str::from_utf8(&pending[start..]) {
+            Ok(rest) => {
+                decoded.push_str(rest);
+                start = pending.len();
+            }
+            Err(e) => {
+                let valid = start + e.valid_up_to();
+                decoded.push_str(&String::from_utf8_lossy(&pending[start..valid]));
+                match e.error_len() {
+                    Some(len) => {
+                        decoded.push(char::REPLACEMENT_CHARACTER);
+                        start = valid + len;
+                    }
+                    None => {
+                        start = valid;
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    pending.drain(..start);
+
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_events_split_across_chunks() {
+        let mut parser = SseParser::default();
+
+        assert!(parser.push("event: tok").is_empty());
+        let events = parser.push("en\ndata: {\"text\":\"Hel\"}\r\n\r\n: ping\n\nevent: token\n");
+        assert_eq!(
+            events,
+            vec![SseEvent {
+                event: "token".to_string(),
+                data: "{\"text\":\"Hel\"}".to_string(),
+            }]
+        );
+
+        assert!(parser.push("data: {\"text\":\"lo\"}").is_empty());
+        assert_eq!(
+            parser.finish(),
+            Some(SseEvent {
+                event: "token".to_string(),
+                data: "{\"text\":\"lo\"}".to_string(),
+            })
+        );
+    }
+
+    fn body(chunks: &[&[u8]]) -> BoxStream<'static, reqwest::Result<Vec<u8>>> {
+        let chunks: Vec<_> = chunks.iter().map(|chunk| Ok(chunk.to_vec())).collect();
+        futures::stream::iter(chunks).boxed()
+    }
+
+    #[tokio::test]
+    async fn decodes_characters_split_across_chunks() {
+        let mut stream = SseStream::from_body(body(&[b"data: caf\xc3", b"\xa9\n\n"]));
+
+        let event = stream.next().await.expect("failed to read the stream");
+        assert_eq!(event.map(|event| event.data), Some("café".to_string()));
+        assert_eq!(
+            stream.next().await.expect("failed to read the stream"),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn replaces_invalid_bytes() {
+        let mut stream =
+            SseStream::from_body(body(&[b"data: a\xffb\n\n", b"data: c\n\ndata: \xe2\x82"]));
+
+        let mut data = vec![];
+        while let Some(event) = stream.next().await.expect("failed to read the stream") {
+            data.push(event.data);
+        }
+        assert_eq!(data, vec!["a\u{fffd}b", "c", "\u{fffd}"]);
+    }
+}
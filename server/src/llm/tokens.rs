@@ -0,0 +1,64 @@
+use once_cell::sync::Lazy;
+use tiktoken_rs::CoreBPE;
+use tracing::warn;
+
+/// The encoding of the OpenAI chat models. Other models tokenize differently, but it is close enough to budget a prompt.
+static BPE: Lazy<Option<CoreBPE>> = Lazy::new(|| match tiktoken_rs::cl100k_base() {
+    Ok(bpe) => Some(bpe),
+    Err(e) => {
+        warn!(
+            "Failed to load the tokenizer, estimating token counts: {:?}",
+            e
+        );
+        None
+    }
+});
+
+/// Rough number of characters per token, used when the tokenizer is not available.
+const CHARS_PER_TOKEN: usize = 4;
+
+/// count_tokens returns how many tokens `text` takes up in a prompt.
+pub fn count_tokens(text: &str) -> usize {
+    match BPE.as_ref() {
+        Some(bpe) => bpe.encode_with_special_tokens(text).len(),
+        None => text.chars().count().div_ceil(CHARS_PER_TOKEN),
+    }
+}
+
+/// truncate_tokens cuts `text` down to its first `max_tokens` tokens.
+pub fn truncate_tokens(text: &str, max_tokens: usize) -> String {
+    let Some(bpe) = BPE.as_ref() else {
+        return text.chars().take(max_tokens * CHARS_PER_TOKEN).collect();
+    };
+
+    let tokens = bpe.encode_with_special_tokens(text);
+    if tokens.len() <= max_tokens {
+        return text.to_string();
+    }
+
+    // a character can be split across tokens, drop tokens until the rest decodes again
+    let mut end = max_tokens;
+    while end > 0 {
+        if let Ok(text) = bpe.decode(tokens[..end].to_vec()) {
+            return text;
+        }
+        end -= 1;
+    }
+    String::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_to_the_token_budget() {
+        let text = "The mitochondria is the powerhouse of the cell. ".repeat(20);
+        assert!(count_tokens(&text) > 50);
+
+        let truncated = truncate_tokens(&text, 50);
+        assert!(count_tokens(&truncated) <= 50);
+        assert!(text.starts_with(&truncated));
+        assert_eq!(truncate_tokens("short", 50), "short");
+    }
+}
@@ -0,0 +1,463 @@
+use anyhow::{Context, Result};
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension};
+use serde::Serialize;
+use specta::Type;
+use std::{
+    env,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::index::TextChunk;
+
+/// Bumped whenever the tables change, an index with another version is rebuilt from scratch.
+const SEARCH_SCHEMA_VERSION: i32 = 1;
+const SEARCH_DB_FILE_NAME: &str = "search.db";
+/// Most hits a single query returns.
+pub const MAX_SEARCH_HITS: usize = 100;
+/// Tokens of context a snippet shows around the matches.
+const SNIPPET_TOKENS: u32 = 16;
+
+// Marks the start and end of a match in a snippet, private use characters can't clash with stored text
+const HIGHLIGHT_START: char = '\u{E000}';
+const HIGHLIGHT_END: char = '\u{E001}';
+
+const SCHEMA: &str = "
+CREATE VIRTUAL TABLE IF NOT EXISTS message_text USING fts5(
+    text,
+    message_id UNINDEXED,
+    space_id UNINDEXED,
+    conversation_id UNINDEXED,
+    tokenize = 'porter unicode61 remove_diacritics 2'
+);
+CREATE VIRTUAL TABLE IF NOT EXISTS file_text USING fts5(
+    text,
+    file_id UNINDEXED,
+    space_id UNINDEXED,
+    name UNINDEXED,
+    page UNINDEXED,
+    tokenize = 'porter unicode61 remove_diacritics 2'
+);
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Type)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchHitKind {
+    Message,
+    File,
+}
+
+/// A piece of a snippet, `highlighted` parts matched the query.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Type)]
+pub struct SnippetPart {
+    pub text: String,
+    pub highlighted: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct SearchHit {
+    pub kind: SearchHitKind,
+    pub space_id: Uuid,
+    /// id of the message or file that matched
+    pub id: Uuid,
+    /// conversation of a matching message
+    pub conversation_id: Option<Uuid>,
+    /// name of a matching file
+    pub file_name: Option<String>,
+    /// page of a matching file the snippet is on, for paginated formats
+    pub page: Option<u32>,
+    pub snippet: Vec<SnippetPart>,
+    /// higher is better, comparable between messages and files of the same query
+    pub score: f64,
+}
+
+/// A message as it is stored in the search index.
+#[derive(Debug, Clone)]
+pub struct MessageDocument {
+    pub id: Uuid,
+    pub space_id: Uuid,
+    pub conversation_id: Option<Uuid>,
+    pub text: String,
+}
+
+/// SearchIndex is the node's full-text index over messages and the text of learned files. It is an
+/// SQLite FTS5 database next to the spaces, separate from the Prisma database so schema pushes leave
+/// it alone.
+pub struct SearchIndex {
+    conn: Arc<Mutex<Connection>>,
+    created: bool,
+}
+
+impl SearchIndex {
+    /// path returns where the index is stored, `SEARCH_DB_PATH` or `search.db` in the spaces directory.
+    pub fn path(spaces_dir: &Path) -> PathBuf {
+        env::var("SEARCH_DB_PATH")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| spaces_dir.join(SEARCH_DB_FILE_NAME))
+    }
+
+    pub fn open(path: impl AsRef<Path>) -> Result<Arc<Self>> {
+        let path = path.as_ref();
+        let conn = Connection::open(path)
+            .with_context(|| format!("Failed to open search index at {:?}", path))?;
+        Self::init(conn)
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> Result<Arc<Self>> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Arc<Self>> {
+        let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        let exists = conn
+            .query_row(
+                "SELECT name FROM sqlite_master WHERE name = 'message_text'",
+                [],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?
+            .is_some();
+
+        let created = !exists || version != SEARCH_SCHEMA_VERSION;
+        if exists && version != SEARCH_SCHEMA_VERSION {
+            debug!(
+                "Search index has schema version {}, rebuilding it as version {}",
+                version, SEARCH_SCHEMA_VERSION
+            );
+            conn.execute_batch(
+                "DROP TABLE IF EXISTS message_text; DROP TABLE IF EXISTS file_text;",
+            )?;
+        }
+        conn.execute_batch(SCHEMA)
+            .context("Failed to create the search index, SQLite needs FTS5")?;
+        conn.execute_batch(&format!("PRAGMA user_version = {}", SEARCH_SCHEMA_VERSION))?;
+
+        Ok(Arc::new(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            created,
+        }))
+    }
+
+    /// created tells whether the index started out empty, so existing data has to be indexed.
+    pub fn created(&self) -> bool {
+        self.created
+    }
+
+    /// index_message adds the message to the index, replacing an earlier version of it.
+    pub async fn index_message(&self, message: MessageDocument) -> Result<()> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "DELETE FROM message_text WHERE message_id = ?",
+                params![message.id.to_string()],
+            )?;
+            if !message.text.trim().is_empty() {
+                tx.execute(
+                    "INSERT INTO message_text (text, message_id, space_id, conversation_id) VALUES (?, ?, ?, ?)",
+                    params![
+                        message.text,
+                        message.id.to_string(),
+                        message.space_id.to_string(),
+                        message.conversation_id.map(|id| id.to_string()),
+                    ],
+                )?;
+            }
+            tx.commit()
+        })
+        .await
+    }
+
+    /// index_file replaces the text of the file in the index with its chunks.
+    pub async fn index_file(
+        &self,
+        space_id: Uuid,
+        file_id: Uuid,
+        name: String,
+        chunks: Vec<TextChunk>,
+    ) -> Result<()> {
+        self.with_conn(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "DELETE FROM file_text WHERE file_id = ?",
+                params![file_id.to_string()],
+            )?;
+            {
+                let mut insert = tx.prepare(
+                    "INSERT INTO file_text (text, file_id, space_id, name, page) VALUES (?, ?, ?, ?, ?)",
+                )?;
+                for chunk in chunks {
+                    insert.execute(params![
+                        chunk.text,
+                        file_id.to_string(),
+                        space_id.to_string(),
+                        name,
+                        chunk.page,
+                    ])?;
+                }
+            }
+            tx.commit()
+        })
+        .await
+    }
+
+    pub async fn remove_file(&self, file_id: Uuid) -> Result<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "DELETE FROM file_text WHERE file_id = ?",
+                params![file_id.to_string()],
+            )
+            .map(|_| ())
+        })
+        .await
+    }
+
+    pub async fn remove_conversation(&self, conversation_id: Uuid) -> Result<()> {
+        self.with_conn(move |conn| {
+            conn.execute(
+                "DELETE FROM message_text WHERE conversation_id = ?",
+                params![conversation_id.to_string()],
+            )
+            .map(|_| ())
+        })
+        .await
+    }
+
+    pub async fn remove_space(&self, space_id: Uuid) -> Result<()> {
+        self.with_conn(move |conn| {
+            let space_id = space_id.to_string();
+            conn.execute(
+                "DELETE FROM message_text WHERE space_id = ?",
+                params![space_id],
+            )?;
+            conn.execute(
+                "DELETE FROM file_text WHERE space_id = ?",
+                params![space_id],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// query returns the best `limit` messages and file chunks of the spaces that match the query.
+    pub async fn query(
+        &self,
+        space_ids: &[Uuid],
+        query: &str,
+        limit: usize,
+    ) -> Result<Vec<SearchHit>> {
+        let Some(expression) = match_expression(query) else {
+            return Ok(vec![]);
+        };
+        if space_ids.is_empty() || limit == 0 {
+            return Ok(vec![]);
+        }
+
+        let spaces = space_ids.iter().map(Uuid::to_string).collect::<Vec<_>>();
+        let limit = limit.min(MAX_SEARCH_HITS);
+        self.with_conn(move |conn| {
+            let placeholders = vec!["?"; spaces.len()].join(", ");
+            let sql = format!(
+                "SELECT 'message', message_id, space_id, conversation_id, NULL, NULL,
+                        snippet(message_text, 0, ?1, ?2, '…', {tokens}), bm25(message_text) AS rank
+                 FROM message_text WHERE message_text MATCH ?3 AND space_id IN ({placeholders})
+                 UNION ALL
+                 SELECT 'file', file_id, space_id, NULL, name, page,
+                        snippet(file_text, 0, ?1, ?2, '…', {tokens}), bm25(file_text) AS rank
+                 FROM file_text WHERE file_text MATCH ?3 AND space_id IN ({placeholders})
+                 ORDER BY rank LIMIT {limit}",
+                tokens = SNIPPET_TOKENS,
+            );
+
+            let mut params = vec![
+                HIGHLIGHT_START.to_string(),
+                HIGHLIGHT_END.to_string(),
+                expression,
+            ];
+            params.extend(spaces.iter().cloned());
+            params.extend(spaces);
+
+            let mut statement = conn.prepare(&sql)?;
+            let rows = statement.query_map(params_from_iter(params), |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, Option<String>>(3)?,
+                    row.get::<_, Option<String>>(4)?,
+                    row.get::<_, Option<u32>>(5)?,
+                    row.get::<_, String>(6)?,
+                    row.get::<_, f64>(7)?,
+                ))
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()
+        })
+        .await?
+        .into_iter()
+        .map(
+            |(kind, id, space_id, conversation_id, file_name, page, snippet, rank)| {
+                Ok(SearchHit {
+                    kind: match kind.as_str() {
+                        "message" => SearchHitKind::Message,
+                        _ => SearchHitKind::File,
+                    },
+                    space_id: Uuid::from_str(&space_id)?,
+                    id: Uuid::from_str(&id)?,
+                    conversation_id: conversation_id.as_deref().map(Uuid::from_str).transpose()?,
+                    file_name,
+                    page,
+                    snippet: snippet_parts(&snippet),
+                    // bm25 is negative, lower is better
+                    score: -rank,
+                })
+            },
+        )
+        .collect()
+    }
+
+    /// with_conn runs the blocking SQLite work on the blocking thread pool.
+    async fn with_conn<T, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> rusqlite::Result<T> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        let res = tokio::task::spawn_blocking(move || {
+            // a panic can't leave the connection in a broken state, transactions roll back on drop
+            let mut conn = conn.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            f(&mut conn)
+        })
+        .await
+        .context("Search index task failed")?;
+        Ok(res?)
+    }
+}
+
+/// match_expression turns what the user typed into an FTS5 query: every word has to match and the
+/// last one may be incomplete. Returns `None` when there is nothing to search for.
+fn match_expression(query: &str) -> Option<String> {
+    let terms = query
+        .split_whitespace()
+        .map(|term| term.replace('"', ""))
+        .filter(|term| term.chars().any(char::is_alphanumeric))
+        .map(|term| format!("\"{}\"", term))
+        .collect::<Vec<_>>();
+
+    let (last, rest) = terms.split_last()?;
+    Some(
+        rest.iter()
+            .cloned()
+            .chain([format!("{last}*")])
+            .collect::<Vec<_>>()
+            .join(" "),
+    )
+}
+
+/// snippet_parts splits a snippet at the highlight markers.
+fn snippet_parts(snippet: &str) -> Vec<SnippetPart> {
+    let mut parts = vec![];
+    let mut highlighted = false;
+    for piece in snippet.split([HIGHLIGHT_START, HIGHLIGHT_END]) {
+        if !piece.is_empty() {
+            parts.push(SnippetPart {
+                text: piece.to_string(),
+                highlighted,
+            });
+        }
+        highlighted = !highlighted;
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(page: u32, text: &str) -> TextChunk {
+        TextChunk {
+            index: page,
+            page: Some(page),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn builds_match_expressions() {
+        assert_eq!(
+            match_expression("cell \"membrane"),
+            Some("\"cell\" \"membrane\"*".to_string())
+        );
+        assert_eq!(match_expression("  - "), None);
+    }
+
+    #[tokio::test]
+    async fn finds_messages_and_files_of_the_given_spaces() {
+        let index = SearchIndex::open_in_memory().expect("failed to open index");
+        let space = Uuid::new_v4();
+        let other_space = Uuid::new_v4();
+        let file = Uuid::new_v4();
+
+        index
+            .index_message(MessageDocument {
+                id: Uuid::new_v4(),
+                space_id: space,
+                conversation_id: None,
+                text: "What does osmosis do to a cell?".to_string(),
+            })
+            .await
+            .expect("failed to index message");
+        index
+            .index_message(MessageDocument {
+                id: Uuid::new_v4(),
+                space_id: other_space,
+                conversation_id: None,
+                text: "Osmosis in another space".to_string(),
+            })
+            .await
+            .expect("failed to index message");
+        index
+            .index_file(
+                space,
+                file,
+                "biology.pdf".to_string(),
+                vec![
+                    chunk(1, "Osmosis moves water through a membrane."),
+                    chunk(2, "Mitochondria produce energy."),
+                ],
+            )
+            .await
+            .expect("failed to index file");
+
+        let hits = index
+            .query(&[space], "osmo", 10)
+            .await
+            .expect("failed to query");
+        assert_eq!(hits.len(), 2);
+        assert!(hits.iter().all(|hit| hit.space_id == space));
+
+        let file_hit = hits
+            .iter()
+            .find(|hit| hit.kind == SearchHitKind::File)
+            .expect("missing file hit");
+        assert_eq!(file_hit.id, file);
+        assert_eq!(file_hit.page, Some(1));
+        assert_eq!(file_hit.file_name.as_deref(), Some("biology.pdf"));
+        assert!(file_hit.snippet.contains(&SnippetPart {
+            text: "Osmosis".to_string(),
+            highlighted: true,
+        }));
+
+        index
+            .remove_file(file)
+            .await
+            .expect("failed to remove file");
+        let hits = index
+            .query(&[space], "membrane", 10)
+            .await
+            .expect("failed to query");
+        assert!(hits.is_empty());
+    }
+}
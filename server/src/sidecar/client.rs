@@ -0,0 +1,355 @@
+use anyhow::{bail, Context, Result};
+use reqwest::{Client, Response};
+use serde::{de::DeserializeOwned, Serialize};
+use specta::Type;
+use std::{
+    fmt::Debug,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+use crate::utils::python_server_root;
+
+use super::{SidecarResponse, VersionResponse, PROTOCOL_VERSION};
+
+/// Consecutive failed requests after which the circuit opens.
+const FAILURE_THRESHOLD: u32 = 5;
+/// How long an open circuit fails requests before letting a probe through.
+const OPEN_FOR: Duration = Duration::from_secs(30);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// timeout returns how long a request to the endpoint may take, including its body.
+fn timeout(path: &str) -> Duration {
+    match path {
+        "/version" => Duration::from_secs(5),
+        "/unlearn" => Duration::from_secs(60),
+        "/extract" | "/ask" | "/complete" => Duration::from_secs(180),
+        "/ask/stream" => Duration::from_secs(300),
+        // loading and embedding a large pdf takes a while
+        "/learn" => Duration::from_secs(900),
+        _ => Duration::from_secs(60),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Circuit {
+    Closed {
+        failures: u32,
+    },
+    Open {
+        until: Instant,
+    },
+    /// one probe request is in flight, its outcome closes or re-opens the circuit. A probe whose
+    /// future was dropped never records an outcome, so once `until` passes another one is let
+    /// through as if the circuit was still open.
+    HalfOpen {
+        until: Instant,
+    },
+}
+
+#[derive(Debug)]
+struct BreakerState {
+    circuit: Circuit,
+    last_error: Option<String>,
+}
+
+#[derive(Serialize, Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+/// BackendHealth is what the UI needs to tell whether the AI backend can be used.
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct BackendHealth {
+    pub available: bool,
+    pub circuit: CircuitState,
+    /// whether the node runs the Python server itself
+    pub supervised: bool,
+    pub last_error: Option<String>,
+}
+
+/// SidecarClient is the node's shared client for the Python server. It pools connections, applies
+/// per-endpoint timeouts and fails fast through a circuit breaker while the server is down.
+pub struct SidecarClient {
+    client: Client,
+    breaker: Mutex<BreakerState>,
+    /// health reported by the supervisor when the node runs the server itself
+    supervisor_health: Option<watch::Receiver<bool>>,
+}
+
+impl SidecarClient {
+    pub fn new(supervisor_health: Option<watch::Receiver<bool>>) -> Result<Self> {
+        let client = Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .pool_idle_timeout(Duration::from_secs(90))
+            .pool_max_idle_per_host(8)
+            .build()
+            .context("Failed to build Python server client")?;
+
+        Ok(Self {
+            client,
+            breaker: Mutex::new(BreakerState {
+                circuit: Circuit::Closed { failures: 0 },
+                last_error: None,
+            }),
+            supervisor_health,
+        })
+    }
+
+    /// post sends a request to the Python server. Fails when the request fails or when the server
+    /// answers with `success: false`.
+    pub async fn post<Req, Res>(&self, path: &str, request: &Req) -> Result<Res>
+    where
+        Req: Serialize + Debug,
+        Res: SidecarResponse + DeserializeOwned,
+    {
+        let res = self.send(path, request).await?;
+
+        let res: Res = res
+            .json()
+            .await
+            .with_context(|| format!("Failed to parse {} response", path))?;
+
+        if !res.success() {
+            bail!(
+                "{} request failed: {}",
+                path,
+                res.error().unwrap_or("unknown error")
+            );
+        }
+
+        Ok(res)
+    }
+
+    /// post_stream sends a request to a streaming endpoint and returns the response once its headers arrived.
+    pub async fn post_stream<Req>(&self, path: &str, request: &Req) -> Result<Response>
+    where
+        Req: Serialize + Debug,
+    {
+        self.send(path, request).await
+    }
+
+    /// version asks the Python server which protocol version it speaks. It bypasses the circuit
+    /// breaker so it can be used as a health check, but closes the circuit when it succeeds.
+    pub async fn version(&self) -> Result<u32> {
+        let res = self
+            .client
+            .get(python_server_root() + "/version")
+            .timeout(timeout("/version"))
+            .send()
+            .await
+            .context("Failed to send version request")?;
+
+        if !res.status().is_success() {
+            bail!("Version request failed: {}", res.status());
+        }
+
+        let version: VersionResponse = res
+            .json()
+            .await
+            .context("Failed to parse version response")?;
+        self.record(Ok(()));
+
+        Ok(version.protocol_version)
+    }
+
+    /// handshake checks that the Python server speaks the same protocol version as this node. A
+    /// server that can't be reached is only logged, it may be started after the node.
+    pub async fn handshake(&self) -> Result<()> {
+        let version = match self.version().await {
+            Ok(version) => version,
+            Err(e) => {
+                warn!(
+                    "Python server at {} did not answer the version check: {:?}",
+                    python_server_root(),
+                    e
+                );
+                return Ok(());
+            }
+        };
+
+        if version != PROTOCOL_VERSION {
+            bail!(
+                "Python server speaks protocol version {}, this node needs {}",
+                version,
+                PROTOCOL_VERSION
+            );
+        }
+
+        info!("Python server speaks protocol version {}", PROTOCOL_VERSION);
+        Ok(())
+    }
+
+    pub fn health(&self) -> BackendHealth {
+        let breaker = self.lock_breaker();
+        let circuit = match breaker.circuit {
+            Circuit::Closed { .. } => CircuitState::Closed,
+            Circuit::Open { until } if Instant::now() >= until => CircuitState::HalfOpen,
+            Circuit::Open { .. } => CircuitState::Open,
+            Circuit::HalfOpen { .. } => CircuitState::HalfOpen,
+        };
+        let process_healthy = match &self.supervisor_health {
+            Some(health) => *health.borrow(),
+            None => true,
+        };
+
+        BackendHealth {
+            available: process_healthy && circuit != CircuitState::Open,
+            circuit,
+            supervised: self.supervisor_health.is_some(),
+            last_error: breaker.last_error.clone(),
+        }
+    }
+
+    async fn send<Req>(&self, path: &str, request: &Req) -> Result<Response>
+    where
+        Req: Serialize + Debug,
+    {
+        self.admit(path)?;
+        debug!("Sending {} request: {:?}", path, request);
+
+        let res = self
+            .client
+            .post(python_server_root() + path)
+            .json(request)
+            .timeout(timeout(path))
+            .send()
+            .await
+            .with_context(|| format!("Failed to send {} request", path));
+
+        let res = match res {
+            Ok(res) if res.status().is_server_error() => {
+                Err(anyhow::anyhow!("{} request failed: {}", path, res.status()))
+            }
+            res => res,
+        };
+        self.record(res.as_ref().map(|_| ()).map_err(|e| format!("{e:#}")));
+
+        let res = res?;
+        if !res.status().is_success() {
+            bail!("{} request failed: {}", path, res.status());
+        }
+        Ok(res)
+    }
+
+    /// admit fails fast when the server is known to be down.
+    fn admit(&self, path: &str) -> Result<()> {
+        if let Some(health) = &self.supervisor_health {
+            if !*health.borrow() {
+                bail!("AI backend unavailable: the Python server is not running");
+            }
+        }
+
+        let mut breaker = self.lock_breaker();
+        let now = Instant::now();
+        match breaker.circuit {
+            Circuit::Closed { .. } => Ok(()),
+            Circuit::Open { until } | Circuit::HalfOpen { until } if now >= until => {
+                debug!("Circuit half-open, probing the Python server with {}", path);
+                breaker.circuit = Circuit::HalfOpen {
+                    until: now + timeout(path),
+                };
+                Ok(())
+            }
+            Circuit::Open { .. } | Circuit::HalfOpen { .. } => bail!(
+                "AI backend unavailable: {}",
+                breaker
+                    .last_error
+                    .as_deref()
+                    .unwrap_or("too many failed requests")
+            ),
+        }
+    }
+
+    fn record(&self, outcome: std::result::Result<(), String>) {
+        let mut breaker = self.lock_breaker();
+        match outcome {
+            Ok(()) => {
+                if !matches!(breaker.circuit, Circuit::Closed { .. }) {
+                    info!("Python server is reachable again, closing the circuit");
+                }
+                breaker.circuit = Circuit::Closed { failures: 0 };
+                breaker.last_error = None;
+            }
+            Err(error) => {
+                breaker.circuit = match breaker.circuit {
+                    Circuit::Closed { failures } if failures + 1 < FAILURE_THRESHOLD => {
+                        Circuit::Closed {
+                            failures: failures + 1,
+                        }
+                    }
+                    _ => {
+                        warn!(
+                            "Opening the circuit to the Python server for {:?}: {}",
+                            OPEN_FOR, error
+                        );
+                        Circuit::Open {
+                            until: Instant::now() + OPEN_FOR,
+                        }
+                    }
+                };
+                breaker.last_error = Some(error);
+            }
+        }
+    }
+
+    fn lock_breaker(&self) -> std::sync::MutexGuard<'_, BreakerState> {
+        // the state is always left consistent, a panic while holding the lock can't corrupt it
+        self.breaker
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn circuit_opens_after_repeated_failures() {
+        let client = SidecarClient::new(None).expect("failed to build client");
+
+        for _ in 0..FAILURE_THRESHOLD - 1 {
+            client.record(Err("connection refused".into()));
+            assert!(client.admit("/ask").is_ok());
+        }
+        client.record(Err("connection refused".into()));
+
+        let health = client.health();
+        assert_eq!(health.circuit, CircuitState::Open);
+        assert!(!health.available);
+        assert!(client.admit("/ask").is_err());
+
+        client.record(Ok(()));
+        assert_eq!(client.health().circuit, CircuitState::Closed);
+        assert!(client.admit("/ask").is_ok());
+    }
+
+    #[test]
+    fn abandoned_probes_are_replaced() {
+        let client = SidecarClient::new(None).expect("failed to build client");
+        client.lock_breaker().circuit = Circuit::Open {
+            until: Instant::now(),
+        };
+
+        // the probe is let through, requests behind it fail fast
+        assert!(client.admit("/ask").is_ok());
+        assert_eq!(client.health().circuit, CircuitState::HalfOpen);
+        assert!(client.admit("/ask").is_err());
+
+        // the probe was dropped before recording its outcome and its deadline passed
+        client.lock_breaker().circuit = Circuit::HalfOpen {
+            until: Instant::now(),
+        };
+        assert!(client.admit("/ask").is_ok());
+        assert!(client.admit("/ask").is_err());
+
+        client.record(Ok(()));
+        assert_eq!(client.health().circuit, CircuitState::Closed);
+    }
+}
@@ -0,0 +1,7 @@
+mod client;
+pub mod protocol;
+mod supervisor;
+
+pub use client::{BackendHealth, SidecarClient};
+pub use protocol::*;
+pub use supervisor::SidecarSupervisor;
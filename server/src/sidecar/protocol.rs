@@ -0,0 +1,229 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Version of the protocol spoken by this node. The Python server reports its own on `/version`.
+/// Bump it on any change to the messages below that is not backwards compatible, along with the
+/// version in `python-server/yerba/protocol.py`, then regenerate
+/// `python-server/yerba/protocol.schema.json` with
+/// `UPDATE_PROTOCOL_SCHEMA=1 cargo test protocol_schema_is_up_to_date`.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct VersionResponse {
+    pub protocol_version: u32,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct LearnRequest {
+    pub vector_db_path: String,
+    pub file_path: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct LearnResponse {
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct UnlearnRequest {
+    pub vector_db_path: String,
+    pub file_path: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct UnlearnResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    pub removed: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ExtractRequest {
+    pub file_path: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ExtractedChunk {
+    pub text: String,
+    /// 1-based page number
+    pub page: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct ExtractResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    pub chunks: Option<Vec<ExtractedChunk>>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct AskRequest {
+    pub vector_db_path: String,
+    pub question: String,
+    /// JSON encoded list of `{ "HUMAN": ..., "AI": ... }` turns
+    pub chat_history: String,
+    /// chunks retrieved by the node. When set the Python server answers from these instead of its own store.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub context: Option<Vec<AskContext>>,
+    /// limits retrieval from the Python server's store to documents loaded from these paths
+    #[serde(default)]
+    pub source_paths: Option<Vec<String>>,
+    /// the space's answer style, replaces the Python server's default answer prompt
+    #[serde(default)]
+    pub instructions: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct AskContext {
+    pub text: String,
+    pub file_id: Uuid,
+    /// 1-based page number
+    pub page: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct AskResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    pub result: Option<String>,
+    #[serde(default)]
+    pub sources: Vec<AskSource>,
+}
+
+/// A chunk the answer was generated from.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct AskSource {
+    /// set when the chunk came from the native index
+    pub file_id: Option<Uuid>,
+    /// absolute path the chunk was loaded from, set by the Chroma store
+    pub source: Option<String>,
+    /// 1-based page number
+    pub page: Option<u32>,
+    pub snippet: String,
+    pub score: Option<f32>,
+}
+
+/// Data of the `token` events of `/ask/stream`.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct AskStreamToken {
+    pub text: String,
+}
+
+/// Data of the `done` event ending `/ask/stream`.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct AskStreamDone {
+    pub result: String,
+    #[serde(default)]
+    pub sources: Vec<AskSource>,
+}
+
+/// Data of the `error` event ending `/ask/stream`.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct AskStreamError {
+    pub error: String,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct CompleteMessage {
+    /// "system", "user" or "assistant"
+    pub role: String,
+    pub content: String,
+}
+
+/// Asks the Python server's chat model for a completion, without any retrieval.
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct CompleteRequest {
+    pub messages: Vec<CompleteMessage>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Clone, Debug)]
+pub struct CompleteResponse {
+    pub success: bool,
+    pub error: Option<String>,
+    pub text: Option<String>,
+}
+
+/// SidecarResponse is implemented by every response carrying a `success` flag.
+pub trait SidecarResponse {
+    fn success(&self) -> bool;
+    fn error(&self) -> Option<&str>;
+}
+
+macro_rules! impl_sidecar_response {
+    ($($response:ty),*) => {
+        $(
+            impl SidecarResponse for $response {
+                fn success(&self) -> bool {
+                    self.success
+                }
+
+                fn error(&self) -> Option<&str> {
+                    self.error.as_deref()
+                }
+            }
+        )*
+    };
+}
+
+impl_sidecar_response!(
+    LearnResponse,
+    UnlearnResponse,
+    ExtractResponse,
+    AskResponse,
+    CompleteResponse
+);
+
+/// Every message of the protocol, by endpoint. Only used to generate the JSON schema.
+#[derive(JsonSchema)]
+#[allow(dead_code)]
+pub struct Protocol {
+    version: VersionResponse,
+    learn_request: LearnRequest,
+    learn_response: LearnResponse,
+    unlearn_request: UnlearnRequest,
+    unlearn_response: UnlearnResponse,
+    extract_request: ExtractRequest,
+    extract_response: ExtractResponse,
+    ask_request: AskRequest,
+    ask_response: AskResponse,
+    ask_stream_token: AskStreamToken,
+    ask_stream_done: AskStreamDone,
+    ask_stream_error: AskStreamError,
+    complete_request: CompleteRequest,
+    complete_response: CompleteResponse,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// protocol_schema_is_up_to_date fails when the types changed without regenerating the schema the
+    /// Python server is checked against. Set `UPDATE_PROTOCOL_SCHEMA=1` to rewrite it.
+    #[test]
+    fn protocol_schema_is_up_to_date() {
+        let mut schema = schemars::schema_for!(Protocol);
+        schema.schema.extensions.insert(
+            "x-protocol-version".to_string(),
+            serde_json::json!(PROTOCOL_VERSION),
+        );
+        let schema =
+            serde_json::to_string_pretty(&schema).expect("Failed to serialize schema") + "\n";
+
+        let path = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+            .join("python-server/yerba/protocol.schema.json");
+        if std::env::var("UPDATE_PROTOCOL_SCHEMA").as_deref() == Ok("1") {
+            std::fs::write(path, schema).expect("Failed to write protocol schema");
+            return;
+        }
+
+        let current = std::fs::read_to_string(&path).expect("Failed to read protocol schema");
+        assert_eq!(
+            current,
+            schema,
+            "{} is out of date, regenerate it with UPDATE_PROTOCOL_SCHEMA=1",
+            path.display()
+        );
+    }
+}
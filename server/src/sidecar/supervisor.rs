@@ -0,0 +1,231 @@
+use anyhow::{Context, Result};
+use std::{
+    env,
+    path::PathBuf,
+    process::Stdio,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, BufReader},
+    process::{Child, Command},
+    sync::{watch, Mutex},
+    time::sleep,
+};
+use tracing::{debug, error, info, warn};
+
+use super::{SidecarClient, PROTOCOL_VERSION};
+
+/// How long a freshly spawned server gets to answer its first health check.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(120);
+const STARTUP_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+/// Consecutive failed health checks after which a running server is restarted.
+const MAX_FAILED_CHECKS: u32 = 3;
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A server that stayed up this long is considered stable, its next crash restarts it right away.
+const STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// SidecarSupervisor runs the Python server as a child process of the node: it spawns it, waits
+/// for it to answer `/version`, forwards its output to tracing and restarts it with exponential
+/// backoff when it exits or stops answering.
+pub struct SidecarSupervisor {
+    command: String,
+    dir: Option<PathBuf>,
+    health_tx: watch::Sender<bool>,
+    child: Mutex<Option<Child>>,
+    shutdown_tx: watch::Sender<bool>,
+}
+
+enum Exit {
+    Restart,
+    Shutdown,
+}
+
+impl SidecarSupervisor {
+    /// from_env returns a supervisor when `PYTHON_SERVER_CMD` is set, e.g. `python -m yerba.main`.
+    /// `PYTHON_SERVER_DIR` sets the directory it runs in.
+    pub fn from_env() -> Option<Arc<Self>> {
+        let command = env::var("PYTHON_SERVER_CMD")
+            .ok()
+            .filter(|c| !c.trim().is_empty())?;
+
+        Some(Arc::new(Self {
+            command,
+            dir: env::var("PYTHON_SERVER_DIR").ok().map(PathBuf::from),
+            health_tx: watch::channel(false).0,
+            child: Mutex::new(None),
+            shutdown_tx: watch::channel(false).0,
+        }))
+    }
+
+    /// health follows whether the server is currently up and answering.
+    pub fn health(&self) -> watch::Receiver<bool> {
+        self.health_tx.subscribe()
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        *self.health_tx.borrow()
+    }
+
+    /// start spawns the server and keeps it running in the background until [`Self::shutdown`].
+    pub fn start(self: Arc<Self>, client: Arc<SidecarClient>) {
+        tokio::spawn(async move {
+            let mut backoff = MIN_BACKOFF;
+
+            loop {
+                let started = Instant::now();
+                let exit = match self.spawn().await {
+                    Ok(()) => self.watch(&client).await,
+                    Err(e) => {
+                        error!("Failed to start the Python server: {:?}", e);
+                        Exit::Restart
+                    }
+                };
+                self.health_tx.send_replace(false);
+                self.kill().await;
+
+                if matches!(exit, Exit::Shutdown) || *self.shutdown_tx.borrow() {
+                    break;
+                }
+
+                if started.elapsed() > STABLE_AFTER {
+                    backoff = MIN_BACKOFF;
+                }
+                warn!("Restarting the Python server in {:?}", backoff);
+                let mut shutdown_rx = self.shutdown_tx.subscribe();
+                tokio::select! {
+                    _ = sleep(backoff) => {}
+                    _ = shutdown_rx.changed() => break,
+                }
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+
+            info!("Python server supervisor stopped");
+        });
+    }
+
+    /// shutdown stops the server and does not restart it.
+    pub async fn shutdown(&self) {
+        self.shutdown_tx.send_replace(true);
+        self.health_tx.send_replace(false);
+        self.kill().await;
+    }
+
+    async fn spawn(&self) -> Result<()> {
+        let mut parts = self.command.split_whitespace();
+        let program = parts.next().context("PYTHON_SERVER_CMD is empty")?;
+
+        let mut command = Command::new(program);
+        command
+            .args(parts)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        if let Some(dir) = &self.dir {
+            command.current_dir(dir);
+        }
+
+        info!("Starting the Python server: {}", self.command);
+        let mut child = command
+            .spawn()
+            .with_context(|| format!("Failed to spawn {:?}", self.command))?;
+
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(forward_logs(stdout, false));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(forward_logs(stderr, true));
+        }
+
+        *self.child.lock().await = Some(child);
+        Ok(())
+    }
+
+    /// watch waits for the server to become healthy, then health checks it until it exits or stops answering.
+    async fn watch(&self, client: &SidecarClient) -> Exit {
+        let mut shutdown_rx = self.shutdown_tx.subscribe();
+        let started = Instant::now();
+        let mut failed_checks = 0;
+
+        loop {
+            let interval = if self.is_healthy() {
+                HEALTH_CHECK_INTERVAL
+            } else {
+                STARTUP_CHECK_INTERVAL
+            };
+            tokio::select! {
+                _ = sleep(interval) => {}
+                _ = shutdown_rx.changed() => return Exit::Shutdown,
+            }
+
+            if let Some(status) = self.try_wait().await {
+                error!("Python server exited: {}", status);
+                return Exit::Restart;
+            }
+
+            match client.version().await {
+                Ok(v) if v == PROTOCOL_VERSION => {
+                    if !self.is_healthy() {
+                        info!("Python server is healthy after {:?}", started.elapsed());
+                    }
+                    failed_checks = 0;
+                    self.health_tx.send_replace(true);
+                }
+                Ok(v) => {
+                    error!(
+                        "Python server speaks protocol version {}, this node needs {}",
+                        v, PROTOCOL_VERSION
+                    );
+                    return Exit::Restart;
+                }
+                Err(e) if self.is_healthy() => {
+                    failed_checks += 1;
+                    warn!(
+                        "Python server health check failed ({}/{}): {:?}",
+                        failed_checks, MAX_FAILED_CHECKS, e
+                    );
+                    if failed_checks >= MAX_FAILED_CHECKS {
+                        return Exit::Restart;
+                    }
+                }
+                Err(e) => {
+                    debug!("Python server is not up yet: {:?}", e);
+                    if started.elapsed() > STARTUP_TIMEOUT {
+                        error!(
+                            "Python server did not become healthy within {:?}",
+                            STARTUP_TIMEOUT
+                        );
+                        return Exit::Restart;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn try_wait(&self) -> Option<std::process::ExitStatus> {
+        let mut child = self.child.lock().await;
+        child.as_mut()?.try_wait().ok().flatten()
+    }
+
+    async fn kill(&self) {
+        if let Some(mut child) = self.child.lock().await.take() {
+            if let Err(e) = child.kill().await {
+                debug!("Failed to kill the Python server: {:?}", e);
+            }
+        }
+    }
+}
+
+async fn forward_logs(output: impl AsyncRead + Unpin, stderr: bool) {
+    let mut lines = BufReader::new(output).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        // uvicorn and langchain log everything to stderr, so it is not a sign of an error
+        if stderr {
+            info!(target: "sidecar", "{}", line);
+        } else {
+            debug!(target: "sidecar", "{}", line);
+        }
+    }
+}
@@ -0,0 +1,282 @@
+use anyhow::{Context, Result};
+use chrono::Utc;
+use custom_prisma::prisma::{conversation, message, SortOrder};
+use std::collections::{HashMap, HashSet};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{api::message_with_tasks_and_peer, invalidate_query, utils::u2b};
+
+use super::Space;
+
+/// Thread is the selected branch of a conversation.
+#[derive(Debug, Default)]
+pub(crate) struct Thread {
+    /// oldest first
+    pub messages: Vec<message_with_tasks_and_peer::Data>,
+    /// ids of all alternatives of a message on the branch, including itself, for messages that have any
+    pub alternatives: HashMap<String, Vec<String>>,
+}
+
+impl Space {
+    /// thread returns the messages on the selected branch of the conversation.
+    pub(crate) async fn thread(&self, conversation: &conversation::Data) -> Result<Thread> {
+        let Some(head) = &conversation.head_id else {
+            return Ok(Thread::default());
+        };
+
+        let messages = self
+            .db
+            .message()
+            .find_many(vec![message::conversation_id::equals(Some(
+                conversation.id.clone(),
+            ))])
+            .order_by(message::date_created::order(SortOrder::Asc))
+            .include(message_with_tasks_and_peer::include())
+            .exec()
+            .await?;
+
+        let parents = messages
+            .iter()
+            .map(|message| (message.id.clone(), message.parent_id.clone()))
+            .collect::<HashMap<_, _>>();
+        let path = path_to(&parents, head);
+
+        let mut alternatives = HashMap::new();
+        for id in &path {
+            let siblings = messages
+                .iter()
+                .filter(|message| message.parent_id == parents[id])
+                .map(|message| message.id_str.clone())
+                .collect::<Vec<_>>();
+            if siblings.len() > 1 {
+                let message = messages.iter().find(|message| &message.id == id);
+                if let Some(message) = message {
+                    alternatives.insert(message.id_str.clone(), siblings);
+                }
+            }
+        }
+
+        let mut messages = messages
+            .into_iter()
+            .map(|message| (message.id.clone(), message))
+            .collect::<HashMap<_, _>>();
+
+        Ok(Thread {
+            messages: path.iter().filter_map(|id| messages.remove(id)).collect(),
+            alternatives,
+        })
+    }
+
+    /// select_message switches the conversation to the branch of the message, down to the most recent
+    /// message on it. Answers on the branch become the selected answers of their questions.
+    pub(crate) async fn select_message(&self, message_id: Uuid) -> Result<conversation::Data> {
+        let message = self
+            .db
+            .message()
+            .find_first(vec![
+                message::id::equals(u2b(message_id)),
+                message::space_id::equals(u2b(self.id)),
+            ])
+            .exec()
+            .await?
+            .with_context(|| format!("Message {} not found", message_id))?;
+        let conversation_id = message
+            .conversation_id
+            .context("The message is not part of a conversation")?;
+
+        let messages = self
+            .db
+            .message()
+            .find_many(vec![message::conversation_id::equals(Some(
+                conversation_id.clone(),
+            ))])
+            .order_by(message::date_created::order(SortOrder::Asc))
+            .exec()
+            .await?;
+
+        let mut children: HashMap<Vec<u8>, Vec<Vec<u8>>> = HashMap::new();
+        for message in &messages {
+            if let Some(parent_id) = &message.parent_id {
+                children
+                    .entry(parent_id.clone())
+                    .or_default()
+                    .push(message.id.clone());
+            }
+        }
+        let leaf = latest_leaf(&children, &message.id);
+
+        let parents = messages
+            .iter()
+            .map(|message| (message.id.clone(), message.parent_id.clone()))
+            .collect::<HashMap<_, _>>();
+        for id in path_to(&parents, &leaf) {
+            let Some(answer) = messages.iter().find(|message| message.id == id) else {
+                continue;
+            };
+            let Some(question) = answer
+                .parent_id
+                .as_ref()
+                .and_then(|parent_id| messages.iter().find(|message| &message.id == parent_id))
+            else {
+                continue;
+            };
+            if answer.is_user_message || question.response_message_id.as_ref() == Some(&answer.id) {
+                continue;
+            }
+
+            self.db
+                .message()
+                .update(
+                    message::id::equals(question.id.clone()),
+                    vec![message::response_message::connect(message::id::equals(
+                        answer.id.clone(),
+                    ))],
+                )
+                .exec()
+                .await?;
+        }
+
+        let conversation = self.set_conversation_head(conversation_id, leaf).await?;
+        invalidate_query!(self, "messages.list");
+
+        Ok(conversation)
+    }
+
+    /// set_conversation_head makes the message the last one of the selected branch.
+    pub(crate) async fn set_conversation_head(
+        &self,
+        conversation_id: Vec<u8>,
+        head_id: Vec<u8>,
+    ) -> Result<conversation::Data> {
+        let conversation = self
+            .db
+            .conversation()
+            .update(
+                conversation::id::equals(conversation_id),
+                vec![
+                    conversation::head_id::set(Some(head_id)),
+                    conversation::date_modified::set(Utc::now().into()),
+                ],
+            )
+            .exec()
+            .await?;
+
+        invalidate_query!(self, "conversations.list");
+
+        Ok(conversation)
+    }
+
+    /// link_legacy_messages chains the messages of a conversation that was started before messages had
+    /// parents in the order they were sent.
+    pub(crate) async fn link_legacy_messages(
+        &self,
+        conversation: conversation::Data,
+    ) -> Result<conversation::Data> {
+        if conversation.head_id.is_some() {
+            return Ok(conversation);
+        }
+
+        let messages = self
+            .db
+            .message()
+            .find_many(vec![message::conversation_id::equals(Some(
+                conversation.id.clone(),
+            ))])
+            .order_by(message::date_created::order(SortOrder::Asc))
+            .exec()
+            .await?;
+        let Some(last) = messages.last() else {
+            return Ok(conversation);
+        };
+
+        let updates = messages
+            .windows(2)
+            .filter(|pair| pair[1].parent_id.is_none())
+            .map(|pair| {
+                self.db.message().update(
+                    message::id::equals(pair[1].id.clone()),
+                    vec![message::parent::connect(message::id::equals(
+                        pair[0].id.clone(),
+                    ))],
+                )
+            })
+            .collect::<Vec<_>>();
+        debug!(
+            "Linking {} messages of conversation {}",
+            updates.len(),
+            conversation.id_str
+        );
+        self.db._batch(updates).await?;
+
+        self.set_conversation_head(conversation.id, last.id.clone())
+            .await
+    }
+}
+
+/// path_to walks from `leaf` up through the parents and returns the ids on the way, root first.
+pub(crate) fn path_to(parents: &HashMap<Vec<u8>, Option<Vec<u8>>>, leaf: &[u8]) -> Vec<Vec<u8>> {
+    let mut path = vec![];
+    let mut seen = HashSet::new();
+    let mut current = Some(leaf.to_vec());
+
+    while let Some(id) = current {
+        // a missing message ends the path, a cycle would be a bug but must not hang a reply
+        if !parents.contains_key(&id) || !seen.insert(id.clone()) {
+            break;
+        }
+        current = parents[&id].clone();
+        path.push(id);
+    }
+
+    path.reverse();
+    path
+}
+
+/// latest_leaf follows the most recent child from `id` down to the end of its branch. `children`
+/// holds the children of every message, oldest first.
+pub(crate) fn latest_leaf(children: &HashMap<Vec<u8>, Vec<Vec<u8>>>, id: &[u8]) -> Vec<u8> {
+    let mut seen = HashSet::new();
+    let mut current = id.to_vec();
+
+    while let Some(child) = children.get(&current).and_then(|children| children.last()) {
+        if !seen.insert(current.clone()) {
+            break;
+        }
+        current = child.clone();
+    }
+
+    current
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(n: u8) -> Vec<u8> {
+        vec![n]
+    }
+
+    #[test]
+    fn follows_the_selected_branch() {
+        // 1 -> 2 -> 3, with 4 answering 1 again and 5 following up on 4
+        let parents = HashMap::from([
+            (id(1), None),
+            (id(2), Some(id(1))),
+            (id(3), Some(id(2))),
+            (id(4), Some(id(1))),
+            (id(5), Some(id(4))),
+        ]);
+        assert_eq!(path_to(&parents, &id(3)), vec![id(1), id(2), id(3)]);
+        assert_eq!(path_to(&parents, &id(5)), vec![id(1), id(4), id(5)]);
+
+        let children = HashMap::from([
+            (id(1), vec![id(2), id(4)]),
+            (id(2), vec![id(3)]),
+            (id(4), vec![id(5)]),
+        ]);
+        assert_eq!(latest_leaf(&children, &id(1)), id(5));
+        assert_eq!(latest_leaf(&children, &id(2)), id(3));
+        assert_eq!(latest_leaf(&children, &id(3)), id(3));
+    }
+}
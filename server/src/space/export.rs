@@ -0,0 +1,418 @@
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, Utc};
+use custom_prisma::prisma::file;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use super::Space;
+
+/// The formats a conversation can be exported to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    /// a self-contained page that prints well, to save as PDF from the browser
+    Html,
+}
+
+impl ExportFormat {
+    /// parse accepts the names of the formats and their file extensions.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.to_lowercase().as_str() {
+            "markdown" | "md" => Some(ExportFormat::Markdown),
+            "json" => Some(ExportFormat::Json),
+            "html" | "htm" => Some(ExportFormat::Html),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "md",
+            ExportFormat::Json => "json",
+            ExportFormat::Html => "html",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ExportFormat::Markdown => "text/markdown; charset=utf-8",
+            ExportFormat::Json => "application/json",
+            ExportFormat::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+/// A rendered export, ready to be saved.
+#[derive(Debug, Clone, Serialize, Type)]
+pub struct ExportedFile {
+    pub file_name: String,
+    pub mime_type: String,
+    pub content: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportRole {
+    User,
+    Assistant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportStatus {
+    Sent,
+    Generating,
+    Answered,
+    Failed,
+}
+
+impl ExportStatus {
+    fn from_message(is_user_message: bool, response_status: i32) -> Self {
+        match (is_user_message, response_status) {
+            (true, _) => ExportStatus::Sent,
+            (false, 1) => ExportStatus::Generating,
+            (false, 3) => ExportStatus::Failed,
+            (false, _) => ExportStatus::Answered,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Citation {
+    /// 1-based, as it is referred to in the answer
+    pub number: i32,
+    pub file_name: Option<String>,
+    pub page: Option<i32>,
+    pub snippet: String,
+    pub link: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportedMessage {
+    pub id: String,
+    pub role: ExportRole,
+    pub status: ExportStatus,
+    pub text: String,
+    pub date_created: DateTime<FixedOffset>,
+    pub date_finalized: DateTime<FixedOffset>,
+    pub citations: Vec<Citation>,
+}
+
+/// ConversationExport is the selected branch of a conversation as it is exported, oldest message first.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationExport {
+    pub space: String,
+    pub conversation: String,
+    pub exported_at: DateTime<Utc>,
+    pub messages: Vec<ExportedMessage>,
+}
+
+impl ConversationExport {
+    pub fn render(&self, format: ExportFormat) -> Result<ExportedFile> {
+        let content = match format {
+            ExportFormat::Markdown => self.to_markdown(),
+            ExportFormat::Json => serde_json::to_string_pretty(self)?,
+            ExportFormat::Html => self.to_html(),
+        };
+
+        Ok(ExportedFile {
+            file_name: format!("{}.{}", file_stem(&self.conversation), format.extension()),
+            mime_type: format.mime_type().to_string(),
+            content,
+        })
+    }
+
+    fn to_markdown(&self) -> String {
+        let mut out = format!(
+            "# {}\n\n_{} · exported {}_\n",
+            self.conversation,
+            self.space,
+            timestamp(self.exported_at)
+        );
+
+        for message in &self.messages {
+            out += &format!(
+                "\n---\n\n**{}** · {}{}\n\n{}\n",
+                role_label(message.role),
+                timestamp(message.date_created),
+                status_note(message.status)
+                    .map(|note| format!(" · _{note}_"))
+                    .unwrap_or_default(),
+                message.text.trim()
+            );
+
+            if !message.citations.is_empty() {
+                out += "\n**Sources**\n\n";
+                for citation in &message.citations {
+                    out += &format!(
+                        "{}. {}: {}\n",
+                        citation.number,
+                        citation_label(citation),
+                        quote(&citation.snippet)
+                    );
+                }
+            }
+        }
+
+        out
+    }
+
+    fn to_html(&self) -> String {
+        let mut body = String::new();
+        for message in &self.messages {
+            let role = match message.role {
+                ExportRole::User => "user",
+                ExportRole::Assistant => "assistant",
+            };
+            body += &format!(
+                "<article class=\"message {role}\">\n<header><strong>{}</strong> <time datetime=\"{}\">{}</time>{}</header>\n<div class=\"text\">{}</div>\n",
+                role_label(message.role),
+                message.date_created.to_rfc3339(),
+                timestamp(message.date_created),
+                status_note(message.status)
+                    .map(|note| format!(" <span class=\"status\">{note}</span>"))
+                    .unwrap_or_default(),
+                escape_html(message.text.trim())
+            );
+
+            if !message.citations.is_empty() {
+                body += "<ol class=\"sources\">\n";
+                for citation in &message.citations {
+                    body += &format!(
+                        "<li value=\"{}\"><span class=\"source\">{}</span> <q>{}</q></li>\n",
+                        citation.number,
+                        escape_html(&citation_label(citation)),
+                        escape_html(&one_line(&citation.snippet))
+                    );
+                }
+                body += "</ol>\n";
+            }
+            body += "</article>\n";
+        }
+
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n<style>{HTML_STYLE}</style>\n</head>\n<body>\n<h1>{title}</h1>\n<p class=\"meta\">{} · exported {}</p>\n{body}</body>\n</html>\n",
+            escape_html(&self.space),
+            timestamp(self.exported_at),
+            title = escape_html(&self.conversation),
+        )
+    }
+}
+
+const HTML_STYLE: &str = "
+body { font-family: -apple-system, 'Segoe UI', Helvetica, Arial, sans-serif; max-width: 48rem; margin: 2rem auto; padding: 0 1rem; color: #1f2328; line-height: 1.5; }
+.meta, header, .sources { color: #59636e; font-size: 0.875rem; }
+.message { border-top: 1px solid #d1d9e0; padding: 1rem 0; break-inside: avoid; }
+.message.user .text { font-weight: 600; }
+.text { white-space: pre-wrap; margin-top: 0.5rem; }
+.status { color: #cf222e; }
+.source { font-weight: 600; }
+@media print { body { margin: 0; max-width: none; } a { color: inherit; } }
+";
+
+impl Space {
+    /// export_conversation collects the selected branch of the conversation, with the sources of every
+    /// answer. Without an id, the most recently used conversation is exported.
+    pub(crate) async fn export_conversation(
+        &self,
+        conversation_id: Option<Uuid>,
+    ) -> Result<ConversationExport> {
+        let conversation = self.resolve_conversation(conversation_id).await?;
+        let thread = self.thread(&conversation).await?;
+
+        let file_ids = thread
+            .messages
+            .iter()
+            .flat_map(|message| message.sources.iter())
+            .filter_map(|source| source.file_id.clone())
+            .collect::<Vec<_>>();
+        let file_names = self
+            .db
+            .file()
+            .find_many(vec![file::id::in_vec(file_ids)])
+            .exec()
+            .await?
+            .into_iter()
+            .map(|file| (file.id, file.name))
+            .collect::<HashMap<_, _>>();
+
+        let messages = thread
+            .messages
+            .into_iter()
+            .map(|message| {
+                let mut sources = message.sources;
+                sources.sort_by_key(|source| source.rank);
+                ExportedMessage {
+                    id: message.id_str,
+                    role: if message.is_user_message {
+                        ExportRole::User
+                    } else {
+                        ExportRole::Assistant
+                    },
+                    status: ExportStatus::from_message(
+                        message.is_user_message,
+                        message.response_status,
+                    ),
+                    text: message.text,
+                    date_created: message.date_created,
+                    date_finalized: message.date_finalized,
+                    citations: sources
+                        .into_iter()
+                        .map(|source| Citation {
+                            number: source.rank + 1,
+                            file_name: source.file_id.and_then(|id| file_names.get(&id).cloned()),
+                            page: source.page,
+                            snippet: source.snippet,
+                            link: source.link,
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        Ok(ConversationExport {
+            space: self.meta.name.clone(),
+            conversation: conversation.name,
+            exported_at: Utc::now(),
+            messages,
+        })
+    }
+}
+
+fn role_label(role: ExportRole) -> &'static str {
+    match role {
+        ExportRole::User => "You",
+        ExportRole::Assistant => "Assistant",
+    }
+}
+
+fn status_note(status: ExportStatus) -> Option<&'static str> {
+    match status {
+        ExportStatus::Generating => Some("still being generated"),
+        ExportStatus::Failed => Some("failed"),
+        ExportStatus::Sent | ExportStatus::Answered => None,
+    }
+}
+
+fn citation_label(citation: &Citation) -> String {
+    let name = citation.file_name.as_deref().unwrap_or("Deleted file");
+    match citation.page {
+        Some(page) => format!("{name}, page {page}"),
+        None => name.to_string(),
+    }
+}
+
+fn timestamp<Tz: chrono::TimeZone>(date: DateTime<Tz>) -> String {
+    date.with_timezone(&Utc)
+        .format("%Y-%m-%d %H:%M UTC")
+        .to_string()
+}
+
+fn one_line(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", one_line(text))
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// file_stem turns the conversation name into something that can be used as a file name anywhere.
+fn file_stem(name: &str) -> String {
+    let stem = name
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-");
+    if stem.is_empty() {
+        "conversation".to_string()
+    } else {
+        stem
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn conversation() -> ConversationExport {
+        let date = DateTime::parse_from_rfc3339("2023-06-01T10:30:00+02:00").expect("valid date");
+        ConversationExport {
+            space: "Biology".to_string(),
+            conversation: "Cells & <membranes>".to_string(),
+            exported_at: date.with_timezone(&Utc),
+            messages: vec![
+                ExportedMessage {
+                    id: "1".to_string(),
+                    role: ExportRole::User,
+                    status: ExportStatus::Sent,
+                    text: "What is osmosis?".to_string(),
+                    date_created: date,
+                    date_finalized: date,
+                    citations: vec![],
+                },
+                ExportedMessage {
+                    id: "2".to_string(),
+                    role: ExportRole::Assistant,
+                    status: ExportStatus::Answered,
+                    text: "Diffusion of water [1].".to_string(),
+                    date_created: date,
+                    date_finalized: date,
+                    citations: vec![Citation {
+                        number: 1,
+                        file_name: Some("biology.pdf".to_string()),
+                        page: Some(3),
+                        snippet: "Osmosis is the\ndiffusion of water".to_string(),
+                        link: None,
+                    }],
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn renders_markdown_with_citations() {
+        let file = conversation()
+            .render(ExportFormat::Markdown)
+            .expect("failed to render");
+
+        assert_eq!(file.file_name, "Cells-membranes.md");
+        assert!(file.content.starts_with("# Cells & <membranes>\n"));
+        assert!(file
+            .content
+            .contains("**You** · 2023-06-01 08:30 UTC\n\nWhat is osmosis?"));
+        assert!(file
+            .content
+            .contains("1. biology.pdf, page 3: \"Osmosis is the diffusion of water\""));
+    }
+
+    #[test]
+    fn escapes_html() {
+        let file = conversation()
+            .render(ExportFormat::Html)
+            .expect("failed to render");
+
+        assert!(file
+            .content
+            .contains("<title>Cells &amp; &lt;membranes&gt;</title>"));
+        assert!(!file.content.contains("<membranes>"));
+    }
+}
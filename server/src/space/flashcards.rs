@@ -0,0 +1,344 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use custom_prisma::prisma::{flashcard, flashcard_review, user, SortOrder};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::utils::{u2b, u2s};
+
+use super::Space;
+
+/// Best grade of a review: 0 means the answer was forgotten, 3 and up that it was remembered.
+pub const MAX_GRADE: u8 = 5;
+/// Lowest grade that counts as remembered.
+const PASSING_GRADE: u8 = 3;
+const INITIAL_EASE: f64 = 2.5;
+const MIN_EASE: f64 = 1.3;
+
+/// Schedule is the SM-2 state of a card for one user.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Schedule {
+    /// how much the interval grows after a remembered review
+    pub ease: f64,
+    /// days until the next review
+    pub interval: i32,
+    /// remembered reviews in a row
+    pub repetitions: i32,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Self {
+            ease: INITIAL_EASE,
+            interval: 0,
+            repetitions: 0,
+        }
+    }
+}
+
+impl Schedule {
+    /// review returns the schedule after a review with the grade, between 0 and [`MAX_GRADE`].
+    /// Forgotten cards start over and come back the next day.
+    pub fn review(self, grade: u8) -> Self {
+        let grade = grade.min(MAX_GRADE);
+        let (interval, repetitions) = if grade < PASSING_GRADE {
+            (1, 0)
+        } else {
+            let interval = match self.repetitions {
+                0 => 1,
+                1 => 6,
+                _ => (self.interval as f64 * self.ease).round() as i32,
+            };
+            (interval, self.repetitions + 1)
+        };
+
+        let missed = f64::from(MAX_GRADE - grade);
+        let ease = (self.ease + 0.1 - missed * (0.08 + missed * 0.02)).max(MIN_EASE);
+
+        Self {
+            ease,
+            interval,
+            repetitions,
+        }
+    }
+}
+
+/// FlashcardReviewState is when a user reviewed a flashcard last and when it is due again.
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct FlashcardReviewState {
+    pub ease: f64,
+    pub interval: i32,
+    pub repetitions: i32,
+    pub grade: i32,
+    pub date_reviewed: DateTime<FixedOffset>,
+    pub date_due: DateTime<FixedOffset>,
+}
+
+impl From<flashcard_review::Data> for FlashcardReviewState {
+    fn from(review: flashcard_review::Data) -> Self {
+        Self {
+            ease: review.ease,
+            interval: review.interval,
+            repetitions: review.repetitions,
+            grade: review.grade,
+            date_reviewed: review.date_reviewed,
+            date_due: review.date_due,
+        }
+    }
+}
+
+/// ScheduledFlashcard is a flashcard with the review state of the user, unset for new cards.
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct ScheduledFlashcard {
+    pub id: Uuid,
+    pub question: String,
+    pub answer: String,
+    pub page: Option<i32>,
+    pub file_id: Option<Uuid>,
+    pub conversation_id: Option<Uuid>,
+    pub date_created: DateTime<FixedOffset>,
+    pub review: Option<FlashcardReviewState>,
+}
+
+impl ScheduledFlashcard {
+    fn new(card: flashcard::Data, review: Option<flashcard_review::Data>) -> Result<Self> {
+        Ok(Self {
+            id: Uuid::from_slice(&card.id)?,
+            question: card.question,
+            answer: card.answer,
+            page: card.page,
+            file_id: card.file_id.as_deref().map(Uuid::from_slice).transpose()?,
+            conversation_id: card
+                .conversation_id
+                .as_deref()
+                .map(Uuid::from_slice)
+                .transpose()?,
+            date_created: card.date_created,
+            review: review.map(Into::into),
+        })
+    }
+
+    /// due cards were never reviewed or are due again.
+    fn is_due(&self, now: DateTime<FixedOffset>) -> bool {
+        match &self.review {
+            Some(review) => review.date_due <= now,
+            None => true,
+        }
+    }
+}
+
+/// Limits the flashcards that are listed.
+#[derive(Deserialize, Type, Debug, Clone, Default)]
+pub struct FlashcardFilter {
+    #[specta(optional)]
+    pub file_id: Option<Uuid>,
+    #[specta(optional)]
+    pub conversation_id: Option<Uuid>,
+}
+
+impl Space {
+    /// flashcards returns the flashcards of the space with the review state of the user, oldest first.
+    pub(crate) async fn flashcards(
+        &self,
+        user_id: Uuid,
+        filter: FlashcardFilter,
+    ) -> Result<Vec<ScheduledFlashcard>> {
+        let mut filters = vec![flashcard::space_id::equals(u2b(self.id))];
+        if let Some(file_id) = filter.file_id {
+            filters.push(flashcard::file_id::equals(Some(u2b(file_id))));
+        }
+        if let Some(conversation_id) = filter.conversation_id {
+            filters.push(flashcard::conversation_id::equals(Some(u2b(
+                conversation_id,
+            ))));
+        }
+        let cards = self
+            .db
+            .flashcard()
+            .find_many(filters)
+            .order_by(flashcard::date_created::order(SortOrder::Asc))
+            .exec()
+            .await?;
+
+        let mut reviews = self
+            .db
+            .flashcard_review()
+            .find_many(vec![
+                flashcard_review::user_id::equals(u2b(user_id)),
+                flashcard_review::flashcard_id::in_vec(
+                    cards.iter().map(|card| card.id.clone()).collect(),
+                ),
+            ])
+            .exec()
+            .await?
+            .into_iter()
+            .map(|review| (review.flashcard_id.clone(), review))
+            .collect::<HashMap<_, _>>();
+
+        cards
+            .into_iter()
+            .map(|card| {
+                let review = reviews.remove(&card.id);
+                ScheduledFlashcard::new(card, review)
+            })
+            .collect()
+    }
+
+    /// due_flashcards returns the flashcards the user should review now: the most overdue first, then
+    /// cards that were never reviewed.
+    pub(crate) async fn due_flashcards(
+        &self,
+        user_id: Uuid,
+        limit: usize,
+    ) -> Result<Vec<ScheduledFlashcard>> {
+        let now = Utc::now().into();
+        let mut due = self
+            .flashcards(user_id, FlashcardFilter::default())
+            .await?
+            .into_iter()
+            .filter(|card| card.is_due(now))
+            .collect::<Vec<_>>();
+        // new cards keep their order, after all reviewed ones
+        due.sort_by_key(|card| card.review.is_none());
+        let reviewed = due.iter().take_while(|card| card.review.is_some()).count();
+        due[..reviewed].sort_by_key(|card| card.review.as_ref().map(|review| review.date_due));
+        due.truncate(limit);
+
+        Ok(due)
+    }
+
+    /// grade_flashcard records a review of the flashcard and schedules the next one.
+    pub(crate) async fn grade_flashcard(
+        &self,
+        user_id: Uuid,
+        flashcard_id: Uuid,
+        grade: u8,
+    ) -> Result<ScheduledFlashcard> {
+        if grade > MAX_GRADE {
+            bail!("Grades go from 0 to {}", MAX_GRADE);
+        }
+
+        let card = self
+            .db
+            .flashcard()
+            .find_first(vec![
+                flashcard::id::equals(u2b(flashcard_id)),
+                flashcard::space_id::equals(u2b(self.id)),
+            ])
+            .exec()
+            .await?
+            .with_context(|| format!("Flashcard {} not found", flashcard_id))?;
+
+        let unique = || flashcard_review::flashcard_id_user_id(card.id.clone(), u2b(user_id));
+        let schedule = self
+            .db
+            .flashcard_review()
+            .find_unique(unique())
+            .exec()
+            .await?
+            .map_or_else(Schedule::default, |review| Schedule {
+                ease: review.ease,
+                interval: review.interval,
+                repetitions: review.repetitions,
+            })
+            .review(grade);
+
+        let now = Utc::now();
+        let date_due = now + Duration::days(schedule.interval.into());
+        let updates = || {
+            vec![
+                flashcard_review::ease::set(schedule.ease),
+                flashcard_review::interval::set(schedule.interval),
+                flashcard_review::repetitions::set(schedule.repetitions),
+                flashcard_review::grade::set(grade.into()),
+                flashcard_review::date_reviewed::set(now.into()),
+                flashcard_review::date_due::set(date_due.into()),
+            ]
+        };
+        let id = Uuid::new_v4();
+        let review = self
+            .db
+            .flashcard_review()
+            .upsert(
+                unique(),
+                flashcard_review::create(
+                    u2b(id),
+                    u2s(id),
+                    grade.into(),
+                    date_due.into(),
+                    flashcard::id::equals(card.id.clone()),
+                    user::id::equals(u2b(user_id)),
+                    updates(),
+                ),
+                updates(),
+            )
+            .exec()
+            .await?;
+
+        ScheduledFlashcard::new(card, Some(review))
+    }
+
+    /// delete_flashcard removes the flashcard and all reviews of it.
+    pub(crate) async fn delete_flashcard(&self, flashcard_id: Uuid) -> Result<()> {
+        let deleted = self
+            .db
+            .flashcard()
+            .delete_many(vec![
+                flashcard::id::equals(u2b(flashcard_id)),
+                flashcard::space_id::equals(u2b(self.id)),
+            ])
+            .exec()
+            .await?;
+        if deleted == 0 {
+            bail!("Flashcard {} not found", flashcard_id);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remembered_cards_come_back_later_and_later() {
+        let first = Schedule::default().review(4);
+        assert_eq!(first.interval, 1);
+        assert_eq!(first.repetitions, 1);
+
+        let second = first.review(4);
+        assert_eq!(second.interval, 6);
+
+        let third = second.review(4);
+        assert_eq!(third.interval, 15);
+        assert_eq!(third.repetitions, 3);
+        assert!((third.ease - INITIAL_EASE).abs() < 1e-9);
+    }
+
+    #[test]
+    fn forgotten_cards_start_over() {
+        let learned = Schedule::default().review(5).review(5).review(5);
+        let forgotten = learned.review(1);
+
+        assert_eq!(forgotten.interval, 1);
+        assert_eq!(forgotten.repetitions, 0);
+        assert!(forgotten.ease < learned.ease);
+    }
+
+    #[test]
+    fn ease_does_not_drop_below_the_minimum() {
+        let schedule = (0..10).fold(Schedule::default(), |schedule, _| schedule.review(0));
+
+        assert!((schedule.ease - MIN_EASE).abs() < 1e-9);
+    }
+
+    #[test]
+    fn perfect_recall_makes_cards_easier() {
+        let schedule = Schedule::default().review(5);
+
+        assert!((schedule.ease - 2.6).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,391 @@
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset};
+use custom_prisma::prisma::{
+    concept, concept_mention, concept_source, file, message, space as db_space,
+};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::{cmp::Reverse, collections::HashMap};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{
+    custom_uri::file_link,
+    invalidate_query,
+    tasks::extract_concepts::{concept_key, ExtractedConcept},
+    utils::{u2b, u2s},
+};
+
+use super::Space;
+
+/// A chunk of a file that explains a concept.
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct ConceptSourceEntry {
+    pub file_id: Uuid,
+    pub file_name: String,
+    pub chunk: i32,
+    pub page: Option<i32>,
+    pub snippet: String,
+    pub link: String,
+}
+
+/// ConceptEntry is a term of the glossary with the files it was found in.
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct ConceptEntry {
+    pub id: Uuid,
+    pub term: String,
+    pub definition: String,
+    pub sources: Vec<ConceptSourceEntry>,
+    pub date_modified: DateTime<FixedOffset>,
+}
+
+/// Limits the concepts that are listed.
+#[derive(Deserialize, Type, Debug, Clone, Default)]
+pub struct ConceptFilter {
+    /// only terms containing the text
+    #[specta(optional)]
+    pub query: Option<String>,
+    /// only terms found in the file
+    #[specta(optional)]
+    pub file_id: Option<Uuid>,
+}
+
+/// A concept mentioned in a text. Offsets are in UTF-16 code units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mention {
+    /// index of the mentioned key
+    pub concept: usize,
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Space {
+    /// store_concepts replaces the concepts found in the file. Terms that are already in the
+    /// glossary keep their definition and get the file as another source.
+    pub(crate) async fn store_concepts(
+        &self,
+        file_id: Uuid,
+        concepts: Vec<ExtractedConcept>,
+    ) -> Result<()> {
+        self.db
+            .concept_source()
+            .delete_many(vec![concept_source::file_id::equals(u2b(file_id))])
+            .exec()
+            .await?;
+
+        let mut ids = self
+            .db
+            .concept()
+            .find_many(vec![
+                concept::space_id::equals(u2b(self.id)),
+                concept::key::in_vec(concepts.iter().map(|c| concept_key(&c.term)).collect()),
+            ])
+            .exec()
+            .await?
+            .into_iter()
+            .map(|concept| (concept.key, concept.id))
+            .collect::<HashMap<_, _>>();
+
+        let mut creates = vec![];
+        for extracted in &concepts {
+            let key = concept_key(&extracted.term);
+            if ids.contains_key(&key) {
+                continue;
+            }
+            let id = Uuid::new_v4();
+            ids.insert(key.clone(), u2b(id));
+            creates.push(self.db.concept().create(
+                u2b(id),
+                u2s(id),
+                extracted.term.clone(),
+                extracted.definition.clone(),
+                key,
+                db_space::id::equals(u2b(self.id)),
+                vec![],
+            ));
+        }
+        debug!("Adding {} concepts to the glossary", creates.len());
+        self.db._batch(creates).await?;
+
+        let sources = concepts
+            .into_iter()
+            .filter_map(|extracted| {
+                let concept_id = ids.get(&concept_key(&extracted.term))?.clone();
+                let id = Uuid::new_v4();
+                Some(self.db.concept_source().create(
+                    u2b(id),
+                    u2s(id),
+                    extracted.chunk as i32,
+                    extracted.snippet,
+                    concept::id::equals(concept_id),
+                    file::id::equals(u2b(file_id)),
+                    vec![concept_source::page::set(
+                        extracted.page.map(|page| page as i32),
+                    )],
+                ))
+            })
+            .collect::<Vec<_>>();
+        self.db._batch(sources).await?;
+
+        self.prune_concepts().await
+    }
+
+    /// remove_file_concepts drops the file from the sources of the glossary, and the concepts that
+    /// were only found in it.
+    pub(crate) async fn remove_file_concepts(&self, file_id: Uuid) -> Result<()> {
+        self.db
+            .concept_source()
+            .delete_many(vec![concept_source::file_id::equals(u2b(file_id))])
+            .exec()
+            .await?;
+
+        self.prune_concepts().await?;
+        invalidate_query!(self, "concepts.list");
+
+        Ok(())
+    }
+
+    async fn prune_concepts(&self) -> Result<()> {
+        let pruned = self
+            .db
+            .concept()
+            .delete_many(vec![
+                concept::space_id::equals(u2b(self.id)),
+                concept::sources::none(vec![]),
+            ])
+            .exec()
+            .await?;
+        if pruned > 0 {
+            debug!("Removed {} concepts without sources", pruned);
+        }
+
+        Ok(())
+    }
+
+    /// concepts returns the glossary of the space, sorted by term.
+    pub(crate) async fn concepts(&self, filter: ConceptFilter) -> Result<Vec<ConceptEntry>> {
+        let mut filters = vec![concept::space_id::equals(u2b(self.id))];
+        if let Some(query) = filter.query.as_deref().map(concept_key) {
+            if !query.is_empty() {
+                filters.push(concept::key::contains(query));
+            }
+        }
+        if let Some(file_id) = filter.file_id {
+            filters.push(concept::sources::some(vec![
+                concept_source::file_id::equals(u2b(file_id)),
+            ]));
+        }
+        let concepts = self.db.concept().find_many(filters).exec().await?;
+
+        let sources = self
+            .db
+            .concept_source()
+            .find_many(vec![concept_source::concept_id::in_vec(
+                concepts.iter().map(|concept| concept.id.clone()).collect(),
+            )])
+            .exec()
+            .await?;
+        let file_names = self
+            .db
+            .file()
+            .find_many(vec![file::id::in_vec(
+                sources
+                    .iter()
+                    .map(|source| source.file_id.clone())
+                    .collect(),
+            )])
+            .exec()
+            .await?
+            .into_iter()
+            .map(|file| (file.id, file.name))
+            .collect::<HashMap<_, _>>();
+
+        let mut by_concept = HashMap::<Vec<u8>, Vec<ConceptSourceEntry>>::new();
+        for source in sources {
+            let file_id = Uuid::from_slice(&source.file_id)?;
+            by_concept
+                .entry(source.concept_id)
+                .or_default()
+                .push(ConceptSourceEntry {
+                    file_id,
+                    file_name: file_names.get(&source.file_id).cloned().unwrap_or_default(),
+                    chunk: source.chunk,
+                    page: source.page,
+                    snippet: source.snippet,
+                    link: file_link(self.id, file_id, source.page),
+                });
+        }
+
+        let mut entries = concepts
+            .into_iter()
+            .map(|concept| {
+                let mut sources = by_concept.remove(&concept.id).unwrap_or_default();
+                sources.sort_by(|a, b| (&a.file_name, a.chunk).cmp(&(&b.file_name, b.chunk)));
+                Ok(ConceptEntry {
+                    id: Uuid::from_slice(&concept.id)?,
+                    term: concept.term,
+                    definition: concept.definition,
+                    sources,
+                    date_modified: concept.date_modified,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        entries.sort_by_key(|entry| entry.term.to_lowercase());
+
+        Ok(entries)
+    }
+
+    /// link_concepts stores where the terms of the glossary are mentioned in the message.
+    pub(crate) async fn link_concepts(&self, message_id: Uuid, text: &str) -> Result<usize> {
+        let concepts = self
+            .db
+            .concept()
+            .find_many(vec![concept::space_id::equals(u2b(self.id))])
+            .exec()
+            .await?;
+        if concepts.is_empty() {
+            return Ok(0);
+        }
+
+        let keys = concepts
+            .iter()
+            .map(|concept| concept.key.as_str())
+            .collect::<Vec<_>>();
+        let mentions = find_mentions(text, &keys);
+
+        self.db
+            .concept_mention()
+            .delete_many(vec![concept_mention::message_id::equals(u2b(message_id))])
+            .exec()
+            .await?;
+        let creates = mentions
+            .iter()
+            .map(|mention| {
+                let id = Uuid::new_v4();
+                self.db.concept_mention().create(
+                    u2b(id),
+                    u2s(id),
+                    mention.start as i32,
+                    mention.end as i32,
+                    message::id::equals(u2b(message_id)),
+                    concept::id::equals(concepts[mention.concept].id.clone()),
+                    vec![],
+                )
+            })
+            .collect::<Vec<_>>();
+        self.db._batch(creates).await?;
+
+        Ok(mentions.len())
+    }
+}
+
+/// find_mentions finds the first mention of every key in the text, as a whole word and ignoring
+/// case. Longer keys win over keys they contain, so "support vector machine" is linked rather than
+/// "vector".
+pub fn find_mentions(text: &str, keys: &[&str]) -> Vec<Mention> {
+    let lower = text.to_lowercase();
+    // lowercasing changes the length of a few characters, whose offsets would then be off
+    let haystack = if lower.len() == text.len() {
+        lower.as_str()
+    } else {
+        text
+    };
+
+    let mut order = (0..keys.len()).collect::<Vec<_>>();
+    order.sort_by_key(|&i| Reverse(keys[i].len()));
+
+    let mut found: Vec<(usize, usize, usize)> = vec![];
+    for concept in order {
+        let key = keys[concept];
+        if key.is_empty() {
+            continue;
+        }
+        let mention = haystack.match_indices(key).find(|&(start, _)| {
+            let end = start + key.len();
+            let whole_word = !haystack[..start]
+                .chars()
+                .next_back()
+                .is_some_and(char::is_alphanumeric)
+                && !haystack[end..]
+                    .chars()
+                    .next()
+                    .is_some_and(char::is_alphanumeric);
+            let overlaps = found.iter().any(|&(_, s, e)| start < e && s < end);
+            whole_word && !overlaps && text.is_char_boundary(start) && text.is_char_boundary(end)
+        });
+        if let Some((start, _)) = mention {
+            found.push((concept, start, start + key.len()));
+        }
+    }
+    found.sort_by_key(|&(_, start, _)| start);
+
+    found
+        .into_iter()
+        .map(|(concept, start, end)| Mention {
+            concept,
+            start: text[..start].encode_utf16().count(),
+            end: text[..end].encode_utf16().count(),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_first_whole_word_mention() {
+        let text = "Regularization helps. A regular fit uses Regularization too.";
+
+        assert_eq!(
+            find_mentions(text, &["regular", "regularization"]),
+            vec![
+                Mention {
+                    concept: 1,
+                    start: 0,
+                    end: 14
+                },
+                Mention {
+                    concept: 0,
+                    start: 24,
+                    end: 31
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn prefers_longer_terms() {
+        let text = "A support vector machine finds the widest margin.";
+
+        assert_eq!(
+            find_mentions(text, &["vector", "support vector machine", "margin"]),
+            vec![
+                Mention {
+                    concept: 1,
+                    start: 2,
+                    end: 24
+                },
+                Mention {
+                    concept: 2,
+                    start: 42,
+                    end: 48
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn offsets_count_utf16_code_units() {
+        let text = "📈 Gradient descent";
+
+        assert_eq!(
+            find_mentions(text, &["gradient descent"]),
+            vec![Mention {
+                concept: 0,
+                start: 3,
+                end: 19
+            }]
+        );
+    }
+}
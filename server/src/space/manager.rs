@@ -21,7 +21,7 @@ use custom_prisma::prisma::{
 use fs_extra::dir::CopyOptions;
 
 use tokio::sync::RwLock;
-use tracing::debug;
+use tracing::{debug, info, warn};
 use uuid::Uuid;
 
 use super::Space;
@@ -58,6 +58,15 @@ impl SpaceManager {
             });
         }
 
+        for space in &spaces {
+            if let Err(e) = space.migrate_conversations().await {
+                warn!(
+                    "Failed to migrate the conversations of space {}: {:?}",
+                    space.id, e
+                );
+            }
+        }
+
         let this = Arc::new(Self {
             spaces: RwLock::new(spaces),
             node_context,
@@ -157,6 +166,7 @@ impl SpaceManager {
             fs::create_dir_all(space_path.as_path()).await?;
         }
 
+        new_space.create_conversation(None).await?;
         self.spaces.write().await.push(new_space.clone());
 
         invalidate_query!(new_space, "spaces.list");
@@ -254,7 +264,11 @@ impl SpaceManager {
         )?;
 
         space
-            .receieve_msg_from_user("Explain Jensen's inequality step by step".to_string())
+            .receieve_msg_from_user(
+                "Explain Jensen's inequality step by step".to_string(),
+                None,
+                None,
+            )
             .await?;
 
         invalidate_query!(space, "files.list");
@@ -288,11 +302,33 @@ impl SpaceManager {
 
         spaces.retain(|space| space.id != space_id);
 
+        self.node_context.index_manager.evict(space_id).await;
+        if let Err(e) = self.node_context.search.remove_space(space_id).await {
+            warn!(
+                "Failed to remove space {} from the search index: {:?}",
+                space_id, e
+            );
+        }
+
         invalidate_query!(space, "spaces.list");
 
         Ok(())
     }
 
+    /// reindex_messages adds the messages of every space to the search index, used when the index was
+    /// created after messages had already been sent.
+    pub(crate) async fn reindex_messages(&self) {
+        for space in self.spaces.read().await.iter() {
+            match space.reindex_messages().await {
+                Ok(count) => info!("Indexed {} messages of space {}", count, space.id),
+                Err(e) => warn!(
+                    "Failed to index the messages of space {}: {:?}",
+                    space.id, e
+                ),
+            }
+        }
+    }
+
     pub async fn get_all_spaces_for_user(&self, user: User) -> Vec<SpaceWrapped> {
         let _user_id_vec: Vec<u8> = user.id.as_bytes().to_vec();
 
@@ -1,6 +1,14 @@
+pub(crate) mod branch;
+pub(crate) mod export;
+pub(crate) mod flashcards;
+pub(crate) mod glossary;
 mod manager;
+pub(crate) mod quiz;
+pub(crate) mod ratings;
 #[allow(clippy::module_inception)]
 mod space;
+#[cfg(test)]
+pub(crate) mod testing;
 
 pub use manager::*;
 pub use space::*;
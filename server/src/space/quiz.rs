@@ -0,0 +1,507 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, FixedOffset};
+use custom_prisma::prisma::{quiz, quiz_attempt, user, SortOrder};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::collections::HashSet;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::{
+    index::{IndexBackend, RetrievalMode},
+    llm::{tokens::truncate_tokens, ChatMessage, LlmProvider},
+    tasks::{
+        generate_quiz::{QuestionKind, QuizQuestion, QuizSource},
+        summarize_file::parse_json,
+    },
+    utils::{u2b, u2s},
+};
+
+use super::Space;
+
+/// Chunks retrieved from the index to grade a short answer against.
+const GRADING_CONTEXT_CHUNKS: usize = 4;
+const GRADING_CONTEXT_TOKENS: usize = 1500;
+/// Lowest score of a short answer that counts as right.
+const PASSING_SCORE: f64 = 0.5;
+
+const GRADING_PROMPT: &str = "You grade a student's short answer to a quiz question. Compare it \
+with the reference answer and the context, and accept answers that are worded differently but mean \
+the same. Answer only with JSON of the form {\"score\": <number from 0 to 1>, \"feedback\": \"one \
+or two sentences for the student\"}.";
+
+/// QuizOverview is a quiz without its questions.
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct QuizOverview {
+    pub id: Uuid,
+    pub name: String,
+    pub question_count: u32,
+    pub attempt_count: u32,
+    /// best score of the user's attempts, from 0 to 1
+    pub best_score: Option<f64>,
+    pub date_created: DateTime<FixedOffset>,
+}
+
+/// A question as it is shown while taking a quiz, without its answer.
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct QuizQuestionPrompt {
+    pub kind: QuestionKind,
+    pub question: String,
+    pub choices: Vec<String>,
+}
+
+/// QuizDetails is a quiz ready to be taken.
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct QuizDetails {
+    pub id: Uuid,
+    pub name: String,
+    pub questions: Vec<QuizQuestionPrompt>,
+    pub date_created: DateTime<FixedOffset>,
+}
+
+/// The answer given to a question, by its index in the quiz.
+#[derive(Serialize, Deserialize, Type, Debug, Clone, Default)]
+pub struct QuizResponse {
+    pub question: u32,
+    /// the chosen choice of multiple choice questions
+    #[specta(optional)]
+    pub choice: Option<u32>,
+    /// the answer to short answer questions
+    #[specta(optional)]
+    pub text: Option<String>,
+}
+
+/// GradedAnswer is the user's answer to a question next to the answer key.
+#[derive(Serialize, Deserialize, Type, Debug, Clone, PartialEq)]
+pub struct GradedAnswer {
+    pub question: u32,
+    pub choice: Option<u32>,
+    pub text: Option<String>,
+    pub correct: bool,
+    /// from 0 to 1, short answers can be partly right
+    pub score: f64,
+    pub feedback: Option<String>,
+    pub correct_choice: Option<u32>,
+    pub answer: String,
+    pub explanation: Option<String>,
+    pub sources: Vec<QuizSource>,
+}
+
+/// QuizAttemptResult is a graded attempt at a quiz.
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct QuizAttemptResult {
+    pub id: Uuid,
+    pub quiz_id: Uuid,
+    /// from 0 to 1
+    pub score: f64,
+    pub answers: Vec<GradedAnswer>,
+    pub date_created: DateTime<FixedOffset>,
+}
+
+impl QuizAttemptResult {
+    fn from_data(attempt: quiz_attempt::Data) -> Result<Self> {
+        Ok(Self {
+            id: Uuid::from_slice(&attempt.id)?,
+            quiz_id: Uuid::from_slice(&attempt.quiz_id)?,
+            score: attempt.score,
+            answers: serde_json::from_str(&attempt.answers)
+                .context("Failed to parse the answers of the attempt")?,
+            date_created: attempt.date_created,
+        })
+    }
+}
+
+fn questions(quiz: &quiz::Data) -> Result<Vec<QuizQuestion>> {
+    serde_json::from_str(&quiz.questions).context("Failed to parse the questions of the quiz")
+}
+
+impl Space {
+    async fn quiz(&self, quiz_id: Uuid) -> Result<quiz::Data> {
+        self.db
+            .quiz()
+            .find_first(vec![
+                quiz::id::equals(u2b(quiz_id)),
+                quiz::space_id::equals(u2b(self.id)),
+            ])
+            .exec()
+            .await?
+            .with_context(|| format!("Quiz {} not found", quiz_id))
+    }
+
+    /// quizzes returns the quizzes of the space with the user's best score, newest first.
+    pub(crate) async fn quizzes(&self, user_id: Uuid) -> Result<Vec<QuizOverview>> {
+        let quizzes = self
+            .db
+            .quiz()
+            .find_many(vec![quiz::space_id::equals(u2b(self.id))])
+            .order_by(quiz::date_created::order(SortOrder::Desc))
+            .exec()
+            .await?;
+        let attempts = self
+            .db
+            .quiz_attempt()
+            .find_many(vec![
+                quiz_attempt::user_id::equals(u2b(user_id)),
+                quiz_attempt::quiz_id::in_vec(quizzes.iter().map(|quiz| quiz.id.clone()).collect()),
+            ])
+            .exec()
+            .await?;
+
+        quizzes
+            .into_iter()
+            .map(|quiz| {
+                let scores = attempts
+                    .iter()
+                    .filter(|attempt| attempt.quiz_id == quiz.id)
+                    .map(|attempt| attempt.score)
+                    .collect::<Vec<_>>();
+                Ok(QuizOverview {
+                    id: Uuid::from_slice(&quiz.id)?,
+                    question_count: questions(&quiz)?.len() as u32,
+                    attempt_count: scores.len() as u32,
+                    best_score: scores.into_iter().reduce(f64::max),
+                    name: quiz.name,
+                    date_created: quiz.date_created,
+                })
+            })
+            .collect()
+    }
+
+    /// quiz_details returns the questions of the quiz without their answers.
+    pub(crate) async fn quiz_details(&self, quiz_id: Uuid) -> Result<QuizDetails> {
+        let quiz = self.quiz(quiz_id).await?;
+        let questions = questions(&quiz)?
+            .into_iter()
+            .map(|question| QuizQuestionPrompt {
+                kind: question.kind,
+                question: question.question,
+                choices: question.choices,
+            })
+            .collect();
+
+        Ok(QuizDetails {
+            id: quiz_id,
+            name: quiz.name,
+            questions,
+            date_created: quiz.date_created,
+        })
+    }
+
+    /// submit_quiz grades the answers and stores them as an attempt of the user. Questions without
+    /// an answer count as wrong.
+    pub(crate) async fn submit_quiz(
+        &self,
+        user_id: Uuid,
+        quiz_id: Uuid,
+        responses: Vec<QuizResponse>,
+    ) -> Result<QuizAttemptResult> {
+        let quiz = self.quiz(quiz_id).await?;
+        let questions = questions(&quiz)?;
+        if let Some(response) = responses
+            .iter()
+            .find(|response| response.question as usize >= questions.len())
+        {
+            bail!("The quiz has no question {}", response.question);
+        }
+
+        let llm = self.generator();
+        let mut answers = vec![];
+        for (index, question) in questions.into_iter().enumerate() {
+            let response = responses
+                .iter()
+                .find(|response| response.question as usize == index)
+                .cloned()
+                .unwrap_or_default();
+            let context = match question.kind {
+                QuestionKind::ShortAnswer => self.grading_context(&question).await,
+                QuestionKind::MultipleChoice => vec![],
+            };
+            let graded = grade(llm.as_ref(), index as u32, question, response, &context).await?;
+            answers.push(graded);
+        }
+        let score = quiz_score(&answers);
+
+        let id = Uuid::new_v4();
+        let attempt = self
+            .db
+            .quiz_attempt()
+            .create(
+                u2b(id),
+                u2s(id),
+                serde_json::to_string(&answers)?,
+                score,
+                quiz::id::equals(quiz.id),
+                user::id::equals(u2b(user_id)),
+                vec![],
+            )
+            .exec()
+            .await?;
+
+        QuizAttemptResult::from_data(attempt)
+    }
+
+    /// quiz_attempts returns the user's attempts at the quiz, newest first.
+    pub(crate) async fn quiz_attempts(
+        &self,
+        user_id: Uuid,
+        quiz_id: Uuid,
+    ) -> Result<Vec<QuizAttemptResult>> {
+        let quiz = self.quiz(quiz_id).await?;
+        self.db
+            .quiz_attempt()
+            .find_many(vec![
+                quiz_attempt::quiz_id::equals(quiz.id),
+                quiz_attempt::user_id::equals(u2b(user_id)),
+            ])
+            .order_by(quiz_attempt::date_created::order(SortOrder::Desc))
+            .exec()
+            .await?
+            .into_iter()
+            .map(QuizAttemptResult::from_data)
+            .collect()
+    }
+
+    pub(crate) async fn delete_quiz(&self, quiz_id: Uuid) -> Result<()> {
+        let quiz = self.quiz(quiz_id).await?;
+        self.db
+            .quiz()
+            .delete(quiz::id::equals(quiz.id))
+            .exec()
+            .await?;
+
+        Ok(())
+    }
+
+    /// grading_context retrieves passages on the question from the files it was made from, next to
+    /// the snippets it cites. Retrieval needs the native index, failures only leave the snippets.
+    async fn grading_context(&self, question: &QuizQuestion) -> Vec<String> {
+        let mut context = question
+            .sources
+            .iter()
+            .map(|source| source.snippet.clone())
+            .collect::<Vec<_>>();
+        if IndexBackend::from_env() != IndexBackend::Native {
+            return context;
+        }
+
+        let files = question
+            .sources
+            .iter()
+            .map(|source| source.file_id)
+            .collect::<HashSet<_>>();
+        let mode = RetrievalMode::parse(&self.meta.retrieval_mode);
+        match self
+            .index_manager()
+            .search(
+                self.id,
+                &question.question,
+                GRADING_CONTEXT_CHUNKS,
+                mode,
+                Some(&files),
+            )
+            .await
+        {
+            Ok(hits) => {
+                debug!("Grading against {} retrieved chunks", hits.len());
+                context.extend(hits.into_iter().map(|hit| hit.chunk.text));
+            }
+            Err(e) => warn!("Failed to retrieve context to grade an answer: {:?}", e),
+        }
+        context
+    }
+}
+
+/// grade checks the response against the answer key. Multiple choice answers are compared directly,
+/// short answers are graded by the model against the context.
+pub async fn grade(
+    llm: &dyn LlmProvider,
+    index: u32,
+    question: QuizQuestion,
+    response: QuizResponse,
+    context: &[String],
+) -> Result<GradedAnswer> {
+    let text = response
+        .text
+        .map(|text| text.trim().to_string())
+        .filter(|text| !text.is_empty());
+
+    let (score, feedback) = match question.kind {
+        QuestionKind::MultipleChoice => {
+            let right = response.choice.is_some() && response.choice == question.correct_choice;
+            (if right { 1.0 } else { 0.0 }, None)
+        }
+        QuestionKind::ShortAnswer => match &text {
+            None => (0.0, None),
+            Some(text) => grade_short_answer(llm, &question, text, context).await?,
+        },
+    };
+
+    Ok(GradedAnswer {
+        question: index,
+        choice: response.choice,
+        text,
+        correct: score >= PASSING_SCORE,
+        score,
+        feedback,
+        correct_choice: question.correct_choice,
+        answer: question.answer,
+        explanation: question.explanation,
+        sources: question.sources,
+    })
+}
+
+async fn grade_short_answer(
+    llm: &dyn LlmProvider,
+    question: &QuizQuestion,
+    text: &str,
+    context: &[String],
+) -> Result<(f64, Option<String>)> {
+    let context = truncate_tokens(&context.join("\n\n"), GRADING_CONTEXT_TOKENS);
+    let prompt = [
+        ChatMessage::system(GRADING_PROMPT),
+        ChatMessage::user(format!(
+            "Context:\n{}\n\nQuestion: {}\nReference answer: {}\nStudent answer: {}",
+            context, question.question, question.answer, text
+        )),
+    ];
+    let reply = llm.complete(&prompt).await?;
+
+    #[derive(Deserialize)]
+    struct Grade {
+        score: f64,
+        feedback: Option<String>,
+    }
+    match parse_json::<Grade>(&reply) {
+        Some(grade) => Ok((
+            grade.score.clamp(0.0, 1.0),
+            grade
+                .feedback
+                .map(|feedback| feedback.trim().to_string())
+                .filter(|feedback| !feedback.is_empty()),
+        )),
+        None => {
+            warn!("Grade of a short answer was not valid JSON, comparing it to the answer");
+            let same = text.to_lowercase() == question.answer.to_lowercase();
+            Ok((if same { 1.0 } else { 0.0 }, None))
+        }
+    }
+}
+
+/// quiz_score is the mean score of the answers.
+fn quiz_score(answers: &[GradedAnswer]) -> f64 {
+    if answers.is_empty() {
+        return 0.0;
+    }
+    answers.iter().map(|answer| answer.score).sum::<f64>() / answers.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::MockProvider;
+
+    fn multiple_choice() -> QuizQuestion {
+        QuizQuestion {
+            kind: QuestionKind::MultipleChoice,
+            question: "What moves in osmosis?".to_string(),
+            choices: vec!["Salt".to_string(), "Water".to_string()],
+            correct_choice: Some(1),
+            answer: "Water".to_string(),
+            explanation: None,
+            sources: vec![],
+        }
+    }
+
+    fn short_answer() -> QuizQuestion {
+        QuizQuestion {
+            kind: QuestionKind::ShortAnswer,
+            question: "Is osmosis active or passive?".to_string(),
+            choices: vec![],
+            correct_choice: None,
+            answer: "Passive".to_string(),
+            explanation: None,
+            sources: vec![],
+        }
+    }
+
+    #[tokio::test]
+    async fn grades_multiple_choice_without_the_model() {
+        let llm = MockProvider::new(Vec::<String>::new());
+        let response = QuizResponse {
+            question: 0,
+            choice: Some(1),
+            text: None,
+        };
+
+        let graded = grade(&llm, 0, multiple_choice(), response, &[])
+            .await
+            .expect("failed to grade");
+
+        assert!(graded.correct);
+        assert_eq!(graded.score, 1.0);
+        assert!(llm.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn grades_short_answers_with_the_model() {
+        let llm =
+            MockProvider::new([r#"{"score": 0.8, "feedback": "Right, it needs no energy."}"#]);
+        let response = QuizResponse {
+            question: 0,
+            choice: None,
+            text: Some(" it is passive ".to_string()),
+        };
+        let context = vec!["Osmosis needs no energy.".to_string()];
+
+        let graded = grade(&llm, 0, short_answer(), response, &context)
+            .await
+            .expect("failed to grade");
+
+        assert!(graded.correct);
+        assert_eq!(graded.score, 0.8);
+        assert_eq!(
+            graded.feedback.as_deref(),
+            Some("Right, it needs no energy.")
+        );
+        assert_eq!(graded.text.as_deref(), Some("it is passive"));
+        let prompt = &llm.requests()[0][1].content;
+        assert!(prompt.contains("Osmosis needs no energy."));
+        assert!(prompt.contains("Reference answer: Passive"));
+    }
+
+    #[tokio::test]
+    async fn missing_answers_are_wrong() {
+        let llm = MockProvider::new(Vec::<String>::new());
+
+        let graded = grade(&llm, 0, short_answer(), QuizResponse::default(), &[])
+            .await
+            .expect("failed to grade");
+
+        assert!(!graded.correct);
+        assert_eq!(graded.score, 0.0);
+        assert!(llm.requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn scores_are_averaged() {
+        let llm = MockProvider::new(Vec::<String>::new());
+        let right = grade(
+            &llm,
+            0,
+            multiple_choice(),
+            QuizResponse {
+                question: 0,
+                choice: Some(1),
+                text: None,
+            },
+            &[],
+        )
+        .await
+        .expect("failed to grade");
+        let wrong = grade(&llm, 1, multiple_choice(), QuizResponse::default(), &[])
+            .await
+            .expect("failed to grade");
+
+        assert_eq!(quiz_score(&[right, wrong]), 0.5);
+    }
+}
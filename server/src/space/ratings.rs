@@ -0,0 +1,215 @@
+use anyhow::{bail, Result};
+use custom_prisma::prisma::message;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use uuid::Uuid;
+
+/// Longest comment that can be left with a rating, in characters.
+pub const MAX_RATING_COMMENT_LENGTH: usize = 2000;
+
+message::include!(rated_answer { parent user_message sources });
+
+/// Rating of an answer, stored as 1 or -1.
+#[derive(Deserialize, Serialize, Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Rating {
+    Up,
+    Down,
+}
+
+impl Rating {
+    pub fn score(self) -> i32 {
+        match self {
+            Rating::Up => 1,
+            Rating::Down => -1,
+        }
+    }
+
+    pub fn from_score(score: i32) -> Option<Self> {
+        match score {
+            1 => Some(Rating::Up),
+            -1 => Some(Rating::Down),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Serialize, Type, Debug)]
+pub struct RatingStats {
+    /// finished answers in the space
+    pub answered: i64,
+    pub up: i64,
+    pub down: i64,
+}
+
+/// RatedAnswer is a rated question/answer pair with the context it was answered from, for evaluation sets.
+#[derive(Serialize, Type, Debug)]
+pub struct RatedAnswer {
+    pub message_id: String,
+    pub question: String,
+    pub answer: String,
+    pub rating: Rating,
+    pub comment: Option<String>,
+    pub context: Vec<RatedContext>,
+}
+
+#[derive(Serialize, Type, Debug)]
+pub struct RatedContext {
+    pub file_id: Option<Uuid>,
+    pub page: Option<i32>,
+    /// the start of the chunk, see tasks::reply::SNIPPET_LENGTH
+    pub snippet: String,
+}
+
+/// rating_comment trims the comment and checks its length, an empty one is dropped.
+pub fn rating_comment(comment: Option<String>) -> Result<Option<String>> {
+    let Some(comment) = comment
+        .map(|comment| comment.trim().to_string())
+        .filter(|comment| !comment.is_empty())
+    else {
+        return Ok(None);
+    };
+    if comment.chars().count() > MAX_RATING_COMMENT_LENGTH {
+        bail!(
+            "Comments can have at most {} characters",
+            MAX_RATING_COMMENT_LENGTH
+        );
+    }
+
+    Ok(Some(comment))
+}
+
+impl RatedAnswer {
+    /// from_answer pairs a rated answer with its question, None when it has no valid rating or its
+    /// question is gone.
+    pub fn from_answer(answer: rated_answer::Data) -> Option<Self> {
+        // the question is the answer's parent, or its user message for older answers
+        let question = answer.parent.or(answer.user_message)?;
+        let sources = answer
+            .sources
+            .into_iter()
+            .map(|source| {
+                let context = RatedContext {
+                    file_id: source.file_id.and_then(|id| Uuid::from_slice(&id).ok()),
+                    page: source.page,
+                    snippet: source.snippet,
+                };
+                (source.rank, context)
+            })
+            .collect();
+
+        Self::new(
+            answer.id_str,
+            question.text,
+            answer.text,
+            answer.rating?,
+            answer.rating_comment,
+            sources,
+        )
+    }
+
+    /// new builds the entry of an answer with the score it was rated with and its context, ordered by
+    /// the rank it was retrieved at.
+    fn new(
+        message_id: String,
+        question: String,
+        answer: String,
+        score: i32,
+        comment: Option<String>,
+        mut context: Vec<(i32, RatedContext)>,
+    ) -> Option<Self> {
+        let rating = Rating::from_score(score)?;
+        context.sort_by_key(|(rank, _)| *rank);
+
+        Some(RatedAnswer {
+            message_id,
+            question,
+            answer,
+            rating,
+            comment,
+            context: context.into_iter().map(|(_, context)| context).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(snippet: &str, page: Option<i32>) -> RatedContext {
+        RatedContext {
+            file_id: None,
+            page,
+            snippet: snippet.to_string(),
+        }
+    }
+
+    #[test]
+    fn only_accepts_up_and_down_scores() {
+        assert_eq!(Rating::from_score(Rating::Up.score()), Some(Rating::Up));
+        assert_eq!(Rating::from_score(Rating::Down.score()), Some(Rating::Down));
+        assert_eq!(Rating::from_score(0), None);
+        assert_eq!(Rating::from_score(2), None);
+        assert!(serde_json::from_str::<Rating>("\"sideways\"").is_err());
+    }
+
+    #[test]
+    fn limits_comments() {
+        assert_eq!(rating_comment(None).expect("no comment is valid"), None);
+        assert_eq!(rating_comment(Some("  ".to_string())).expect("valid"), None);
+        assert_eq!(
+            rating_comment(Some(" Missed the point ".to_string())).expect("valid"),
+            Some("Missed the point".to_string())
+        );
+        assert!(rating_comment(Some("é".repeat(MAX_RATING_COMMENT_LENGTH))).is_ok());
+        assert!(rating_comment(Some("é".repeat(MAX_RATING_COMMENT_LENGTH + 1))).is_err());
+    }
+
+    #[test]
+    fn exports_rated_answers_with_ranked_context() {
+        let file_id = Uuid::new_v4();
+        let answer = RatedAnswer::new(
+            "answer".to_string(),
+            "What is osmosis?".to_string(),
+            "Diffusion of water.".to_string(),
+            -1,
+            Some("Too short".to_string()),
+            vec![
+                (1, context("second", None)),
+                (
+                    0,
+                    RatedContext {
+                        file_id: Some(file_id),
+                        ..context("first", Some(3))
+                    },
+                ),
+            ],
+        )
+        .expect("the answer is rated");
+
+        assert_eq!(
+            serde_json::to_value(&answer).expect("failed to serialize"),
+            serde_json::json!({
+                "message_id": "answer",
+                "question": "What is osmosis?",
+                "answer": "Diffusion of water.",
+                "rating": "down",
+                "comment": "Too short",
+                "context": [
+                    { "file_id": file_id, "page": 3, "snippet": "first" },
+                    { "file_id": null, "page": null, "snippet": "second" },
+                ],
+            })
+        );
+
+        let unrated = RatedAnswer::new(
+            "answer".to_string(),
+            "What is osmosis?".to_string(),
+            "Diffusion of water.".to_string(),
+            0,
+            None,
+            vec![],
+        );
+        assert!(unrated.is_none());
+    }
+}
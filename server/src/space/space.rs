@@ -1,6 +1,10 @@
 use crate::{
     api::{message_with_tasks_and_peer, CoreEvent},
     get_spaces_dir,
+    index::{IndexManager, SpaceIndex},
+    llm::{LlmProvider, SidecarProvider},
+    search::{MessageDocument, SearchIndex},
+    sidecar::SidecarClient,
     tasks::{dispatcher::Dispatcher, IntoTask},
     utils::u2b,
     NodeContext,
@@ -16,6 +20,7 @@ use std::{
 
 use custom_prisma::prisma::{meta, PrismaClient};
 
+use tokio::sync::RwLock;
 use tracing::warn;
 use uuid::Uuid;
 
@@ -24,15 +29,17 @@ use std::ops::Sub;
 
 use anyhow::{anyhow, bail, Context, Error, Result};
 use custom_prisma::prisma::message;
-use custom_prisma::prisma::{file, space as db_space, task};
+use custom_prisma::prisma::{conversation, file, space as db_space, task, SortOrder};
 use prisma_client_rust::schema::constants::ordering::SORT_ORDER;
 use rspc::alpha::AlphaRouter;
 
 use tracing::debug;
 
 use crate::invalidate_query;
-use crate::tasks::reply::ReplyTaskInfo;
-use crate::utils::u2s;
+use crate::tasks::learn_file::LearnFileTaskInfo;
+use crate::tasks::reply::{MessageScope, ReplyTaskInfo};
+use crate::utils::{check_file, u2s, FileChange};
+use chrono::Utc;
 
 #[derive(Clone)]
 pub struct Space {
@@ -66,25 +73,356 @@ impl Space {
         let spaces_dir = get_spaces_dir().await;
         spaces_dir.join(self.id.to_string())
     }
+
+    /// index returns the native vector index of the space (see [`crate::index::IndexBackend`]).
+    pub(crate) async fn index(&self) -> Result<Arc<RwLock<SpaceIndex>>> {
+        self.node_context.index_manager.get(self.id).await
+    }
+
+    pub(crate) fn index_manager(&self) -> Arc<IndexManager> {
+        self.node_context.index_manager.clone()
+    }
+
+    pub(crate) fn sidecar(&self) -> Arc<SidecarClient> {
+        self.node_context.sidecar.clone()
+    }
+
+    /// llm returns the provider replies are generated with, if the node generates them itself.
+    pub(crate) fn llm(&self) -> Option<Arc<dyn LlmProvider>> {
+        self.node_context.llm.clone()
+    }
+
+    /// generator returns the provider used to generate text outside of replies: the node's own provider,
+    /// or the Python server's chat model.
+    pub(crate) fn generator(&self) -> Arc<dyn LlmProvider> {
+        self.llm()
+            .unwrap_or_else(|| Arc::new(SidecarProvider::new(self.sidecar())))
+    }
+
+    pub(crate) fn search(&self) -> Arc<SearchIndex> {
+        self.node_context.search.clone()
+    }
+
+    /// index_message adds a question or a finished answer to the search index. Failures are only
+    /// logged, the message is kept either way.
+    pub(crate) async fn index_message(&self, document: MessageDocument) {
+        let id = document.id;
+        if let Err(e) = self.search().index_message(document).await {
+            warn!("Failed to add message {} to the search index: {:?}", id, e);
+        }
+    }
+
+    /// reindex_messages adds all questions and successful answers of the space to the search index.
+    pub(crate) async fn reindex_messages(&self) -> Result<usize> {
+        let messages = self
+            .db
+            .message()
+            .find_many(vec![
+                message::space_id::equals(u2b(self.id)),
+                message::response_status::not_in_vec(vec![1, 3]),
+            ])
+            .exec()
+            .await?;
+
+        let search = self.search();
+        for message in &messages {
+            search.index_message(message_document(message)?).await?;
+        }
+        Ok(messages.len())
+    }
+
+    /// refresh_stale_files marks learned files whose content changed on disk since they were indexed as
+    /// stale and dispatches a re-learn for each of them. Returns the ids of the files that became stale.
+    pub(crate) async fn refresh_stale_files(&self) -> Result<Vec<Uuid>> {
+        let files = self
+            .db
+            .file()
+            .find_many(vec![
+                file::space_id::equals(u2b(self.id)),
+                file::learned::equals(true),
+                file::stale::equals(false),
+            ])
+            .exec()
+            .await?;
+
+        let space_path = self.path().await;
+        let mut stale = vec![];
+
+        for file in files {
+            let path = space_path.join(&file.path);
+            let update = match check_file(
+                &path,
+                file.date_indexed.with_timezone(&Utc),
+                file.content_hash.as_deref(),
+            )
+            .await
+            {
+                FileChange::Unchanged => continue,
+                FileChange::Unreadable(e) => {
+                    warn!("Failed to check {:?} for changes: {:?}", file.path, e);
+                    continue;
+                }
+                // the modification time no longer matches, so the next check would hash it again
+                FileChange::Touched => file::date_indexed::set(Utc::now().into()),
+                FileChange::Changed => {
+                    stale.push(Uuid::from_slice(&file.id)?);
+                    file::stale::set(true)
+                }
+            };
+
+            self.db
+                .file()
+                .update(file::id::equals(file.id.clone()), vec![update])
+                .exec()
+                .await?;
+        }
+
+        for file_id in &stale {
+            debug!("File {} changed since it was learned, re-learning", file_id);
+            if let Err(e) = self
+                .dispatcher
+                .clone()
+                .dispatch(self, LearnFileTaskInfo { file_id: *file_id }.runnable())
+                .await
+            {
+                warn!("Failed to re-learn changed file {}: {:?}", file_id, e);
+            }
+        }
+        if !stale.is_empty() {
+            invalidate_query!(self, "files.list");
+        }
+
+        Ok(stale)
+    }
+}
+
+/// Name of conversations that were created without one.
+const DEFAULT_CONVERSATION_NAME: &str = "New conversation";
+
+impl Space {
+    /// conversation returns the conversation with the id if it belongs to this space.
+    pub(crate) async fn conversation(&self, id: Uuid) -> Result<conversation::Data> {
+        self.db
+            .conversation()
+            .find_first(vec![
+                conversation::id::equals(u2b(id)),
+                conversation::space_id::equals(u2b(self.id)),
+            ])
+            .exec()
+            .await?
+            .with_context(|| format!("Conversation {} not found", id))
+    }
+
+    /// file returns the file with the id if it belongs to this space.
+    pub(crate) async fn file(&self, id: Uuid) -> Result<file::Data> {
+        self.db
+            .file()
+            .find_first(vec![
+                file::id::equals(u2b(id)),
+                file::space_id::equals(u2b(self.id)),
+            ])
+            .exec()
+            .await?
+            .with_context(|| format!("File {} not found", id))
+    }
+
+    pub(crate) async fn create_conversation(
+        &self,
+        name: Option<String>,
+    ) -> Result<conversation::Data> {
+        let id = Uuid::new_v4();
+        let conversation = self
+            .db
+            .conversation()
+            .create(
+                u2b(id),
+                u2s(id),
+                name.unwrap_or_else(|| DEFAULT_CONVERSATION_NAME.to_string()),
+                db_space::id::equals(u2b(self.id)),
+                vec![],
+            )
+            .exec()
+            .await?;
+
+        invalidate_query!(self, "conversations.list");
+
+        Ok(conversation)
+    }
+
+    /// default_conversation returns the most recently used conversation. Every space has one, see
+    /// [Space::migrate_conversations].
+    pub(crate) async fn default_conversation(&self) -> Result<conversation::Data> {
+        self.db
+            .conversation()
+            .find_first(vec![conversation::space_id::equals(u2b(self.id))])
+            .order_by(conversation::date_modified::order(SortOrder::Desc))
+            .exec()
+            .await?
+            .context("The space has no conversation")
+    }
+
+    /// migrate_conversations runs when the space is loaded, so queries never have to write. It makes
+    /// sure the space has a conversation, moves messages sent before conversations existed into the
+    /// most recent one and chains the messages of conversations started before messages had parents.
+    pub(crate) async fn migrate_conversations(&self) -> Result<()> {
+        let latest = self
+            .db
+            .conversation()
+            .find_first(vec![conversation::space_id::equals(u2b(self.id))])
+            .order_by(conversation::date_modified::order(SortOrder::Desc))
+            .exec()
+            .await?;
+        let conversation = match latest {
+            Some(conversation) => conversation,
+            None => self.create_conversation(None).await?,
+        };
+
+        let adopted = self
+            .db
+            .message()
+            .update_many(
+                vec![
+                    message::space_id::equals(u2b(self.id)),
+                    message::conversation_id::equals(None),
+                ],
+                vec![message::conversation_id::set(Some(conversation.id.clone()))],
+            )
+            .exec()
+            .await?;
+        if adopted > 0 {
+            debug!(
+                "Moved {} messages into conversation {}",
+                adopted, conversation.id_str
+            );
+            // search hits point to the conversation of a message
+            if let Err(e) = self.reindex_messages().await {
+                warn!("Failed to update the search index: {:?}", e);
+            }
+        }
+
+        let unlinked = self
+            .db
+            .conversation()
+            .find_many(vec![
+                conversation::space_id::equals(u2b(self.id)),
+                conversation::head_id::equals(None),
+            ])
+            .exec()
+            .await?;
+        for conversation in unlinked {
+            self.link_legacy_messages(conversation).await?;
+        }
+
+        Ok(())
+    }
+
+    /// resolve_conversation returns the conversation with the id, or the default conversation without one.
+    pub(crate) async fn resolve_conversation(
+        &self,
+        id: Option<Uuid>,
+    ) -> Result<conversation::Data> {
+        match id {
+            Some(id) => self.conversation(id).await,
+            None => self.default_conversation().await,
+        }
+    }
 }
 
 impl Space {
     pub async fn receieve_msg_from_user(
         &self,
         msg: String,
+        conversation_id: Option<Uuid>,
+        scope: Option<MessageScope>,
+    ) -> Result<message_with_tasks_and_peer::Data> {
+        let conversation = self.resolve_conversation(conversation_id).await?;
+        let parent_id = conversation.head_id.clone();
+        self.send_user_message(msg, conversation, parent_id, scope)
+            .await
+    }
+
+    /// edit_user_message sends an edited version of a question as a new branch next to the original.
+    pub(crate) async fn edit_user_message(
+        &self,
+        message_id: Uuid,
+        text: String,
     ) -> Result<message_with_tasks_and_peer::Data> {
-        let latest_messages = self
+        let original = self.user_message(message_id).await?;
+        let conversation_id = original
+            .conversation_id
+            .context("The message is not part of a conversation")?;
+        let conversation = self
+            .conversation(Uuid::from_slice(&conversation_id)?)
+            .await?;
+
+        let scope = message_scope(&original)?;
+        self.send_user_message(text, conversation, original.parent_id, scope)
+            .await
+    }
+
+    /// regenerate_reply answers a question again. The new answer becomes the selected one, earlier answers
+    /// stay around as alternatives.
+    pub(crate) async fn regenerate_reply(&self, message_id: Uuid) -> Result<()> {
+        let message = self.user_message(message_id).await?;
+        let generating = self
             .db
             .message()
-            .find_many(vec![message::space_id::equals(u2b(self.id))])
-            .order_by(message::date_created::order(
-                custom_prisma::prisma::SortOrder::Desc,
-            ))
-            .take(100)
+            .find_first(vec![
+                message::parent_id::equals(Some(message.id.clone())),
+                message::response_status::equals(1),
+            ])
             .exec()
             .await?;
+        if generating.is_some() {
+            bail!("The answer to this message is still being generated");
+        }
 
+        let reply_task = ReplyTaskInfo {
+            message_id,
+            scope: message_scope(&message)?,
+            message_text: message.text,
+        };
+        self.clone()
+            .dispatcher
+            .dispatch(self, reply_task.runnable())
+            .await?;
+
+        Ok(())
+    }
+
+    async fn user_message(&self, message_id: Uuid) -> Result<message::Data> {
+        self.db
+            .message()
+            .find_first(vec![
+                message::id::equals(u2b(message_id)),
+                message::space_id::equals(u2b(self.id)),
+                message::is_user_message::equals(true),
+            ])
+            .exec()
+            .await?
+            .with_context(|| format!("Message {} not found", message_id))
+    }
+
+    /// send_user_message adds a question after `parent_id` in the conversation and dispatches its reply.
+    async fn send_user_message(
+        &self,
+        msg: String,
+        conversation: conversation::Data,
+        parent_id: Option<Vec<u8>>,
+        scope: Option<MessageScope>,
+    ) -> Result<message_with_tasks_and_peer::Data> {
         let id = Uuid::new_v4();
+        let scope = scope.filter(|scope| !scope.is_empty());
+
+        let mut params = vec![message::conversation::connect(conversation::id::equals(
+            conversation.id.clone(),
+        ))];
+        if let Some(parent_id) = parent_id {
+            params.push(message::parent::connect(message::id::equals(parent_id)));
+        }
+        if let Some(scope) = &scope {
+            params.push(message::scope::set(Some(serde_json::to_string(scope)?)));
+        }
 
         let message = self
             .db
@@ -94,17 +432,29 @@ impl Space {
                 u2s(id),
                 msg,
                 db_space::id::equals(u2b(self.clone().id)),
-                vec![],
+                params,
             )
             .include(message_with_tasks_and_peer::include())
             .exec()
             .await?;
 
+        let conversation_id = Uuid::from_slice(&conversation.id)?;
+        self.set_conversation_head(conversation.id, message.id.clone())
+            .await?;
+        self.index_message(MessageDocument {
+            id,
+            space_id: self.id,
+            conversation_id: Some(conversation_id),
+            text: message.text.clone(),
+        })
+        .await;
+
         debug!("Created message {:?}", message);
 
         let reply_task = ReplyTaskInfo {
             message_id: id,
             message_text: message.text.clone(),
+            scope,
         };
         let task_res = self
             .clone()
@@ -116,6 +466,30 @@ impl Space {
     }
 }
 
+/// message_document returns the message as it is stored in the search index.
+pub(crate) fn message_document(message: &message::Data) -> Result<MessageDocument> {
+    Ok(MessageDocument {
+        id: Uuid::from_slice(&message.id)?,
+        space_id: Uuid::from_slice(&message.space_id)?,
+        conversation_id: message
+            .conversation_id
+            .as_deref()
+            .map(Uuid::from_slice)
+            .transpose()?,
+        text: message.text.clone(),
+    })
+}
+
+/// message_scope returns the files a question was limited to.
+fn message_scope(message: &message::Data) -> Result<Option<MessageScope>> {
+    message
+        .scope
+        .as_deref()
+        .map(serde_json::from_str)
+        .transpose()
+        .context("Failed to parse the scope of the message")
+}
+
 #[derive(Serialize, Deserialize, Debug, Type)]
 pub struct SpaceWrapped {
     pub id: Uuid,
@@ -0,0 +1,108 @@
+//! Spaces backed by a throwaway database, for tests of code that queries it.
+use std::{env, sync::Arc};
+
+use custom_prisma::prisma::{meta, space as db_space, user, PrismaClient};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::{
+    index::IndexManager,
+    llm::LlmProvider,
+    search::SearchIndex,
+    sidecar::SidecarClient,
+    tasks::dispatcher::Dispatcher,
+    utils::{load_and_migrate, u2b, u2s},
+    NodeContext,
+};
+
+use super::Space;
+
+/// database creates an empty database with the current schema in the temp dir.
+pub(crate) async fn database() -> Arc<PrismaClient> {
+    let path = env::temp_dir().join(format!("yerb-test-{}.db", Uuid::new_v4()));
+    let db = load_and_migrate(&format!("file:{}", path.display()))
+        .await
+        .expect("failed to create the database");
+    Arc::new(db)
+}
+
+/// space creates a space owned by a new user.
+pub(crate) async fn space(db: &Arc<PrismaClient>) -> Space {
+    let (user_id, meta_id, space_id) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    db.user()
+        .create(u2b(user_id), u2s(user_id), vec![])
+        .exec()
+        .await
+        .expect("failed to create the user");
+    let meta = db
+        .meta()
+        .create(
+            u2b(meta_id),
+            u2s(meta_id),
+            "Test".to_string(),
+            String::new(),
+            vec![],
+        )
+        .exec()
+        .await
+        .expect("failed to create the meta");
+    db.space()
+        .create(
+            u2b(space_id),
+            u2s(space_id),
+            meta::id::equals(u2b(meta_id)),
+            user::id::equals(u2b(user_id)),
+            vec![],
+        )
+        .exec()
+        .await
+        .expect("failed to create the space");
+
+    let dispatcher = Dispatcher::new(None);
+    Space {
+        id: space_id,
+        owner_id: user_id,
+        meta,
+        db: db.clone(),
+        dispatcher: dispatcher.clone(),
+        node_context: NodeContext {
+            event_bus_tx: broadcast::channel(16).0,
+            db: db.clone(),
+            dispatcher,
+            index_manager: IndexManager::new(),
+            llm: None,
+            sidecar: Arc::new(
+                SidecarClient::new(None).expect("failed to create the sidecar client"),
+            ),
+            search: SearchIndex::open_in_memory().expect("failed to open the search index"),
+        },
+    }
+}
+
+/// with_llm makes the space generate text with the provider instead of the Python server.
+pub(crate) fn with_llm(mut space: Space, llm: Arc<dyn LlmProvider>) -> Space {
+    space.node_context.llm = Some(llm);
+    space
+}
+
+/// file adds a file at the path, relative to the space, and returns its id.
+pub(crate) async fn file(space: &Space, path: &str) -> Uuid {
+    let id = Uuid::new_v4();
+    let (name, extension) = path.rsplit_once('.').unwrap_or((path, ""));
+    space
+        .db
+        .file()
+        .create(
+            u2b(id),
+            u2s(id),
+            path.to_string(),
+            name.to_string(),
+            extension.to_string(),
+            db_space::id::equals(u2b(space.id)),
+            vec![],
+        )
+        .exec()
+        .await
+        .expect("failed to create the file");
+    id
+}
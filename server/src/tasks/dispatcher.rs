@@ -4,7 +4,7 @@ use anyhow::Result;
 use std::{collections::HashMap, sync::Arc};
 use uuid::Uuid;
 
-use tokio::sync::{mpsc, oneshot, RwLock, Semaphore};
+use tokio::sync::{mpsc, oneshot, watch, RwLock, Semaphore};
 use tracing::{debug, error, info};
 
 use super::DTask;
@@ -23,10 +23,12 @@ pub struct Dispatcher {
     running_txs: RwLock<HashMap<Uuid, tokio::sync::mpsc::Sender<TaskCommand>>>,
     dispatcher_tx: mpsc::UnboundedSender<DispatcherEvent>,
     sem: Arc<Semaphore>,
+    /// health of the supervised Python server, tasks that need it wait while it is down
+    sidecar_health: Option<watch::Receiver<bool>>,
 }
 
 impl Dispatcher {
-    pub fn new() -> Arc<Self> {
+    pub fn new(sidecar_health: Option<watch::Receiver<bool>>) -> Arc<Self> {
         let (dispatcher_tx, mut dispatcher_rx) = mpsc::unbounded_channel();
         let sem = Arc::new(Semaphore::new(100)); // Adjust as needed
 
@@ -35,6 +37,7 @@ impl Dispatcher {
             running_txs: RwLock::new(HashMap::new()),
             dispatcher_tx,
             sem,
+            sidecar_health,
         });
 
         let _this2 = this.clone();
@@ -65,12 +68,26 @@ impl Dispatcher {
         let sem = self.sem.clone();
         let space = space.clone();
         let task_id: Uuid = task.id();
+        let sidecar_health = self
+            .sidecar_health
+            .clone()
+            .filter(|_| task.needs_sidecar(&space));
 
         let task_fut = {
             let sem_clone = sem.clone(); // Clone Semaphore for use in async block
             tokio::spawn({
                 async move {
                     info!("Task {} started", task_id);
+                    if let Some(mut health) = sidecar_health {
+                        if !*health.borrow_and_update() {
+                            info!("Task {} is waiting for the Python server", task_id);
+                        }
+                        while !*health.borrow_and_update() {
+                            if health.changed().await.is_err() {
+                                break;
+                            }
+                        }
+                    }
                     let permit = sem_clone.acquire().await.unwrap(); // Acquire permit
 
                     info!("Task {} acquired permit", task_id);
@@ -0,0 +1,317 @@
+use crate::index::{extract_chunks, TextChunk};
+use crate::llm::tokens::truncate_tokens;
+use crate::llm::{ChatMessage, LlmProvider};
+use crate::utils::u2b;
+use crate::{invalidate_query, space::Space};
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use custom_prisma::prisma::{file, task};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::summarize_file::{parse_json, split_sections, SECTION_TOKENS};
+use super::{generation_needs_sidecar, TaskExec, TaskInfo, TaskState};
+
+/// Most terms asked for per section of the file.
+const CONCEPTS_PER_SECTION: usize = 8;
+/// Characters of the chunk kept as the source of a concept.
+const SNIPPET_LENGTH: usize = 300;
+const MAX_TERM_LENGTH: usize = 80;
+
+const CONCEPT_PROMPT: &str = "You build the glossary of a course. List up to {count} key terms \
+that are introduced or explained in the text below, with a definition of one or two sentences \
+based on the text. Use the term as it is written in the text. Answer only with JSON of the form \
+{\"concepts\": [{\"term\": \"...\", \"definition\": \"...\"}]}.";
+
+pub struct ExtractConceptsTask {}
+
+#[derive(Serialize, Deserialize, Clone, Type)]
+pub struct ExtractConceptsTaskInfo {
+    pub file_id: Uuid,
+}
+
+impl Hash for ExtractConceptsTaskInfo {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.file_id.hash(state);
+    }
+}
+
+impl TaskInfo for ExtractConceptsTaskInfo {
+    type Task = ExtractConceptsTask;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ExtractConceptsTaskState {
+    file_rel_path: String,
+    extension: String,
+    file_name: String,
+}
+
+/// A term found in a file, with the chunk that explains it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExtractedConcept {
+    pub term: String,
+    pub definition: String,
+    pub chunk: u32,
+    pub page: Option<u32>,
+    pub snippet: String,
+}
+
+#[async_trait::async_trait]
+impl TaskExec for ExtractConceptsTask {
+    type Info = ExtractConceptsTaskInfo;
+    type Data = ExtractConceptsTaskState;
+    const TYPE: &'static str = "extract_concepts";
+    const NEEDS_SIDECAR: bool = true;
+
+    fn new() -> Self {
+        Self {}
+    }
+
+    fn needs_sidecar(&self, space: &Space, task_info: &TaskState<Self>) -> bool {
+        match &task_info.data {
+            Some(data) => generation_needs_sidecar(space, [data.extension.as_str()]),
+            None => Self::NEEDS_SIDECAR,
+        }
+    }
+
+    async fn setup(
+        &self,
+        space: &Space,
+        task_id: Uuid,
+        task_info: &mut TaskState<Self>,
+    ) -> Result<()> {
+        debug!("extract_concepts::setup");
+        let info = task_info.info.clone();
+
+        let file = space
+            .db
+            .file()
+            .find_first(vec![
+                file::id::equals(u2b(info.file_id)),
+                file::space_id::equals(u2b(space.id)),
+            ])
+            .exec()
+            .await?
+            .context("Failed to find file")?;
+
+        space
+            .db
+            .task()
+            .update(
+                task::id::equals(u2b(task_id)),
+                vec![
+                    task::file::connect(file::id::equals(u2b(info.file_id))),
+                    task::date_modified::set(Utc::now().into()),
+                ],
+            )
+            .exec()
+            .await?;
+
+        if !file.learned {
+            bail!("Concepts can only be extracted from learned files");
+        }
+
+        task_info.data = Some(ExtractConceptsTaskState {
+            file_rel_path: file.path,
+            extension: file.extension,
+            file_name: file.name,
+        });
+
+        Ok(())
+    }
+
+    async fn run(
+        &self,
+        space: &Space,
+        _task_id: Uuid,
+        task_info: &mut TaskState<Self>,
+    ) -> Result<()> {
+        debug!("extract_concepts::run");
+        let file_id = task_info.info.file_id;
+        let data = task_info
+            .data
+            .as_ref()
+            .context("Failed to get concept task data")?;
+
+        let file_path = space.path().await.join(&data.file_rel_path);
+        let chunks = extract_chunks(&space.sidecar(), &file_path, &data.extension).await?;
+        if chunks.is_empty() {
+            bail!("The file has no text to extract concepts from");
+        }
+
+        let llm = space.generator();
+        info!(
+            "Extracting concepts from {} chunks of {:?} with {}",
+            chunks.len(),
+            data.file_name,
+            llm.name()
+        );
+        let concepts = extract_concepts(llm.as_ref(), &data.file_name, chunks).await?;
+        info!("Found {} concepts in {:?}", concepts.len(), data.file_name);
+        space.store_concepts(file_id, concepts).await?;
+
+        Ok(())
+    }
+
+    async fn finish(
+        &self,
+        space: &Space,
+        _task_id: Uuid,
+        _task_info: &mut TaskState<Self>,
+    ) -> Result<()> {
+        info!("extract_concepts::finish");
+        invalidate_query!(space, "concepts.list");
+
+        Ok(())
+    }
+}
+
+/// extract_concepts asks the model for the key terms of every section of the file. Every term is
+/// attributed to the first chunk of its section that mentions it.
+pub async fn extract_concepts(
+    llm: &dyn LlmProvider,
+    name: &str,
+    chunks: Vec<TextChunk>,
+) -> Result<Vec<ExtractedConcept>> {
+    #[derive(Deserialize)]
+    struct Concept {
+        term: String,
+        definition: String,
+    }
+    #[derive(Deserialize)]
+    struct Concepts {
+        concepts: Vec<Concept>,
+    }
+
+    let mut keys = HashSet::new();
+    let mut concepts = vec![];
+    for section in split_sections(chunks) {
+        let text = section
+            .iter()
+            .map(|chunk| chunk.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let prompt = [
+            ChatMessage::system(
+                CONCEPT_PROMPT.replace("{count}", &CONCEPTS_PER_SECTION.to_string()),
+            ),
+            ChatMessage::user(format!(
+                "Source: {}\n\n{}",
+                name,
+                truncate_tokens(&text, SECTION_TOKENS)
+            )),
+        ];
+        let reply = llm.complete(&prompt).await?;
+
+        let Some(parsed) = parse_json::<Concepts>(&reply) else {
+            warn!("Concepts of {:?} were not valid JSON", name);
+            continue;
+        };
+        for concept in parsed.concepts.into_iter().take(CONCEPTS_PER_SECTION) {
+            let term = concept.term.trim();
+            let definition = concept.definition.trim();
+            if term.is_empty() || term.len() > MAX_TERM_LENGTH || definition.is_empty() {
+                continue;
+            }
+            let key = concept_key(term);
+            if !keys.insert(key.clone()) {
+                continue;
+            }
+            let Some(chunk) = section
+                .iter()
+                .find(|chunk| chunk.text.to_lowercase().contains(&key))
+                .or(section.first())
+            else {
+                continue;
+            };
+            concepts.push(ExtractedConcept {
+                term: term.to_string(),
+                definition: definition.to_string(),
+                chunk: chunk.index,
+                page: chunk.page,
+                snippet: chunk.text.chars().take(SNIPPET_LENGTH).collect(),
+            });
+        }
+    }
+
+    Ok(concepts)
+}
+
+/// concept_key is the lowercase term with its whitespace collapsed, a space has one concept per
+/// key.
+pub fn concept_key(term: &str) -> String {
+    term.split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::MockProvider;
+
+    fn chunk(index: u32, text: &str) -> TextChunk {
+        TextChunk {
+            index,
+            page: Some(index + 1),
+            text: text.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn attributes_concepts_to_the_chunk_that_mentions_them() {
+        let llm = MockProvider::new([r#"{"concepts": [
+            {"term": "Gradient descent", "definition": "Minimizes a function by following its negative gradient."},
+            {"term": "gradient  DESCENT", "definition": "Duplicate."},
+            {"term": "Learning rate", "definition": "The step size of gradient descent."},
+            {"term": "", "definition": "No term."}
+        ]}"#]);
+
+        let concepts = extract_concepts(
+            &llm,
+            "cs229.pdf",
+            vec![
+                chunk(0, "Gradient descent takes small steps downhill."),
+                chunk(1, "The learning rate controls how large they are."),
+            ],
+        )
+        .await
+        .expect("failed to extract concepts");
+
+        assert_eq!(
+            concepts,
+            vec![
+                ExtractedConcept {
+                    term: "Gradient descent".to_string(),
+                    definition: "Minimizes a function by following its negative gradient."
+                        .to_string(),
+                    chunk: 0,
+                    page: Some(1),
+                    snippet: "Gradient descent takes small steps downhill.".to_string(),
+                },
+                ExtractedConcept {
+                    term: "Learning rate".to_string(),
+                    definition: "The step size of gradient descent.".to_string(),
+                    chunk: 1,
+                    page: Some(2),
+                    snippet: "The learning rate controls how large they are.".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn keys_ignore_case_and_spacing() {
+        assert_eq!(
+            concept_key(" Support  Vector\nMachine "),
+            "support vector machine"
+        );
+    }
+}
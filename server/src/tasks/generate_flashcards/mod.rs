@@ -0,0 +1,435 @@
+use crate::index::{extract_chunks, TextChunk};
+use crate::llm::tokens::truncate_tokens;
+use crate::llm::{ChatMessage, LlmProvider};
+use crate::utils::{u2b, u2s};
+use crate::{invalidate_query, space::Space};
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use custom_prisma::prisma::{conversation, file, flashcard, space as db_space, task};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::summarize_file::{parse_json, split_sections, SECTION_TOKENS};
+use super::{generation_needs_sidecar, TaskExec, TaskInfo, TaskState};
+
+/// Most cards asked for per section of the source.
+const CARDS_PER_SECTION: usize = 5;
+/// Most cards made in one run.
+const MAX_CARDS: usize = 60;
+
+const FLASHCARD_PROMPT: &str = "You write flashcards for students. Write up to {count} \
+flashcards about the most important facts and ideas in the text below. Every question must be \
+answerable without seeing the text and every answer must be short. Answer only with JSON of the \
+form {\"cards\": [{\"question\": \"...\", \"answer\": \"...\"}]}.";
+
+pub struct GenerateFlashcardsTask {}
+
+/// Makes flashcards from either a learned file or a conversation.
+#[derive(Serialize, Deserialize, Clone, Type)]
+pub struct GenerateFlashcardsTaskInfo {
+    #[specta(optional)]
+    pub file_id: Option<Uuid>,
+    #[specta(optional)]
+    pub conversation_id: Option<Uuid>,
+}
+
+impl Hash for GenerateFlashcardsTaskInfo {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.file_id.hash(state);
+        self.conversation_id.hash(state);
+    }
+}
+
+impl TaskInfo for GenerateFlashcardsTaskInfo {
+    type Task = GenerateFlashcardsTask;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub enum FlashcardSource {
+    File {
+        file_rel_path: String,
+        extension: String,
+        file_name: String,
+    },
+    Conversation {
+        name: String,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GenerateFlashcardsTaskState {
+    source: FlashcardSource,
+}
+
+/// A card written by the model, before it is stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedCard {
+    pub question: String,
+    pub answer: String,
+    pub page: Option<u32>,
+}
+
+#[async_trait::async_trait]
+impl TaskExec for GenerateFlashcardsTask {
+    type Info = GenerateFlashcardsTaskInfo;
+    type Data = GenerateFlashcardsTaskState;
+    const TYPE: &'static str = "generate_flashcards";
+    const NEEDS_SIDECAR: bool = true;
+
+    fn new() -> Self {
+        Self {}
+    }
+
+    fn needs_sidecar(&self, space: &Space, task_info: &TaskState<Self>) -> bool {
+        match task_info.data.as_ref().map(|data| &data.source) {
+            Some(FlashcardSource::File { extension, .. }) => {
+                generation_needs_sidecar(space, [extension.as_str()])
+            }
+            Some(FlashcardSource::Conversation { .. }) => generation_needs_sidecar(space, []),
+            None => Self::NEEDS_SIDECAR,
+        }
+    }
+
+    async fn setup(
+        &self,
+        space: &Space,
+        task_id: Uuid,
+        task_info: &mut TaskState<Self>,
+    ) -> Result<()> {
+        debug!("generate_flashcards::setup");
+        let info = task_info.info.clone();
+
+        let source = match (info.file_id, info.conversation_id) {
+            (Some(file_id), None) => {
+                let file = space
+                    .db
+                    .file()
+                    .find_first(vec![
+                        file::id::equals(u2b(file_id)),
+                        file::space_id::equals(u2b(space.id)),
+                    ])
+                    .exec()
+                    .await?
+                    .context("Failed to find file")?;
+
+                space
+                    .db
+                    .task()
+                    .update(
+                        task::id::equals(u2b(task_id)),
+                        vec![
+                            task::file::connect(file::id::equals(u2b(file_id))),
+                            task::date_modified::set(Utc::now().into()),
+                        ],
+                    )
+                    .exec()
+                    .await?;
+
+                if !file.learned {
+                    bail!("Flashcards can only be made from learned files");
+                }
+                FlashcardSource::File {
+                    file_rel_path: file.path,
+                    extension: file.extension,
+                    file_name: file.name,
+                }
+            }
+            (None, Some(conversation_id)) => {
+                let conversation = space.conversation(conversation_id).await?;
+                FlashcardSource::Conversation {
+                    name: conversation.name,
+                }
+            }
+            _ => bail!("Flashcards are made from either a file or a conversation"),
+        };
+
+        task_info.data = Some(GenerateFlashcardsTaskState { source });
+
+        Ok(())
+    }
+
+    async fn run(
+        &self,
+        space: &Space,
+        _task_id: Uuid,
+        task_info: &mut TaskState<Self>,
+    ) -> Result<()> {
+        debug!("generate_flashcards::run");
+        let info = task_info.info.clone();
+        let data = task_info
+            .data
+            .as_ref()
+            .context("Failed to get flashcard task data")?;
+
+        let (name, chunks) = match &data.source {
+            FlashcardSource::File {
+                file_rel_path,
+                extension,
+                file_name,
+            } => {
+                let file_path = space.path().await.join(file_rel_path);
+                let chunks = extract_chunks(&space.sidecar(), &file_path, extension).await?;
+                (file_name.clone(), chunks)
+            }
+            FlashcardSource::Conversation { name } => {
+                let conversation_id = info
+                    .conversation_id
+                    .context("Failed to get the conversation of the task")?;
+                let conversation = space.conversation(conversation_id).await?;
+                let thread = space.thread(&conversation).await?;
+                let chunks = thread
+                    .messages
+                    .into_iter()
+                    // only answers that were generated successfully
+                    .filter(|message| message.is_user_message || message.response_status == 2)
+                    .enumerate()
+                    .map(|(index, message)| TextChunk {
+                        index: index as u32,
+                        page: None,
+                        text: format!(
+                            "{}: {}",
+                            if message.is_user_message {
+                                "Student"
+                            } else {
+                                "Tutor"
+                            },
+                            message.text
+                        ),
+                    })
+                    .collect::<Vec<_>>();
+                (name.clone(), chunks)
+            }
+        };
+        if chunks.is_empty() {
+            bail!("There is no text to make flashcards from");
+        }
+
+        let mut filters = vec![flashcard::space_id::equals(u2b(space.id))];
+        filters.push(match (info.file_id, info.conversation_id) {
+            (Some(file_id), _) => flashcard::file_id::equals(Some(u2b(file_id))),
+            (None, conversation_id) => flashcard::conversation_id::equals(conversation_id.map(u2b)),
+        });
+        let existing = space
+            .db
+            .flashcard()
+            .find_many(filters)
+            .exec()
+            .await?
+            .into_iter()
+            .map(|card| normalize_question(&card.question))
+            .collect::<HashSet<_>>();
+
+        let llm = space.generator();
+        info!(
+            "Making flashcards from {} chunks of {:?} with {}",
+            chunks.len(),
+            name,
+            llm.name()
+        );
+        let cards = generate_cards(llm.as_ref(), &name, chunks, existing).await?;
+
+        let creates = cards
+            .into_iter()
+            .map(|card| {
+                let id = Uuid::new_v4();
+                let mut params = vec![flashcard::page::set(card.page.map(|page| page as i32))];
+                if let Some(file_id) = info.file_id {
+                    params.push(flashcard::file::connect(file::id::equals(u2b(file_id))));
+                }
+                if let Some(conversation_id) = info.conversation_id {
+                    params.push(flashcard::conversation::connect(conversation::id::equals(
+                        u2b(conversation_id),
+                    )));
+                }
+                space.db.flashcard().create(
+                    u2b(id),
+                    u2s(id),
+                    card.question,
+                    card.answer,
+                    db_space::id::equals(u2b(space.id)),
+                    params,
+                )
+            })
+            .collect::<Vec<_>>();
+        info!("Storing {} new flashcards", creates.len());
+        space.db._batch(creates).await?;
+
+        Ok(())
+    }
+
+    async fn finish(
+        &self,
+        space: &Space,
+        _task_id: Uuid,
+        _task_info: &mut TaskState<Self>,
+    ) -> Result<()> {
+        info!("generate_flashcards::finish");
+        invalidate_query!(space, "flashcards.list");
+        invalidate_query!(space, "flashcards.due");
+
+        Ok(())
+    }
+}
+
+/// generate_cards asks the model for cards on every section of the source, skipping questions that
+/// are already in `existing`.
+pub async fn generate_cards(
+    llm: &dyn LlmProvider,
+    name: &str,
+    chunks: Vec<TextChunk>,
+    mut existing: HashSet<String>,
+) -> Result<Vec<GeneratedCard>> {
+    #[derive(Deserialize)]
+    struct Card {
+        question: String,
+        answer: String,
+    }
+
+    #[derive(Deserialize)]
+    struct Cards {
+        cards: Vec<Card>,
+    }
+
+    let mut cards = vec![];
+    for section in split_sections(chunks) {
+        if cards.len() >= MAX_CARDS {
+            break;
+        }
+        let text = section
+            .iter()
+            .map(|chunk| chunk.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let prompt = [
+            ChatMessage::system(
+                FLASHCARD_PROMPT.replace("{count}", &CARDS_PER_SECTION.to_string()),
+            ),
+            ChatMessage::user(format!(
+                "Source: {}\n\n{}",
+                name,
+                truncate_tokens(&text, SECTION_TOKENS)
+            )),
+        ];
+        let reply = llm.complete(&prompt).await?;
+
+        let Some(parsed) = parse_json::<Cards>(&reply) else {
+            warn!("Flashcards for {:?} were not valid JSON", name);
+            continue;
+        };
+        let page = section.first().and_then(|chunk| chunk.page);
+        for card in parsed.cards.into_iter().take(CARDS_PER_SECTION) {
+            let question = card.question.trim();
+            let answer = card.answer.trim();
+            if question.is_empty() || answer.is_empty() {
+                continue;
+            }
+            if !existing.insert(normalize_question(question)) {
+                continue;
+            }
+            cards.push(GeneratedCard {
+                question: question.to_string(),
+                answer: answer.to_string(),
+                page,
+            });
+        }
+    }
+    cards.truncate(MAX_CARDS);
+
+    Ok(cards)
+}
+
+/// normalize_question is used to spot the same question asked twice.
+fn normalize_question(question: &str) -> String {
+    question
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .trim_end_matches('?')
+        .to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::MockProvider;
+
+    fn chunk(index: u32, text: &str) -> TextChunk {
+        TextChunk {
+            index,
+            page: Some(index + 1),
+            text: text.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn makes_cards_from_the_reply() {
+        let llm = MockProvider::new([r#"Here you go:
+```json
+{"cards": [
+    {"question": "What is osmosis?", "answer": "Diffusion of water through a membrane."},
+    {"question": "Is osmosis passive?", "answer": "Yes."},
+    {"question": "", "answer": "Missing question."}
+]}
+```"#]);
+
+        let cards = generate_cards(
+            &llm,
+            "biology.pdf",
+            vec![chunk(0, "Osmosis is the diffusion of water.")],
+            HashSet::new(),
+        )
+        .await
+        .expect("failed to make flashcards");
+
+        assert_eq!(
+            cards,
+            vec![
+                GeneratedCard {
+                    question: "What is osmosis?".to_string(),
+                    answer: "Diffusion of water through a membrane.".to_string(),
+                    page: Some(1),
+                },
+                GeneratedCard {
+                    question: "Is osmosis passive?".to_string(),
+                    answer: "Yes.".to_string(),
+                    page: Some(1),
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn skips_questions_that_exist() {
+        let llm = MockProvider::new([
+            r#"{"cards": [{"question": "What is  OSMOSIS", "answer": "Diffusion of water."}]}"#,
+        ]);
+        let existing = HashSet::from([normalize_question("What is osmosis?")]);
+
+        let cards = generate_cards(&llm, "biology.pdf", vec![chunk(0, "Osmosis.")], existing)
+            .await
+            .expect("failed to make flashcards");
+
+        assert!(cards.is_empty());
+    }
+
+    #[tokio::test]
+    async fn ignores_replies_that_are_not_json() {
+        let llm = MockProvider::new(["I can't make flashcards from this."]);
+
+        let cards = generate_cards(
+            &llm,
+            "biology.pdf",
+            vec![chunk(0, "Osmosis.")],
+            HashSet::new(),
+        )
+        .await
+        .expect("failed to make flashcards");
+
+        assert!(cards.is_empty());
+    }
+}
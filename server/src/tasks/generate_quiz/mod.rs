@@ -0,0 +1,491 @@
+use crate::index::{extract_chunks, TextChunk};
+use crate::llm::tokens::truncate_tokens;
+use crate::llm::{ChatMessage, LlmProvider};
+use crate::utils::{u2b, u2s};
+use crate::{invalidate_query, space::Space};
+use std::hash::Hash;
+
+use anyhow::{bail, Context, Result};
+use custom_prisma::prisma::{file, space as db_space};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::summarize_file::{parse_json, split_sections, SECTION_TOKENS};
+use super::{generation_needs_sidecar, TaskExec, TaskInfo, TaskState};
+
+const DEFAULT_QUESTIONS: u32 = 10;
+pub const MAX_QUESTIONS: u32 = 30;
+/// Questions asked for per section of the files.
+const QUESTIONS_PER_SECTION: usize = 3;
+/// Tokens of a section quoted as the source of its questions.
+const SNIPPET_TOKENS: usize = 80;
+const MAX_CHOICES: usize = 6;
+
+const QUIZ_PROMPT: &str = "You write exam questions for students. Write {count} questions \
+about the most important ideas in the text below, mixing multiple choice and short answer \
+questions. Multiple choice questions have two to six choices and exactly one of them is right. \
+Short answers fit in a sentence. Answer only with JSON of the form {\"questions\": [\
+{\"kind\": \"multiple_choice\", \"question\": \"...\", \"choices\": [\"...\"], \"answer\": <index of \
+the right choice>, \"explanation\": \"...\"}, {\"kind\": \"short_answer\", \"question\": \"...\", \
+\"answer\": \"...\", \"explanation\": \"...\"}]}.";
+
+pub struct GenerateQuizTask {}
+
+#[derive(Serialize, Deserialize, Clone, Type)]
+pub struct GenerateQuizTaskInfo {
+    pub file_ids: Vec<Uuid>,
+    /// defaults to the names of the files
+    #[specta(optional)]
+    pub name: Option<String>,
+    #[specta(optional)]
+    pub question_count: Option<u32>,
+}
+
+impl Hash for GenerateQuizTaskInfo {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        let mut file_ids = self.file_ids.clone();
+        file_ids.sort();
+        file_ids.hash(state);
+        self.question_count.hash(state);
+    }
+}
+
+impl TaskInfo for GenerateQuizTaskInfo {
+    type Task = GenerateQuizTask;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuizFile {
+    id: Uuid,
+    rel_path: String,
+    extension: String,
+    name: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct GenerateQuizTaskState {
+    name: String,
+    files: Vec<QuizFile>,
+}
+
+#[derive(Serialize, Deserialize, Type, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum QuestionKind {
+    MultipleChoice,
+    ShortAnswer,
+}
+
+/// Where in a file a question was made from.
+#[derive(Serialize, Deserialize, Type, Debug, Clone, PartialEq)]
+pub struct QuizSource {
+    pub file_id: Uuid,
+    pub file_name: String,
+    pub page: Option<u32>,
+    pub snippet: String,
+}
+
+/// A question of a quiz with its answer key.
+#[derive(Serialize, Deserialize, Type, Debug, Clone, PartialEq)]
+pub struct QuizQuestion {
+    pub kind: QuestionKind,
+    pub question: String,
+    /// empty for short answer questions
+    pub choices: Vec<String>,
+    /// index of the right choice of multiple choice questions
+    pub correct_choice: Option<u32>,
+    /// the right choice, or the expected short answer
+    pub answer: String,
+    pub explanation: Option<String>,
+    pub sources: Vec<QuizSource>,
+}
+
+/// The text of one file a quiz is made from.
+pub struct QuizMaterial {
+    pub file_id: Uuid,
+    pub file_name: String,
+    pub chunks: Vec<TextChunk>,
+}
+
+#[async_trait::async_trait]
+impl TaskExec for GenerateQuizTask {
+    type Info = GenerateQuizTaskInfo;
+    type Data = GenerateQuizTaskState;
+    const TYPE: &'static str = "generate_quiz";
+    const NEEDS_SIDECAR: bool = true;
+
+    fn new() -> Self {
+        Self {}
+    }
+
+    fn needs_sidecar(&self, space: &Space, task_info: &TaskState<Self>) -> bool {
+        match &task_info.data {
+            Some(data) => generation_needs_sidecar(
+                space,
+                data.files.iter().map(|file| file.extension.as_str()),
+            ),
+            None => Self::NEEDS_SIDECAR,
+        }
+    }
+
+    async fn setup(
+        &self,
+        space: &Space,
+        _task_id: Uuid,
+        task_info: &mut TaskState<Self>,
+    ) -> Result<()> {
+        debug!("generate_quiz::setup");
+        let info = task_info.info.clone();
+        if info.file_ids.is_empty() {
+            bail!("Select the files to make the quiz from");
+        }
+
+        let files = space
+            .db
+            .file()
+            .find_many(vec![
+                file::id::in_vec(info.file_ids.iter().copied().map(u2b).collect()),
+                file::space_id::equals(u2b(space.id)),
+            ])
+            .exec()
+            .await?;
+        if files.len() != info.file_ids.len() {
+            bail!("Failed to find the files of the quiz");
+        }
+        if let Some(file) = files.iter().find(|file| !file.learned) {
+            bail!("{} has to be learned before it can be quizzed", file.name);
+        }
+
+        let files = files
+            .into_iter()
+            .map(|file| {
+                Ok(QuizFile {
+                    id: Uuid::from_slice(&file.id)?,
+                    rel_path: file.path,
+                    extension: file.extension,
+                    name: file.name,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let name = match info.name.as_deref().map(str::trim) {
+            Some(name) if !name.is_empty() => name.to_string(),
+            _ => default_name(&files),
+        };
+
+        task_info.data = Some(GenerateQuizTaskState { name, files });
+
+        Ok(())
+    }
+
+    async fn run(
+        &self,
+        space: &Space,
+        _task_id: Uuid,
+        task_info: &mut TaskState<Self>,
+    ) -> Result<()> {
+        debug!("generate_quiz::run");
+        let count = task_info
+            .info
+            .question_count
+            .unwrap_or(DEFAULT_QUESTIONS)
+            .clamp(1, MAX_QUESTIONS) as usize;
+        let data = task_info
+            .data
+            .as_ref()
+            .context("Failed to get quiz task data")?;
+
+        let space_path = space.path().await;
+        let mut materials = vec![];
+        for file in &data.files {
+            let file_path = space_path.join(&file.rel_path);
+            let chunks = extract_chunks(&space.sidecar(), &file_path, &file.extension).await?;
+            materials.push(QuizMaterial {
+                file_id: file.id,
+                file_name: file.name.clone(),
+                chunks,
+            });
+        }
+
+        let llm = space.generator();
+        info!(
+            "Making a quiz of {} questions from {} files with {}",
+            count,
+            materials.len(),
+            llm.name()
+        );
+        let questions = generate_questions(llm.as_ref(), materials, count).await?;
+        if questions.is_empty() {
+            bail!("No questions could be made from the files");
+        }
+
+        let id = Uuid::new_v4();
+        space
+            .db
+            .quiz()
+            .create(
+                u2b(id),
+                u2s(id),
+                data.name.clone(),
+                serde_json::to_string(&questions)?,
+                db_space::id::equals(u2b(space.id)),
+                vec![],
+            )
+            .exec()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn finish(
+        &self,
+        space: &Space,
+        _task_id: Uuid,
+        _task_info: &mut TaskState<Self>,
+    ) -> Result<()> {
+        info!("generate_quiz::finish");
+        invalidate_query!(space, "quizzes.list");
+
+        Ok(())
+    }
+}
+
+fn default_name(files: &[QuizFile]) -> String {
+    match files {
+        [file] => format!("Quiz on {}", file.name),
+        [file, rest @ ..] => format!("Quiz on {} and {} more", file.name, rest.len()),
+        [] => "Quiz".to_string(),
+    }
+}
+
+/// generate_questions asks the model for questions on sections spread evenly over the files, until
+/// there are `count` of them.
+pub async fn generate_questions(
+    llm: &dyn LlmProvider,
+    materials: Vec<QuizMaterial>,
+    count: usize,
+) -> Result<Vec<QuizQuestion>> {
+    #[derive(Deserialize)]
+    struct Question {
+        kind: QuestionKind,
+        question: String,
+        #[serde(default)]
+        choices: Vec<String>,
+        answer: serde_json::Value,
+        explanation: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct Questions {
+        questions: Vec<Question>,
+    }
+
+    let sections = interleave(
+        materials
+            .into_iter()
+            .map(|material| {
+                split_sections(material.chunks)
+                    .into_iter()
+                    .map(|section| (material.file_id, material.file_name.clone(), section))
+                    .collect()
+            })
+            .collect(),
+    );
+    let wanted = count.div_ceil(QUESTIONS_PER_SECTION).max(1);
+    let step = (sections.len() / wanted).max(1);
+
+    let mut questions = vec![];
+    for (file_id, file_name, section) in sections.into_iter().step_by(step) {
+        if questions.len() >= count {
+            break;
+        }
+        let text = section
+            .iter()
+            .map(|chunk| chunk.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let prompt = [
+            ChatMessage::system(QUIZ_PROMPT.replace("{count}", &QUESTIONS_PER_SECTION.to_string())),
+            ChatMessage::user(format!(
+                "Source: {}\n\n{}",
+                file_name,
+                truncate_tokens(&text, SECTION_TOKENS)
+            )),
+        ];
+        let reply = llm.complete(&prompt).await?;
+
+        let Some(parsed) = parse_json::<Questions>(&reply) else {
+            warn!("Quiz questions on {:?} were not valid JSON", file_name);
+            continue;
+        };
+        let source = QuizSource {
+            file_id,
+            file_name: file_name.clone(),
+            page: section.first().and_then(|chunk| chunk.page),
+            snippet: truncate_tokens(&text, SNIPPET_TOKENS),
+        };
+        for question in parsed.questions.into_iter().take(QUESTIONS_PER_SECTION) {
+            let text = question.question.trim().to_string();
+            if text.is_empty() {
+                continue;
+            }
+            let explanation = question
+                .explanation
+                .map(|explanation| explanation.trim().to_string())
+                .filter(|explanation| !explanation.is_empty());
+            let question = match question.kind {
+                QuestionKind::MultipleChoice => {
+                    let choices = question
+                        .choices
+                        .into_iter()
+                        .map(|choice| choice.trim().to_string())
+                        .collect::<Vec<_>>();
+                    let Some(correct) = question.answer.as_u64().map(|index| index as usize) else {
+                        continue;
+                    };
+                    if !(2..=MAX_CHOICES).contains(&choices.len()) || correct >= choices.len() {
+                        continue;
+                    }
+                    QuizQuestion {
+                        kind: QuestionKind::MultipleChoice,
+                        question: text,
+                        answer: choices[correct].clone(),
+                        correct_choice: Some(correct as u32),
+                        choices,
+                        explanation,
+                        sources: vec![source.clone()],
+                    }
+                }
+                QuestionKind::ShortAnswer => {
+                    let Some(answer) = question.answer.as_str().map(str::trim) else {
+                        continue;
+                    };
+                    if answer.is_empty() {
+                        continue;
+                    }
+                    QuizQuestion {
+                        kind: QuestionKind::ShortAnswer,
+                        question: text,
+                        choices: vec![],
+                        correct_choice: None,
+                        answer: answer.to_string(),
+                        explanation,
+                        sources: vec![source.clone()],
+                    }
+                }
+            };
+            questions.push(question);
+        }
+    }
+    questions.truncate(count);
+
+    Ok(questions)
+}
+
+/// interleave takes one item of every list in turn, so files are covered evenly.
+fn interleave<T>(lists: Vec<Vec<T>>) -> Vec<T> {
+    let mut iters = lists.into_iter().map(Vec::into_iter).collect::<Vec<_>>();
+    let mut items = vec![];
+    loop {
+        let before = items.len();
+        items.extend(iters.iter_mut().filter_map(Iterator::next));
+        if items.len() == before {
+            return items;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::MockProvider;
+
+    fn material(name: &str, text: &str) -> QuizMaterial {
+        QuizMaterial {
+            file_id: Uuid::new_v4(),
+            file_name: name.to_string(),
+            chunks: vec![TextChunk {
+                index: 0,
+                page: Some(3),
+                text: text.to_string(),
+            }],
+        }
+    }
+
+    #[test]
+    fn interleaves_lists() {
+        assert_eq!(
+            interleave(vec![vec![1, 2, 3], vec![4], vec![5, 6]]),
+            vec![1, 4, 5, 2, 6, 3]
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_valid_questions_with_their_source() {
+        let llm = MockProvider::new([r#"{"questions": [
+            {"kind": "multiple_choice", "question": "What moves in osmosis?", "choices": ["Salt", "Water"], "answer": 1, "explanation": "Only water passes."},
+            {"kind": "multiple_choice", "question": "Broken?", "choices": ["A"], "answer": 3},
+            {"kind": "short_answer", "question": "Is osmosis active or passive?", "answer": "Passive"}
+        ]}"#]);
+        let biology = material("biology.pdf", "Osmosis moves water.");
+        let file_id = biology.file_id;
+
+        let questions = generate_questions(&llm, vec![biology], 10)
+            .await
+            .expect("failed to make questions");
+
+        let source = QuizSource {
+            file_id,
+            file_name: "biology.pdf".to_string(),
+            page: Some(3),
+            snippet: "Osmosis moves water.".to_string(),
+        };
+        assert_eq!(
+            questions,
+            vec![
+                QuizQuestion {
+                    kind: QuestionKind::MultipleChoice,
+                    question: "What moves in osmosis?".to_string(),
+                    choices: vec!["Salt".to_string(), "Water".to_string()],
+                    correct_choice: Some(1),
+                    answer: "Water".to_string(),
+                    explanation: Some("Only water passes.".to_string()),
+                    sources: vec![source.clone()],
+                },
+                QuizQuestion {
+                    kind: QuestionKind::ShortAnswer,
+                    question: "Is osmosis active or passive?".to_string(),
+                    choices: vec![],
+                    correct_choice: None,
+                    answer: "Passive".to_string(),
+                    explanation: None,
+                    sources: vec![source],
+                },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn asks_about_every_file() {
+        let reply = r#"{"questions": [{"kind": "short_answer", "question": "Q?", "answer": "A"}]}"#;
+        let llm = MockProvider::new([reply, reply]);
+
+        let questions = generate_questions(
+            &llm,
+            vec![
+                material("biology.pdf", "Osmosis."),
+                material("chemistry.pdf", "Diffusion."),
+            ],
+            6,
+        )
+        .await
+        .expect("failed to make questions");
+
+        assert_eq!(llm.requests().len(), 2);
+        let files = questions
+            .iter()
+            .map(|question| question.sources[0].file_name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(files, vec!["biology.pdf", "chemistry.pdf"]);
+    }
+}
@@ -1,27 +1,31 @@
 use crate::get_spaces_dir;
-use crate::utils::{python_server_root, u2b};
+use crate::index::{extract_chunks, extracts_natively, IndexBackend, TextChunk};
+use crate::sidecar::{LearnRequest, LearnResponse};
+use crate::utils::{hash_file, u2b};
 use crate::{api::CoreEvent, invalidate_query, space::Space};
 use std::env;
 use std::hash::{Hash, Hasher};
+use std::path::Path;
 use std::vec;
 
 use chrono::Utc;
 use custom_prisma::prisma::{file, space as db_space, task};
-use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use specta::Type;
 
-use anyhow::bail;
 use anyhow::{Context, Result};
 use axum;
 use std::fs::metadata;
 
 use std::time::{Duration, Instant};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 use uuid::Uuid;
 
-use super::{TaskExec, TaskInfo, TaskState};
+use super::{
+    extract_concepts::ExtractConceptsTaskInfo, summarize_file::SummarizeFileTaskInfo,
+    unlearn_file::unlearn, IntoTask, TaskExec, TaskInfo, TaskState,
+};
 
 pub struct LearnFileTask {}
 
@@ -40,15 +44,12 @@ impl TaskInfo for LearnFileTaskInfo {
     type Task = LearnFileTask;
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct LearnRequest {
-    vector_db_path: String,
-    file_path: String,
-}
-
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LearnFileTaskState {
     file_rel_path: String,
+    extension: String,
+    #[serde(default)]
+    file_name: String,
 }
 
 #[async_trait::async_trait]
@@ -56,11 +57,20 @@ impl TaskExec for LearnFileTask {
     type Info = LearnFileTaskInfo;
     type Data = LearnFileTaskState;
     const TYPE: &'static str = "learn_file";
+    const NEEDS_SIDECAR: bool = true;
 
     fn new() -> Self {
         Self {}
     }
 
+    fn needs_sidecar(&self, _space: &Space, task_info: &TaskState<Self>) -> bool {
+        // the native index only needs the Python server to extract pdfs and the like
+        match (IndexBackend::from_env(), &task_info.data) {
+            (IndexBackend::Native, Some(data)) => !extracts_natively(&data.extension),
+            _ => Self::NEEDS_SIDECAR,
+        }
+    }
+
     async fn setup(
         &self,
         space: &Space,
@@ -95,6 +105,8 @@ impl TaskExec for LearnFileTask {
         let file_path = file.path.clone();
         task_info.data = Some(LearnFileTaskState {
             file_rel_path: file_path,
+            extension: file.extension,
+            file_name: file.name,
         });
 
         Ok(())
@@ -117,33 +129,83 @@ impl TaskExec for LearnFileTask {
         let space_base_path = get_spaces_dir().await;
         let space_path = space_base_path.join(space.id.to_string());
 
-        let vector_db_path = space_path.join("vector_db");
         let file_path = space_path.join(file_path);
-        // create if not exists
-        if !vector_db_path.exists() {
-            std::fs::create_dir(&vector_db_path)?;
-        }
 
-        let learn_request = LearnRequest {
-            vector_db_path: vector_db_path.to_string_lossy().into_owned(),
-            file_path: file_path.to_string_lossy().into_owned(),
-        };
+        // taken before reading the content so a write racing with the learn marks the file stale again
+        let indexed_at = Utc::now();
+        let content_hash = hash_file(&file_path).await?;
 
-        debug!("Sending learn request: {:?}", learn_request);
+        let text = match IndexBackend::from_env() {
+            IndexBackend::Native => {
+                let file_id = task_info.info.file_id;
+                let chunks = extract_chunks(&space.sidecar(), &file_path, &data.extension).await?;
+                debug!("Extracted {} chunks from {:?}", chunks.len(), file_path);
+                let text = chunks.clone();
 
-        let endpoint = python_server_root() + "/learn";
+                let index = space.index().await?;
 
-        let client = Client::new();
-        let res = client
-            .post(&endpoint)
-            .json(&learn_request)
-            .send()
-            .await
-            .context("Failed to send learn request")?;
+                // only embed the chunks whose text isn't already in the index
+                let mut known = index.read().await.vectors_by_hash(file_id);
+                let missing = chunks
+                    .iter()
+                    .filter(|chunk| !known.contains_key(&chunk.hash()))
+                    .collect::<Vec<_>>();
+                debug!(
+                    "Embedding {} of {} chunks ({} unchanged)",
+                    missing.len(),
+                    chunks.len(),
+                    chunks.len() - missing.len()
+                );
 
-        if !res.status().is_success() {
-            bail!("Failed to learn file");
-        }
+                let texts = missing.iter().map(|c| c.text.clone()).collect::<Vec<_>>();
+                let hashes = missing.iter().map(|c| c.hash()).collect::<Vec<_>>();
+                let vectors = space.index_manager().embedder.embed(&texts).await?;
+                known.extend(hashes.into_iter().zip(vectors));
+
+                let chunks = chunks
+                    .into_iter()
+                    .map(|chunk| {
+                        let vector = known
+                            .get(&chunk.hash())
+                            .cloned()
+                            .context("Missing embedding for chunk")?;
+                        Ok((chunk, vector))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                let mut index = index.write().await;
+                index.remove_file(file_id);
+                index.add_chunks(file_id, chunks)?;
+                index.save().await?;
+                Some(text)
+            }
+            IndexBackend::Sidecar => {
+                // the Python server only learns whole files, so unlike the native index a changed
+                // file is embedded again in full. Drop whatever a previous learn left behind so
+                // edited files don't keep stale chunks.
+                let removed = unlearn(space, task_info.info.file_id, &file_path).await?;
+                if removed > 0 {
+                    debug!("Removed {} stale chunks before re-learning", removed);
+                }
+
+                let vector_db_path = space_path.join("vector_db");
+                // create if not exists
+                if !vector_db_path.exists() {
+                    std::fs::create_dir(&vector_db_path)?;
+                }
+
+                let learn_request = LearnRequest {
+                    vector_db_path: vector_db_path.to_string_lossy().into_owned(),
+                    file_path: file_path.to_string_lossy().into_owned(),
+                };
+                space
+                    .sidecar()
+                    .post::<_, LearnResponse>("/learn", &learn_request)
+                    .await
+                    .context("Failed to learn file")?;
+                None
+            }
+        };
 
         // set file.learned to true
         space
@@ -151,11 +213,53 @@ impl TaskExec for LearnFileTask {
             .file()
             .update(
                 file::id::equals(u2b(task_info.info.file_id)),
-                vec![file::learned::set(true)],
+                vec![
+                    file::learned::set(true),
+                    file::stale::set(false),
+                    file::content_hash::set(Some(content_hash)),
+                    file::date_indexed::set(indexed_at.into()),
+                ],
             )
             .exec()
             .await?;
 
+        index_text(
+            space,
+            task_info.info.file_id,
+            data.file_name.clone(),
+            text,
+            &file_path,
+            &data.extension,
+        )
+        .await;
+
+        if space.meta.summarize_files {
+            let summarize = SummarizeFileTaskInfo {
+                file_id: task_info.info.file_id,
+            };
+            if let Err(e) = space
+                .dispatcher
+                .clone()
+                .dispatch(space, summarize.runnable())
+                .await
+            {
+                warn!("Failed to summarize learned file: {:?}", e);
+            }
+        }
+        if space.meta.extract_concepts {
+            let extract = ExtractConceptsTaskInfo {
+                file_id: task_info.info.file_id,
+            };
+            if let Err(e) = space
+                .dispatcher
+                .clone()
+                .dispatch(space, extract.runnable())
+                .await
+            {
+                warn!("Failed to extract concepts of learned file: {:?}", e);
+            }
+        }
+
         Ok(())
     }
     async fn finish(
@@ -170,3 +274,35 @@ impl TaskExec for LearnFileTask {
         Ok(())
     }
 }
+
+/// index_text adds the text of the learned file to the search index. Failures are only logged, the
+/// file is learned either way.
+async fn index_text(
+    space: &Space,
+    file_id: Uuid,
+    name: String,
+    chunks: Option<Vec<TextChunk>>,
+    file_path: &Path,
+    extension: &str,
+) {
+    let chunks = match chunks {
+        Some(chunks) => Ok(chunks),
+        // the Python server doesn't hand back the text it learned
+        None => extract_chunks(&space.sidecar(), file_path, extension).await,
+    };
+    let res = match chunks {
+        Ok(chunks) => {
+            space
+                .search()
+                .index_file(space.id, file_id, name, chunks)
+                .await
+        }
+        Err(e) => Err(e),
+    };
+    if let Err(e) = res {
+        warn!(
+            "Failed to add file {} to the search index: {:?}",
+            file_id, e
+        );
+    }
+}
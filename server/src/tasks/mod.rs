@@ -1,5 +1,6 @@
 use crate::{
     api::{file_with_tasks, message_with_tasks_and_peer, task_with_file, CoreEvent},
+    index::extracts_natively,
     space::Space,
     utils::{u2b, u2s},
 };
@@ -21,8 +22,13 @@ use anyhow::{Context, Result};
 use self::dispatcher::Dispatcher;
 
 pub mod dispatcher;
+pub mod extract_concepts;
+pub mod generate_flashcards;
+pub mod generate_quiz;
 pub mod learn_file;
 pub mod reply;
+pub mod summarize_file;
+pub mod unlearn_file;
 pub mod upload_file;
 
 pub trait TaskInfo: Serialize + DeserializeOwned + Send + Sync + Hash {
@@ -41,9 +47,18 @@ pub trait TaskExec: Send + Sync + Sized {
     type Info: TaskInfo<Task = Self>;
     type Data: Serialize + DeserializeOwned + Send + Sync;
     const TYPE: &'static str;
+    /// Tasks talking to the Python server are held back while a supervised server is unhealthy.
+    const NEEDS_SIDECAR: bool = false;
 
     fn new() -> Self;
 
+    /// needs_sidecar is checked once the task is set up, so tasks that only need the Python server
+    /// for some backends, providers or files can tell from their data. Defaults to
+    /// [`TaskExec::NEEDS_SIDECAR`].
+    fn needs_sidecar(&self, _space: &Space, _task_info: &TaskState<Self>) -> bool {
+        Self::NEEDS_SIDECAR
+    }
+
     async fn setup(
         &self,
         space: &Space,
@@ -66,6 +81,18 @@ pub trait TaskExec: Send + Sync + Sized {
     ) -> Result<()>;
 }
 
+/// generation_needs_sidecar tells whether generating text from files with the extensions goes through
+/// the Python server, for its chat model or to extract the text of the files.
+pub(crate) fn generation_needs_sidecar<'a>(
+    space: &Space,
+    extensions: impl IntoIterator<Item = &'a str>,
+) -> bool {
+    space.llm().is_none()
+        || extensions
+            .into_iter()
+            .any(|extension| !extracts_natively(extension))
+}
+
 #[async_trait::async_trait]
 pub trait DTask: Send + Sync {
     fn id(&self) -> Uuid;
@@ -73,6 +100,7 @@ pub trait DTask: Send + Sync {
     fn space_id(&self) -> Option<Uuid>;
 
     fn task_type(&self) -> &'static str;
+    fn needs_sidecar(&self, space: &Space) -> bool;
     async fn setup(&mut self, space: &Space, dispatcher: Arc<Dispatcher>) -> Result<()>;
     async fn run(&mut self, space: &Space, dispatcher: Arc<Dispatcher>) -> Result<()>;
     async fn finish(
@@ -157,6 +185,10 @@ impl<T: TaskExec> DTask for Task<T> {
         <T as TaskExec>::TYPE
     }
 
+    fn needs_sidecar(&self, space: &Space) -> bool {
+        self.task_with_state.needs_sidecar(space, &self.task_info)
+    }
+
     fn hash(&self) -> u64 {
         <T::Info as TaskInfo>::hash(&self.task_info.info)
     }
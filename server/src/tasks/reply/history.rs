@@ -0,0 +1,363 @@
+use crate::llm::tokens::{count_tokens, truncate_tokens};
+use crate::llm::{ChatMessage, LlmProvider};
+use crate::space::{branch::path_to, Space};
+use crate::utils::u2b;
+use anyhow::{Context, Result};
+use custom_prisma::prisma::{conversation, message};
+use std::collections::HashMap;
+use std::env;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use super::ChatHistoryEntry;
+
+/// Tokens the chat history may take up in a prompt unless `CHAT_HISTORY_TOKENS` says otherwise.
+const DEFAULT_HISTORY_TOKENS: usize = 2000;
+/// The summary of older turns gets at most a quarter of the budget.
+const SUMMARY_SHARE: usize = 4;
+/// Tokens the role markers of a turn take up in a chat prompt.
+const TURN_OVERHEAD: usize = 8;
+/// Most previous turns that are looked at, older ones are neither sent nor summarized.
+const MAX_TURNS: usize = 50;
+
+const SUMMARY_PROMPT: &str = "Summarize the conversation below between a user and a study \
+assistant in a few sentences. Keep the topics, facts and open questions the user may refer back \
+to. Only answer with the summary.";
+
+/// ChatHistory is what a reply gets to know about the previous turns of the conversation.
+#[derive(Debug, Default)]
+pub struct ChatHistory {
+    /// summary of the turns that did not fit into the budget
+    pub summary: Option<String>,
+    /// the most recent turns, oldest first
+    pub turns: Vec<ChatHistoryEntry>,
+}
+
+impl ChatHistory {
+    /// entries returns the history in the format the Python server expects, with the summary as the first turn.
+    pub fn entries(&self) -> Vec<ChatHistoryEntry> {
+        let summary = self.summary.iter().map(|summary| ChatHistoryEntry {
+            HUMAN: "What did we talk about earlier?".to_string(),
+            AI: summary.clone(),
+        });
+        summary.chain(self.turns.iter().cloned()).collect()
+    }
+}
+
+/// history_budget returns the token budget of the chat history, set with `CHAT_HISTORY_TOKENS`.
+pub fn history_budget() -> usize {
+    env::var("CHAT_HISTORY_TOKENS")
+        .ok()
+        .and_then(|budget| budget.parse().ok())
+        .unwrap_or(DEFAULT_HISTORY_TOKENS)
+}
+
+/// build_history collects the turns before `message_id` that fit into `budget` tokens and
+/// summarizes the older ones. The summary is stored on the conversation and reused until the turns
+/// after it no longer fit, so it isn't generated again for every reply.
+pub async fn build_history(space: &Space, message_id: Uuid, budget: usize) -> Result<ChatHistory> {
+    let message = space
+        .db
+        .message()
+        .find_unique(message::id::equals(u2b(message_id)))
+        .exec()
+        .await?
+        .context("Failed to find the message to reply to")?;
+
+    let (answer_ids, turns): (Vec<_>, Vec<_>) =
+        load_turns(space, &message).await?.into_iter().unzip();
+    let total = turns.iter().map(turn_tokens).sum::<usize>();
+    if total <= budget {
+        return Ok(ChatHistory {
+            summary: None,
+            turns,
+        });
+    }
+
+    let summary_budget = budget / SUMMARY_SHARE;
+    let recent_budget = budget - summary_budget;
+
+    let conversation = match &message.conversation_id {
+        Some(id) => {
+            space
+                .db
+                .conversation()
+                .find_unique(conversation::id::equals(id.clone()))
+                .exec()
+                .await?
+        }
+        None => None,
+    };
+    let cached = conversation.as_ref().and_then(|conversation| {
+        cached_history(
+            conversation.summary.as_deref()?,
+            conversation.summary_head_id.as_deref()?,
+            &answer_ids,
+            &turns,
+            recent_budget,
+        )
+    });
+    if let Some(history) = cached {
+        debug!(
+            "Reusing the chat history summary, {} turns follow it",
+            history.turns.len()
+        );
+        return Ok(history);
+    }
+
+    // leave the recent turns room to grow, so the summary can be reused for the next replies
+    let (older, recent) = split_recent(turns, recent_budget / 2);
+    debug!(
+        "Chat history has {} tokens, summarizing {} older turns",
+        total,
+        older.len()
+    );
+    if older.is_empty() || summary_budget == 0 {
+        return Ok(ChatHistory {
+            summary: None,
+            turns: recent,
+        });
+    }
+
+    let summary = match summarize(space.generator().as_ref(), &older, summary_budget).await {
+        Ok(summary) => {
+            if let Some(conversation) = &conversation {
+                let head_id = answer_ids[older.len() - 1].clone();
+                let res = space
+                    .db
+                    .conversation()
+                    .update(
+                        conversation::id::equals(conversation.id.clone()),
+                        vec![
+                            conversation::summary::set(Some(summary.clone())),
+                            conversation::summary_head_id::set(Some(head_id)),
+                        ],
+                    )
+                    .exec()
+                    .await;
+                if let Err(e) = res {
+                    warn!("Failed to store the chat history summary: {:?}", e);
+                }
+            }
+            Some(summary)
+        }
+        Err(e) => {
+            warn!("Failed to summarize the chat history: {:?}", e);
+            list_questions(&older, summary_budget)
+        }
+    };
+
+    Ok(ChatHistory {
+        summary,
+        turns: recent,
+    })
+}
+
+/// load_turns pairs the questions on the branch leading to `message` with their answers, oldest
+/// first, keyed by the id of the answer. Turns that were not answered successfully are left out.
+async fn load_turns(
+    space: &Space,
+    message: &message::Data,
+) -> Result<Vec<(Vec<u8>, ChatHistoryEntry)>> {
+    let messages = space
+        .db
+        .message()
+        .find_many(vec![
+            message::space_id::equals(u2b(space.id)),
+            message::conversation_id::equals(message.conversation_id.clone()),
+        ])
+        .exec()
+        .await?;
+    let parents = messages
+        .iter()
+        .map(|message| (message.id.clone(), message.parent_id.clone()))
+        .collect::<HashMap<_, _>>();
+    let mut messages = messages
+        .into_iter()
+        .map(|message| (message.id.clone(), message))
+        .collect::<HashMap<_, _>>();
+
+    let mut path = path_to(&parents, &message.id)
+        .into_iter()
+        .filter_map(|id| messages.remove(&id))
+        .peekable();
+    // the question being answered is the last message of the path
+    let mut turns = vec![];
+    while let Some(question) = path.next() {
+        if !question.is_user_message || question.id == message.id {
+            continue;
+        }
+        let Some(answer) = path.next_if(|answer| !answer.is_user_message) else {
+            continue;
+        };
+        if answer.response_status == 2 {
+            turns.push((
+                answer.id,
+                ChatHistoryEntry {
+                    HUMAN: question.text,
+                    AI: answer.text,
+                },
+            ));
+        }
+    }
+
+    let skip = turns.len().saturating_sub(MAX_TURNS);
+    Ok(turns.split_off(skip))
+}
+
+/// cached_history reuses the stored summary when it covers the turns up to the answer `head_id`
+/// and the turns after it fit into `budget` tokens. `answer_ids` are the ids of the answers of
+/// `turns`, a summary of another branch is not found among them.
+fn cached_history(
+    summary: &str,
+    head_id: &[u8],
+    answer_ids: &[Vec<u8>],
+    turns: &[ChatHistoryEntry],
+    budget: usize,
+) -> Option<ChatHistory> {
+    let start = answer_ids.iter().position(|id| id.as_slice() == head_id)? + 1;
+    let recent = turns.get(start..)?;
+    if recent.iter().map(turn_tokens).sum::<usize>() > budget {
+        return None;
+    }
+
+    Some(ChatHistory {
+        summary: Some(summary.to_string()),
+        turns: recent.to_vec(),
+    })
+}
+
+fn turn_tokens(turn: &ChatHistoryEntry) -> usize {
+    count_tokens(&turn.HUMAN) + count_tokens(&turn.AI) + TURN_OVERHEAD
+}
+
+/// split_recent splits the turns into the older ones and the most recent ones that fit into `budget` tokens.
+fn split_recent(
+    mut turns: Vec<ChatHistoryEntry>,
+    budget: usize,
+) -> (Vec<ChatHistoryEntry>, Vec<ChatHistoryEntry>) {
+    let mut used = 0;
+    let mut split = turns.len();
+    for turn in turns.iter().rev() {
+        used += turn_tokens(turn);
+        if used > budget {
+            break;
+        }
+        split -= 1;
+    }
+
+    let recent = turns.split_off(split);
+    (turns, recent)
+}
+
+/// summarize condenses the turns into at most `budget` tokens.
+async fn summarize(
+    llm: &dyn LlmProvider,
+    turns: &[ChatHistoryEntry],
+    budget: usize,
+) -> Result<String> {
+    let transcript = turns
+        .iter()
+        .map(|turn| format!("User: {}\nAssistant: {}", turn.HUMAN, turn.AI))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let prompt = [
+        ChatMessage::system(SUMMARY_PROMPT),
+        // leave the model room to read, but don't send it the whole space
+        ChatMessage::user(truncate_tokens(&transcript, budget * 8)),
+    ];
+    let summary = llm.complete(&prompt).await?;
+
+    Ok(truncate_tokens(summary.trim(), budget))
+}
+
+/// list_questions lists the most recent questions of `turns` that fit into `budget` tokens.
+fn list_questions(turns: &[ChatHistoryEntry], budget: usize) -> Option<String> {
+    let intro = "Earlier, the user asked:";
+    let mut used = count_tokens(intro);
+    let mut questions = vec![];
+    for turn in turns.iter().rev() {
+        let question = format!("\n- {}", turn.HUMAN.trim());
+        used += count_tokens(&question);
+        if used > budget {
+            break;
+        }
+        questions.push(question);
+    }
+
+    if questions.is_empty() {
+        return None;
+    }
+    questions.reverse();
+    Some(format!("{intro}{}", questions.concat()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::MockProvider;
+
+    fn turn(human: &str, ai: &str) -> ChatHistoryEntry {
+        ChatHistoryEntry {
+            HUMAN: human.to_string(),
+            AI: ai.to_string(),
+        }
+    }
+
+    #[test]
+    fn keeps_the_most_recent_turns_within_budget() {
+        let turns = vec![
+            turn(
+                "What is osmosis?",
+                &"Osmosis is diffusion of water. ".repeat(20),
+            ),
+            turn("And diffusion?", "Particles spreading out."),
+            turn("Give an example", "Ink in water."),
+        ];
+        let budget = turn_tokens(&turns[1]) + turn_tokens(&turns[2]);
+
+        let (older, recent) = split_recent(turns, budget);
+
+        assert_eq!(older.len(), 1);
+        assert_eq!(older[0].HUMAN, "What is osmosis?");
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].HUMAN, "And diffusion?");
+        assert_eq!(recent[1].HUMAN, "Give an example");
+    }
+
+    #[tokio::test]
+    async fn summarizes_older_turns() {
+        let turns = vec![turn("What is osmosis?", "Diffusion of water.")];
+
+        let llm = MockProvider::new(vec!["The user asked about osmosis.".to_string()]);
+        let summary = summarize(&llm, &turns, 100).await.expect("mock completes");
+        assert_eq!(summary, "The user asked about osmosis.");
+
+        assert_eq!(
+            list_questions(&turns, 100).as_deref(),
+            Some("Earlier, the user asked:\n- What is osmosis?")
+        );
+    }
+
+    #[test]
+    fn reuses_the_summary_while_later_turns_fit() {
+        let turns = vec![
+            turn("What is osmosis?", "Diffusion of water."),
+            turn("And diffusion?", "Particles spreading out."),
+            turn("Give an example", "Ink in water."),
+        ];
+        let answer_ids = vec![vec![1], vec![2], vec![3]];
+        let budget = turn_tokens(&turns[1]) + turn_tokens(&turns[2]);
+
+        let history = cached_history("Osmosis.", &[1], &answer_ids, &turns, budget)
+            .expect("the turns after the summary fit");
+        assert_eq!(history.summary.as_deref(), Some("Osmosis."));
+        assert_eq!(history.turns.len(), 2);
+        assert_eq!(history.turns[0].HUMAN, "And diffusion?");
+
+        // the turns after the summary outgrew the budget
+        assert!(cached_history("Osmosis.", &[1], &answer_ids, &turns, budget - 1).is_none());
+        // the summary belongs to another branch
+        assert!(cached_history("Osmosis.", &[9], &answer_ids, &turns, budget).is_none());
+    }
+}
@@ -1,40 +1,56 @@
-use crate::api::message_with_tasks_and_peer;
+use crate::custom_uri::file_link;
 use crate::get_spaces_dir;
-use crate::utils::{python_server_root, u2b, u2s};
-use crate::{api::CoreEvent, invalidate_query, space::Space};
+use crate::index::{IndexBackend, RetrievalMode, ScoredChunk};
+use crate::llm::sse::{SseEvent, SseStream};
+use crate::llm::{ChatMessage, LlmProvider};
+use crate::sidecar::{
+    AskContext, AskRequest, AskSource, AskStreamDone, AskStreamError, AskStreamToken,
+};
+use crate::utils::{u2b, u2s};
+use crate::{
+    api::CoreEvent,
+    invalidate_query,
+    space::{message_document, Space},
+};
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::hash::{Hash, Hasher};
 use std::vec;
 
 use custom_prisma::prisma::message::{self};
-use custom_prisma::prisma::{file, space as db_space, task};
-use reqwest::Client;
+use custom_prisma::prisma::{conversation, file, message_source, space as db_space, task};
 use serde::{Deserialize, Serialize};
 use specta::Type;
 
-use anyhow::bail;
 use anyhow::{Context, Result};
 use std::fs::metadata;
 use std::time::{Duration, Instant};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 use uuid::Uuid;
 
 use super::{TaskExec, TaskInfo, TaskState};
 
+mod history;
+mod prompt;
+mod scope;
+mod style;
+
+use history::{build_history, history_budget};
+use prompt::build_prompt;
+use scope::scoped_files;
+pub use scope::MessageScope;
+pub use style::{AnswerLength, AnswerStyle};
+
 pub struct ReplyTask {}
 
 #[derive(Serialize, Deserialize, Clone, Type)]
 pub struct ReplyTaskInfo {
     pub message_id: Uuid,
     pub message_text: String,
-}
-
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct AskRequest {
-    vector_db_path: String,
-    question: String,
-    chat_history: String,
+    /// answer only from these files instead of the whole space
+    #[serde(default)]
+    pub scope: Option<MessageScope>,
 }
 
 // JSON:
@@ -48,13 +64,6 @@ pub struct ChatHistoryEntry {
     pub AI: String,
 }
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
-pub struct AskResponse {
-    pub success: bool,
-    pub response_error: Option<String>,
-    pub result: Option<String>,
-}
-
 impl Hash for ReplyTaskInfo {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.message_id.hash(state);
@@ -72,6 +81,8 @@ pub struct ReplyTaskState {
     message_text: String,
     response_text: Option<String>,
     response_error: Option<String>,
+    #[serde(default)]
+    sources: Vec<AskSource>,
 }
 
 #[async_trait::async_trait]
@@ -79,11 +90,17 @@ impl TaskExec for ReplyTask {
     type Info = ReplyTaskInfo;
     type Data = ReplyTaskState;
     const TYPE: &'static str = "reply";
+    const NEEDS_SIDECAR: bool = true;
 
     fn new() -> Self {
         Self {}
     }
 
+    fn needs_sidecar(&self, space: &Space, _task_info: &TaskState<Self>) -> bool {
+        // with the native index and a provider the node answers by itself
+        IndexBackend::from_env() == IndexBackend::Sidecar || space.llm().is_none()
+    }
+
     async fn setup(
         &self,
         space: &Space,
@@ -95,7 +112,7 @@ impl TaskExec for ReplyTask {
         let info = task_info.info.clone();
         let message_id = info.message_id;
         // attach the task to the message
-        let response_message_data = space
+        let message_data = space
             .db
             .message()
             .update(
@@ -121,18 +138,35 @@ impl TaskExec for ReplyTask {
                     message::is_user_message::set(false),
                     message::response_status::set(1),
                     message::user_message::connect(message::id::equals(u2b(message_id))),
+                    message::parent::connect(message::id::equals(u2b(message_id))),
                     message::tasks::connect(vec![task::id::equals(u2b(task_id))]),
-                ],
+                ]
+                .into_iter()
+                // the answer belongs to the conversation of the question
+                .chain(
+                    message_data
+                        .conversation_id
+                        .clone()
+                        .map(|id| message::conversation::connect(conversation::id::equals(id))),
+                )
+                .collect(),
             )
             .exec()
             .await?;
 
+        if let Some(conversation_id) = message_data.conversation_id {
+            space
+                .set_conversation_head(conversation_id, response_message_data.id.clone())
+                .await?;
+        }
+
         task_info.data = Some(ReplyTaskState {
             message_id,
             response_message_id,
             message_text: info.message_text,
             response_text: None,
             response_error: None,
+            sources: vec![],
         });
 
         Ok(())
@@ -154,71 +188,100 @@ impl TaskExec for ReplyTask {
         let space_path = space_base_path.join(space.id.to_string());
         let vector_db_path = space_path.join("vector_db");
 
-        let mut chat_history = space
-            .db
-            .message()
-            .find_many(vec![
-                message::is_user_message::equals(true),
-                message::response_status::equals(2),
-                message::space_id::equals(u2b(space.id)),
-            ])
-            // .include(message_with_tasks_and_peer::include())
-            .order_by(message::date_created::order(
-                custom_prisma::prisma::SortOrder::Desc,
-            ))
-            .take(10)
-            .include(message_with_tasks_and_peer::include())
-            .exec()
-            .await?;
-        // now we need to reverse the order of the messages
-        let mut chat_history = chat_history.into_iter().rev().collect::<Vec<_>>();
-
-        // now we need to format the chat history into the format that the vector db expects
-        let chat_history = chat_history
-            .iter_mut()
-            .map(|message| {
-                // if the message has a response, the response is the AI message. otherwise it's an empty string
-                // the human message is always the message text
-                ChatHistoryEntry {
-                    HUMAN: message.clone().text.clone(),
-                    AI: message
-                        .clone()
-                        .response_message
-                        .map(|response| response.text.clone())
-                        .unwrap_or("".to_string()),
-                }
-            })
-            .collect::<Vec<_>>();
+        let chat_history = build_history(space, data.message_id, history_budget()).await?;
 
-        let mut chat_history = serde_json::to_string(&chat_history)
-            .context("Failed to serialize chat history to json")?;
-
-        let ask_request = AskRequest {
-            vector_db_path: vector_db_path.to_string_lossy().into_owned(),
-            question: data.message_text.clone(),
-            chat_history: chat_history,
+        let files = match &task_info.info.scope {
+            Some(scope) if !scope.is_empty() => Some(scoped_files(space, scope).await?),
+            _ => None,
+        };
+        if let Some(files) = &files {
+            debug!("Answering from {} files", files.len());
+        }
+
+        let mut sources = vec![];
+        let context = match IndexBackend::from_env() {
+            IndexBackend::Native => {
+                let mode = RetrievalMode::parse(&space.meta.retrieval_mode);
+                let file_ids = files.as_ref().map(|files| {
+                    files
+                        .iter()
+                        .filter_map(|file| Uuid::from_slice(&file.id).ok())
+                        .collect::<HashSet<_>>()
+                });
+                let hits = space
+                    .index_manager()
+                    .search(space.id, &data.message_text, 10, mode, file_ids.as_ref())
+                    .await?;
+                debug!(
+                    "Retrieved {} chunks from the space index ({})",
+                    hits.len(),
+                    mode
+                );
+
+                sources = hits.iter().map(chunk_source).collect();
+
+                Some(
+                    hits.into_iter()
+                        .map(|hit| AskContext {
+                            text: hit.chunk.text,
+                            file_id: hit.chunk.file_id,
+                            page: hit.chunk.page,
+                        })
+                        .collect(),
+                )
+            }
+            IndexBackend::Sidecar => None,
         };
 
-        debug!("Sending ask request: {:?}", ask_request);
-
-        let endpoint = python_server_root() + "/ask";
+        let style = AnswerStyle::from_meta(&space.meta);
+        let reply = match (space.llm(), context) {
+            (Some(llm), Some(context)) => {
+                debug!("Generating reply with {}", llm.name());
+                let prompt = build_prompt(&data.message_text, &chat_history, &context, &style);
+                generate(space, data.response_message_id, llm.as_ref(), &prompt).await
+            }
+            (llm, context) => {
+                if let Some(llm) = llm {
+                    warn!(
+                        "{} needs the native index to retrieve context, asking the Python server instead",
+                        llm.name()
+                    );
+                }
 
-        let client = Client::new();
-        let res = client
-            .post(endpoint)
-            .json(&ask_request)
-            .send()
-            .await
-            .context("Failed to send ask request")?;
+                let chat_history = serde_json::to_string(&chat_history.entries())
+                    .context("Failed to serialize chat history to json")?;
+                let ask_request = AskRequest {
+                    vector_db_path: vector_db_path.to_string_lossy().into_owned(),
+                    question: data.message_text.clone(),
+                    chat_history,
+                    context,
+                    source_paths: files.map(|files| {
+                        files
+                            .iter()
+                            .map(|file| space_path.join(&file.path).to_string_lossy().into_owned())
+                            .collect()
+                    }),
+                    // keep the Python server's own prompt unless the space changed the style
+                    instructions: (!style.is_default()).then(|| style.instructions(false)),
+                };
+                ask(space, data.response_message_id, &ask_request)
+                    .await
+                    .unwrap_or_else(|e| StreamedReply::failed(format!("{e:#}")))
+            }
+        };
 
-        let ask_response: AskResponse = res.json().await.context("Failed to parse ask response")?;
+        // the native index already knows what it retrieved, the Chroma store reports it back
+        if sources.is_empty() {
+            sources = reply.sources;
+        }
 
         task_info.data = Some(ReplyTaskState {
             message_id: data.message_id,
             response_message_id: data.response_message_id,
             message_text: data.message_text.clone(),
-            response_text: ask_response.result,
-            response_error: ask_response.response_error,
+            response_text: reply.text,
+            response_error: reply.error,
+            sources,
         });
 
         Ok(())
@@ -237,6 +300,10 @@ impl TaskExec for ReplyTask {
             .context("Failed to get upload task data")?;
 
         let response = data.response_text.clone();
+        // run failed before anything was generated, don't finalize the reply as a successful empty answer
+        if response.is_none() && data.response_error.is_none() {
+            data.response_error = Some("Failed to generate a response".to_string());
+        }
 
         // set response_message.response_status to 3 if response_error
         // set response_message.text to response_error message
@@ -283,6 +350,21 @@ impl TaskExec for ReplyTask {
             .exec()
             .await?;
 
+        if data.response_error.is_none() {
+            store_sources(space, data.response_message_id, &data.sources).await?;
+            space
+                .index_message(message_document(&response_message_data)?)
+                .await;
+            if let Some(response) = &response {
+                if let Err(e) = space
+                    .link_concepts(data.response_message_id, response)
+                    .await
+                {
+                    warn!("Failed to link concepts in the reply: {:?}", e);
+                }
+            }
+        }
+
         debug!("response: {:?}", response);
         debug!("invalidating messages.list");
 
@@ -291,3 +373,340 @@ impl TaskExec for ReplyTask {
         Ok(())
     }
 }
+
+/// The outcome of a generated reply.
+struct StreamedReply {
+    text: Option<String>,
+    error: Option<String>,
+    sources: Vec<AskSource>,
+}
+
+impl StreamedReply {
+    fn failed(error: String) -> Self {
+        Self {
+            text: None,
+            error: Some(error),
+            sources: vec![],
+        }
+    }
+}
+
+/// ask sends the question to the Python server and streams its answer.
+async fn ask(
+    space: &Space,
+    response_message_id: Uuid,
+    ask_request: &AskRequest,
+) -> Result<StreamedReply> {
+    let res = space
+        .sidecar()
+        .post_stream("/ask/stream", ask_request)
+        .await?;
+    stream_reply(space, response_message_id, res).await
+}
+
+/// generate answers with the node's own [`LlmProvider`], emitting a [`CoreEvent::MessageDelta`] for every token.
+async fn generate(
+    space: &Space,
+    response_message_id: Uuid,
+    llm: &dyn LlmProvider,
+    prompt: &[ChatMessage],
+) -> StreamedReply {
+    let mut text = String::new();
+    let result = llm
+        .stream(prompt, &mut |token| {
+            text.push_str(token);
+            space.emit(CoreEvent::MessageDelta {
+                space_id: space.id,
+                message_id: u2s(response_message_id),
+                delta: token.to_string(),
+                text: text.clone(),
+            });
+        })
+        .await;
+
+    match result {
+        Ok(text) => StreamedReply {
+            text: Some(text),
+            error: None,
+            sources: vec![],
+        },
+        Err(e) => StreamedReply::failed(format!("Failed to generate reply: {e:#}")),
+    }
+}
+
+/// stream_reply reads the server-sent events of `/ask/stream`, emitting a
+/// [`CoreEvent::MessageDelta`] for every token until the answer is done.
+async fn stream_reply(
+    space: &Space,
+    response_message_id: Uuid,
+    res: reqwest::Response,
+) -> Result<StreamedReply> {
+    let mut events = SseStream::new(res);
+    let mut text = String::new();
+
+    while let Some(event) = events.next().await? {
+        if let Some(reply) = handle_stream_event(space, response_message_id, event, &mut text)? {
+            return Ok(reply);
+        }
+    }
+
+    Ok(StreamedReply::failed(
+        "The answer stream ended before the answer was complete".to_string(),
+    ))
+}
+
+/// handle_stream_event appends tokens to `text`, returning the reply once the stream is done or failed.
+fn handle_stream_event(
+    space: &Space,
+    response_message_id: Uuid,
+    event: SseEvent,
+    text: &mut String,
+) -> Result<Option<StreamedReply>> {
+    match event.event.as_str() {
+        "token" => {
+            let token: AskStreamToken =
+                serde_json::from_str(&event.data).context("Failed to parse ask stream token")?;
+            text.push_str(&token.text);
+            space.emit(CoreEvent::MessageDelta {
+                space_id: space.id,
+                message_id: u2s(response_message_id),
+                delta: token.text,
+                text: text.clone(),
+            });
+            Ok(None)
+        }
+        "done" => {
+            let done: AskStreamDone =
+                serde_json::from_str(&event.data).context("Failed to parse ask stream result")?;
+            Ok(Some(StreamedReply {
+                text: Some(done.result),
+                error: None,
+                sources: done.sources,
+            }))
+        }
+        "error" => {
+            let error: AskStreamError =
+                serde_json::from_str(&event.data).context("Failed to parse ask stream error")?;
+            Ok(Some(StreamedReply::failed(error.error)))
+        }
+        other => {
+            debug!("Ignoring ask stream event {:?}", other);
+            Ok(None)
+        }
+    }
+}
+
+/// Max characters of a chunk kept as the snippet of a source.
+const SNIPPET_LENGTH: usize = 300;
+
+/// chunk_source describes a chunk retrieved from the native index as a source of the answer.
+fn chunk_source(hit: &ScoredChunk) -> AskSource {
+    AskSource {
+        file_id: Some(hit.chunk.file_id),
+        source: None,
+        page: hit.chunk.page,
+        snippet: hit.chunk.text.clone(),
+        score: Some(hit.score),
+    }
+}
+
+/// A source of the answer as it is stored in a `MessageSource` row.
+#[derive(Debug, PartialEq)]
+struct SourceRow {
+    rank: i32,
+    file_id: Option<Uuid>,
+    page: Option<i32>,
+    snippet: String,
+    score: Option<f64>,
+    link: Option<String>,
+}
+
+/// source_rows ranks the sources in the order they were retrieved. `file_ids` maps the absolute
+/// paths of the files of the space to their ids, for sources that only know their path. Sources of
+/// files that were deleted while the answer was generated are kept without their file.
+fn source_rows(
+    space_id: Uuid,
+    sources: &[AskSource],
+    file_ids: &HashMap<String, Uuid>,
+) -> Vec<SourceRow> {
+    let existing = file_ids.values().collect::<HashSet<_>>();
+    sources
+        .iter()
+        .enumerate()
+        .map(|(rank, source)| {
+            let file_id = match source.file_id {
+                Some(file_id) if existing.contains(&file_id) => Some(file_id),
+                Some(file_id) => {
+                    warn!(
+                        "Source file {} no longer exists, storing the source without it",
+                        file_id
+                    );
+                    None
+                }
+                // sources reported by the Chroma store only know the path they were loaded from
+                None => source
+                    .source
+                    .as_deref()
+                    .and_then(|path| file_ids.get(path).copied()),
+            };
+            let page = source.page.map(|page| page as i32);
+
+            SourceRow {
+                rank: rank as i32,
+                file_id,
+                page,
+                snippet: source.snippet.chars().take(SNIPPET_LENGTH).collect(),
+                score: source.score.map(f64::from),
+                link: file_id.map(|file_id| file_link(space_id, file_id, page)),
+            }
+        })
+        .collect()
+}
+
+/// store_sources attaches the chunks the answer was generated from to the response message.
+async fn store_sources(
+    space: &Space,
+    response_message_id: Uuid,
+    sources: &[AskSource],
+) -> Result<()> {
+    if sources.is_empty() {
+        return Ok(());
+    }
+
+    let space_path = space.path().await;
+    let file_ids = space
+        .db
+        .file()
+        .find_many(vec![file::space_id::equals(u2b(space.id))])
+        .exec()
+        .await?
+        .into_iter()
+        .filter_map(|file| {
+            let path = space_path.join(&file.path).to_string_lossy().into_owned();
+            Some((path, Uuid::from_slice(&file.id).ok()?))
+        })
+        .collect::<HashMap<_, _>>();
+
+    for row in source_rows(space.id, sources, &file_ids) {
+        let mut params = vec![
+            message_source::page::set(row.page),
+            message_source::score::set(row.score),
+            message_source::link::set(row.link),
+        ];
+        if let Some(file_id) = row.file_id {
+            params.push(message_source::file::connect(file::id::equals(u2b(
+                file_id,
+            ))));
+        }
+
+        let source_id = Uuid::new_v4();
+        space
+            .db
+            .message_source()
+            .create(
+                u2b(source_id),
+                u2s(source_id),
+                row.rank,
+                row.snippet,
+                message::id::equals(u2b(response_message_id)),
+                params,
+            )
+            .exec()
+            .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::index::Chunk;
+
+    fn hit(file_id: Uuid, page: Option<u32>, text: &str, score: f32) -> ScoredChunk {
+        ScoredChunk {
+            chunk: Chunk {
+                id: 0,
+                file_id,
+                index: 0,
+                page,
+                text: text.to_string(),
+                hash: String::new(),
+            },
+            score,
+        }
+    }
+
+    #[test]
+    fn ranks_retrieved_chunks_in_order() {
+        let space_id = Uuid::new_v4();
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let hits = [
+            hit(first, Some(3), &"x".repeat(SNIPPET_LENGTH + 50), 0.9),
+            hit(second, None, "short chunk", 0.5),
+        ];
+        let sources = hits.iter().map(chunk_source).collect::<Vec<_>>();
+
+        let file_ids = HashMap::from([
+            ("/spaces/s/first.pdf".to_string(), first),
+            ("/spaces/s/second.md".to_string(), second),
+        ]);
+
+        let rows = source_rows(space_id, &sources, &file_ids);
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].rank, 0);
+        assert_eq!(rows[0].file_id, Some(first));
+        assert_eq!(rows[0].page, Some(3));
+        assert_eq!(rows[0].snippet.chars().count(), SNIPPET_LENGTH);
+        assert_eq!(rows[0].link, Some(file_link(space_id, first, Some(3))));
+        assert!((rows[0].score.expect("hits are scored") - 0.9).abs() < 1e-6);
+        assert_eq!(rows[1].rank, 1);
+        assert_eq!(rows[1].snippet, "short chunk");
+        assert_eq!(rows[1].link, Some(file_link(space_id, second, None)));
+    }
+
+    #[test]
+    fn resolves_sources_by_path() {
+        let space_id = Uuid::new_v4();
+        let file_id = Uuid::new_v4();
+        let file_ids = HashMap::from([("/spaces/s/notes.pdf".to_string(), file_id)]);
+        let source = |path: &str| AskSource {
+            file_id: None,
+            source: Some(path.to_string()),
+            page: Some(1),
+            snippet: "snippet".to_string(),
+            score: None,
+        };
+
+        let rows = source_rows(
+            space_id,
+            &[source("/spaces/s/notes.pdf"), source("/elsewhere.pdf")],
+            &file_ids,
+        );
+
+        assert_eq!(rows[0].file_id, Some(file_id));
+        assert_eq!(rows[0].link, Some(file_link(space_id, file_id, Some(1))));
+        assert_eq!(rows[1].file_id, None);
+        assert_eq!(rows[1].link, None);
+    }
+
+    #[test]
+    fn keeps_sources_of_deleted_files_without_them() {
+        let space_id = Uuid::new_v4();
+        let (kept, deleted) = (Uuid::new_v4(), Uuid::new_v4());
+        let file_ids = HashMap::from([("/spaces/s/kept.pdf".to_string(), kept)]);
+        let sources = [
+            chunk_source(&hit(deleted, Some(2), "gone", 0.8)),
+            chunk_source(&hit(kept, Some(1), "still here", 0.7)),
+        ];
+
+        let rows = source_rows(space_id, &sources, &file_ids);
+
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].file_id, None);
+        assert_eq!(rows[0].link, None);
+        assert_eq!(rows[0].snippet, "gone");
+        assert_eq!(rows[1].file_id, Some(kept));
+    }
+}
@@ -0,0 +1,38 @@
+use crate::llm::ChatMessage;
+use crate::sidecar::AskContext;
+
+use super::history::ChatHistory;
+use super::style::AnswerStyle;
+
+/// build_prompt turns the retrieved context and the previous turns into chat messages for an [`crate::llm::LlmProvider`].
+pub fn build_prompt(
+    question: &str,
+    chat_history: &ChatHistory,
+    context: &[AskContext],
+    style: &AnswerStyle,
+) -> Vec<ChatMessage> {
+    let mut system = format!("{}\n\nContext:", style.instructions(true));
+    for (i, chunk) in context.iter().enumerate() {
+        match chunk.page {
+            Some(page) => system.push_str(&format!("\n\n[{}] (page {page})\n", i + 1)),
+            None => system.push_str(&format!("\n\n[{}]\n", i + 1)),
+        }
+        system.push_str(chunk.text.trim());
+    }
+    if let Some(summary) = &chat_history.summary {
+        system.push_str(&format!(
+            "\n\nSummary of the earlier conversation:\n{summary}"
+        ));
+    }
+
+    let mut messages = vec![ChatMessage::system(system)];
+    for entry in &chat_history.turns {
+        messages.push(ChatMessage::user(entry.HUMAN.clone()));
+        if !entry.AI.is_empty() {
+            messages.push(ChatMessage::assistant(entry.AI.clone()));
+        }
+    }
+    messages.push(ChatMessage::user(question));
+
+    messages
+}
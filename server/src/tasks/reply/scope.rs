@@ -0,0 +1,81 @@
+use anyhow::Result;
+use custom_prisma::prisma::file;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use uuid::Uuid;
+
+use crate::{space::Space, utils::u2b};
+
+/// MessageScope limits the files a question is answered from, e.g. "according to lecture 3 only".
+/// A file counts when it is listed or lies in one of the folders.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Type)]
+#[serde(default)]
+pub struct MessageScope {
+    pub file_ids: Vec<Uuid>,
+    /// paths relative to the space, subfolders included
+    pub folders: Vec<String>,
+}
+
+impl MessageScope {
+    pub fn is_empty(&self) -> bool {
+        self.file_ids.is_empty() && self.folders.is_empty()
+    }
+
+    fn contains(&self, file_id: Uuid, path: &str) -> bool {
+        self.file_ids.contains(&file_id)
+            || self.folders.iter().any(|folder| {
+                let folder = folder.trim_matches('/');
+                folder.is_empty() || path.starts_with(&format!("{folder}/"))
+            })
+    }
+}
+
+/// scoped_files returns the files of the space the scope covers.
+pub async fn scoped_files(space: &Space, scope: &MessageScope) -> Result<Vec<file::Data>> {
+    let files = space
+        .db
+        .file()
+        .find_many(vec![file::space_id::equals(u2b(space.id))])
+        .exec()
+        .await?;
+
+    Ok(files
+        .into_iter()
+        .filter(|file| {
+            Uuid::from_slice(&file.id).is_ok_and(|file_id| scope.contains(file_id, &file.path))
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_files_and_folders() {
+        let lecture = Uuid::new_v4();
+        let scope = MessageScope {
+            file_ids: vec![lecture],
+            folders: vec!["exams/".to_string()],
+        };
+
+        assert!(scope.contains(lecture, "lecture3.pdf"));
+        assert!(scope.contains(Uuid::new_v4(), "exams/2022/exam.pdf"));
+        assert!(!scope.contains(Uuid::new_v4(), "exams.pdf"));
+        assert!(!scope.contains(Uuid::new_v4(), "lecture4.pdf"));
+    }
+
+    #[test]
+    fn fills_in_missing_fields() {
+        let file_id = Uuid::new_v4();
+        let scope: MessageScope =
+            serde_json::from_str(&format!("{{\"file_ids\":[\"{file_id}\"]}}"))
+                .expect("valid scope");
+
+        assert_eq!(scope.file_ids, vec![file_id]);
+        assert!(scope.folders.is_empty());
+        assert!(serde_json::from_str::<MessageScope>("{}")
+            .expect("valid scope")
+            .is_empty());
+    }
+}
@@ -0,0 +1,128 @@
+use custom_prisma::prisma::meta;
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use std::str::FromStr;
+
+const DEFAULT_SYSTEM_PROMPT: &str = "You are a helpful study assistant. Answer the question using \
+the context below, which was taken from the user's documents. If the context does not contain the \
+answer, say that you don't know instead of making one up.";
+
+#[derive(
+    Serialize,
+    Deserialize,
+    Type,
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    strum::Display,
+    strum::EnumString,
+)]
+#[strum(serialize_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum AnswerLength {
+    Short,
+    #[default]
+    Medium,
+    Long,
+}
+
+impl AnswerLength {
+    /// parse falls back to [`AnswerLength::Medium`] for unknown values.
+    pub fn parse(value: &str) -> Self {
+        AnswerLength::from_str(value).unwrap_or_default()
+    }
+}
+
+/// AnswerStyle is how the replies of a space are phrased, set with `spaces.edit`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AnswerStyle {
+    /// replaces the default system prompt
+    pub system_prompt: Option<String>,
+    pub length: AnswerLength,
+    /// language to answer in, the language of the question when unset
+    pub language: Option<String>,
+    pub require_citations: bool,
+    pub persona: Option<String>,
+}
+
+impl AnswerStyle {
+    pub fn from_meta(meta: &meta::Data) -> Self {
+        Self {
+            system_prompt: meta.system_prompt.clone(),
+            length: AnswerLength::parse(&meta.answer_length),
+            language: meta.answer_language.clone(),
+            require_citations: meta.require_citations,
+            persona: meta.persona.clone(),
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// instructions renders the style as a system prompt. `numbered_context` says whether the context
+    /// chunks are numbered, so citations can refer to them.
+    pub fn instructions(&self, numbered_context: bool) -> String {
+        let mut instructions = self
+            .system_prompt
+            .clone()
+            .unwrap_or_else(|| DEFAULT_SYSTEM_PROMPT.to_string());
+
+        if let Some(persona) = &self.persona {
+            instructions.push_str(&format!("\n\nAct as a {persona} when talking to the user."));
+        }
+        match self.length {
+            AnswerLength::Short => {
+                instructions.push_str("\n\nKeep your answers short, a few sentences at most.")
+            }
+            AnswerLength::Medium => {}
+            AnswerLength::Long => instructions
+                .push_str("\n\nGive detailed answers and explain your reasoning step by step."),
+        }
+        if let Some(language) = &self.language {
+            instructions.push_str(&format!("\n\nAlways answer in {language}."));
+        }
+        if self.require_citations {
+            if numbered_context {
+                instructions.push_str(
+                    "\n\nCite the context you use by its number in square brackets, e.g. [1].",
+                );
+            } else {
+                instructions
+                    .push_str("\n\nQuote the parts of the context your answer is based on.");
+            }
+        }
+
+        instructions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_the_style_into_the_prompt() {
+        assert_eq!(
+            AnswerStyle::default().instructions(true),
+            DEFAULT_SYSTEM_PROMPT
+        );
+
+        let style = AnswerStyle {
+            system_prompt: Some("You help with chemistry.".to_string()),
+            length: AnswerLength::Short,
+            language: Some("German".to_string()),
+            require_citations: true,
+            persona: Some("Socratic tutor".to_string()),
+        };
+        let instructions = style.instructions(true);
+        assert!(instructions.starts_with("You help with chemistry."));
+        assert!(instructions.contains("Socratic tutor"));
+        assert!(instructions.contains("short"));
+        assert!(instructions.contains("German"));
+        assert!(instructions.contains("[1]"));
+    }
+}
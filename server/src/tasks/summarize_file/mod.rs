@@ -0,0 +1,443 @@
+use crate::index::{extract_chunks, TextChunk};
+use crate::llm::tokens::{count_tokens, truncate_tokens};
+use crate::llm::{ChatMessage, LlmProvider};
+use crate::utils::{u2b, u2s};
+use crate::{invalidate_query, space::Space};
+use std::hash::Hash;
+
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, FixedOffset, Utc};
+use custom_prisma::prisma::{file, file_summary, task};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use specta::Type;
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use super::{generation_needs_sidecar, TaskExec, TaskInfo, TaskState};
+
+/// Tokens of text summarized in one request.
+pub(crate) const SECTION_TOKENS: usize = 2500;
+/// Most sections a summary has, longer files get longer sections that are cut to [`SECTION_TOKENS`].
+const MAX_SECTIONS: usize = 12;
+
+const SECTION_PROMPT: &str = "You summarize study material. Summarize the part of a document \
+below in two to four sentences and give it a short title. Answer only with JSON of the form \
+{\"title\": \"...\", \"summary\": \"...\"}.";
+
+const OVERVIEW_PROMPT: &str = "You summarize study material. Below are the summaries of the \
+sections of a document. Write an overview of the whole document in a short paragraph and list \
+its most important key points. Answer only with JSON of the form \
+{\"overview\": \"...\", \"key_points\": [\"...\"]}.";
+
+pub struct SummarizeFileTask {}
+
+#[derive(Serialize, Deserialize, Clone, Type)]
+pub struct SummarizeFileTaskInfo {
+    pub file_id: Uuid,
+}
+
+impl Hash for SummarizeFileTaskInfo {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.file_id.hash(state);
+    }
+}
+
+impl TaskInfo for SummarizeFileTaskInfo {
+    type Task = SummarizeFileTask;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct SummarizeFileTaskState {
+    file_rel_path: String,
+    extension: String,
+    file_name: String,
+    content_hash: Option<String>,
+}
+
+/// A part of a summarized file.
+#[derive(Serialize, Deserialize, Type, Debug, Clone, PartialEq)]
+pub struct SummarySection {
+    pub title: String,
+    pub summary: String,
+    /// page the section starts on, for paginated formats
+    pub page: Option<u32>,
+}
+
+/// FileSummary is the structured summary of a learned file.
+#[derive(Serialize, Type, Debug, Clone)]
+pub struct FileSummary {
+    pub file_id: Uuid,
+    pub overview: String,
+    pub key_points: Vec<String>,
+    pub sections: Vec<SummarySection>,
+    /// the file changed since it was summarized
+    pub stale: bool,
+    pub date_modified: DateTime<FixedOffset>,
+}
+
+impl FileSummary {
+    pub fn from_data(file: &file::Data, summary: file_summary::Data) -> Result<Self> {
+        Ok(Self {
+            file_id: Uuid::from_slice(&file.id)?,
+            overview: summary.overview,
+            key_points: serde_json::from_str(&summary.key_points)
+                .context("Failed to parse the key points of the summary")?,
+            sections: serde_json::from_str(&summary.sections)
+                .context("Failed to parse the sections of the summary")?,
+            stale: summary.content_hash != file.content_hash,
+            date_modified: summary.date_modified,
+        })
+    }
+}
+
+/// The generated parts of a summary, before it is stored.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeneratedSummary {
+    pub overview: String,
+    pub key_points: Vec<String>,
+    pub sections: Vec<SummarySection>,
+}
+
+#[async_trait::async_trait]
+impl TaskExec for SummarizeFileTask {
+    type Info = SummarizeFileTaskInfo;
+    type Data = SummarizeFileTaskState;
+    const TYPE: &'static str = "summarize_file";
+    const NEEDS_SIDECAR: bool = true;
+
+    fn new() -> Self {
+        Self {}
+    }
+
+    fn needs_sidecar(&self, space: &Space, task_info: &TaskState<Self>) -> bool {
+        match &task_info.data {
+            Some(data) => generation_needs_sidecar(space, [data.extension.as_str()]),
+            None => Self::NEEDS_SIDECAR,
+        }
+    }
+
+    async fn setup(
+        &self,
+        space: &Space,
+        task_id: Uuid,
+        task_info: &mut TaskState<Self>,
+    ) -> Result<()> {
+        debug!("summarize_file::setup");
+        let info = task_info.info.clone();
+
+        let file = space
+            .db
+            .file()
+            .find_first(vec![
+                file::id::equals(u2b(info.file_id)),
+                file::space_id::equals(u2b(space.id)),
+            ])
+            .exec()
+            .await?
+            .context("Failed to find file")?;
+
+        space
+            .db
+            .task()
+            .update(
+                task::id::equals(u2b(task_id)),
+                vec![
+                    task::file::connect(file::id::equals(u2b(info.file_id))),
+                    task::date_modified::set(Utc::now().into()),
+                ],
+            )
+            .exec()
+            .await?;
+
+        if !file.learned {
+            bail!("Only learned files can be summarized");
+        }
+
+        task_info.data = Some(SummarizeFileTaskState {
+            file_rel_path: file.path,
+            extension: file.extension,
+            file_name: file.name,
+            content_hash: file.content_hash,
+        });
+
+        Ok(())
+    }
+
+    async fn run(
+        &self,
+        space: &Space,
+        _task_id: Uuid,
+        task_info: &mut TaskState<Self>,
+    ) -> Result<()> {
+        debug!("summarize_file::run");
+        let file_id = task_info.info.file_id;
+        let data = task_info
+            .data
+            .as_ref()
+            .context("Failed to get summarize task data")?;
+
+        let file_path = space.path().await.join(&data.file_rel_path);
+        let chunks = extract_chunks(&space.sidecar(), &file_path, &data.extension).await?;
+        if chunks.is_empty() {
+            bail!("The file has no text to summarize");
+        }
+
+        let llm = space.generator();
+        info!(
+            "Summarizing {} chunks of {:?} with {}",
+            chunks.len(),
+            data.file_name,
+            llm.name()
+        );
+        let summary = summarize(llm.as_ref(), &data.file_name, chunks).await?;
+
+        let key_points = serde_json::to_string(&summary.key_points)?;
+        let sections = serde_json::to_string(&summary.sections)?;
+        let id = Uuid::new_v4();
+        space
+            .db
+            .file_summary()
+            .upsert(
+                file_summary::file_id::equals(u2b(file_id)),
+                file_summary::create(
+                    u2b(id),
+                    u2s(id),
+                    summary.overview.clone(),
+                    key_points.clone(),
+                    sections.clone(),
+                    file::id::equals(u2b(file_id)),
+                    vec![file_summary::content_hash::set(data.content_hash.clone())],
+                ),
+                vec![
+                    file_summary::overview::set(summary.overview),
+                    file_summary::key_points::set(key_points),
+                    file_summary::sections::set(sections),
+                    file_summary::content_hash::set(data.content_hash.clone()),
+                    file_summary::date_modified::set(Utc::now().into()),
+                ],
+            )
+            .exec()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn finish(
+        &self,
+        space: &Space,
+        _task_id: Uuid,
+        _task_info: &mut TaskState<Self>,
+    ) -> Result<()> {
+        info!("summarize_file::finish");
+        // the task is attached to the file, so a FileUpdate is emitted with the task's new status
+        invalidate_query!(space, "files.summary");
+
+        Ok(())
+    }
+}
+
+/// summarize summarizes every section of the file, then the whole file from the section summaries.
+pub async fn summarize(
+    llm: &dyn LlmProvider,
+    name: &str,
+    chunks: Vec<TextChunk>,
+) -> Result<GeneratedSummary> {
+    let mut sections = vec![];
+    for (i, section) in split_sections(chunks).into_iter().enumerate() {
+        let text = section
+            .iter()
+            .map(|chunk| chunk.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let prompt = [
+            ChatMessage::system(SECTION_PROMPT),
+            ChatMessage::user(format!(
+                "Document: {}\n\n{}",
+                name,
+                truncate_tokens(&text, SECTION_TOKENS)
+            )),
+        ];
+        let reply = llm.complete(&prompt).await?;
+
+        #[derive(Deserialize)]
+        struct Section {
+            title: String,
+            summary: String,
+        }
+        let page = section.first().and_then(|chunk| chunk.page);
+        sections.push(match parse_json::<Section>(&reply) {
+            Some(parsed) => SummarySection {
+                title: parsed.title.trim().to_string(),
+                summary: parsed.summary.trim().to_string(),
+                page,
+            },
+            None => {
+                warn!("Section summary of {:?} was not valid JSON", name);
+                SummarySection {
+                    title: format!("Part {}", i + 1),
+                    summary: reply.trim().to_string(),
+                    page,
+                }
+            }
+        });
+    }
+
+    let outline = sections
+        .iter()
+        .map(|section| format!("## {}\n{}", section.title, section.summary))
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let prompt = [
+        ChatMessage::system(OVERVIEW_PROMPT),
+        ChatMessage::user(format!("Document: {}\n\n{}", name, outline)),
+    ];
+    let reply = llm.complete(&prompt).await?;
+
+    #[derive(Deserialize)]
+    struct Overview {
+        overview: String,
+        #[serde(default)]
+        key_points: Vec<String>,
+    }
+    let (overview, key_points) = match parse_json::<Overview>(&reply) {
+        Some(parsed) => (parsed.overview.trim().to_string(), parsed.key_points),
+        None => {
+            warn!("Overview of {:?} was not valid JSON", name);
+            (reply.trim().to_string(), vec![])
+        }
+    };
+
+    Ok(GeneratedSummary {
+        overview,
+        key_points,
+        sections,
+    })
+}
+
+/// split_sections groups consecutive chunks into at most [`MAX_SECTIONS`] sections of roughly
+/// [`SECTION_TOKENS`] tokens each.
+pub(crate) fn split_sections(chunks: Vec<TextChunk>) -> Vec<Vec<TextChunk>> {
+    let total = chunks
+        .iter()
+        .map(|chunk| count_tokens(&chunk.text))
+        .sum::<usize>();
+    let budget = SECTION_TOKENS.max(total.div_ceil(MAX_SECTIONS));
+
+    let mut sections: Vec<Vec<TextChunk>> = vec![];
+    let mut used = 0;
+    for chunk in chunks {
+        let tokens = count_tokens(&chunk.text);
+        match sections.last_mut() {
+            Some(section) if used + tokens <= budget => section.push(chunk),
+            _ => {
+                used = 0;
+                sections.push(vec![chunk]);
+            }
+        }
+        used += tokens;
+    }
+    sections
+}
+
+/// parse_json reads the JSON object in a model's reply, which may be wrapped in a code block or prose.
+pub fn parse_json<T: DeserializeOwned>(reply: &str) -> Option<T> {
+    let start = reply.find('{')?;
+    let end = reply.rfind('}')?;
+    serde_json::from_str(reply.get(start..=end)?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::MockProvider;
+    use crate::space::testing;
+    use std::sync::Arc;
+
+    fn chunk(index: u32, text: &str) -> TextChunk {
+        TextChunk {
+            index,
+            page: Some(index + 1),
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn groups_chunks_into_sections() {
+        let long = "word ".repeat(SECTION_TOKENS * 2 / 5);
+        let chunks = (0..5).map(|i| chunk(i, &long)).collect::<Vec<_>>();
+
+        let sections = split_sections(chunks);
+
+        assert_eq!(sections.len(), 3);
+        assert_eq!(sections[0].len(), 2);
+        assert_eq!(sections[2][0].page, Some(5));
+    }
+
+    #[tokio::test]
+    async fn summarizes_sections_then_the_file() {
+        let llm = MockProvider::new([
+            "```json\n{\"title\": \"Osmosis\", \"summary\": \"Water moves through membranes.\"}\n```",
+            "{\"overview\": \"A chapter on transport.\", \"key_points\": [\"Osmosis is passive\"]}",
+        ]);
+
+        let summary = summarize(
+            &llm,
+            "biology.pdf",
+            vec![chunk(0, "Osmosis is the diffusion of water.")],
+        )
+        .await
+        .expect("failed to summarize");
+
+        assert_eq!(
+            summary,
+            GeneratedSummary {
+                overview: "A chapter on transport.".to_string(),
+                key_points: vec!["Osmosis is passive".to_string()],
+                sections: vec![SummarySection {
+                    title: "Osmosis".to_string(),
+                    summary: "Water moves through membranes.".to_string(),
+                    page: Some(1),
+                }],
+            }
+        );
+        assert_eq!(llm.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn keeps_replies_that_are_not_json() {
+        let llm = MockProvider::new(["Water moves.", "A chapter on transport."]);
+
+        let summary = summarize(&llm, "biology.pdf", vec![chunk(0, "Osmosis.")])
+            .await
+            .expect("failed to summarize");
+
+        assert_eq!(summary.sections[0].title, "Part 1");
+        assert_eq!(summary.sections[0].summary, "Water moves.");
+        assert_eq!(summary.overview, "A chapter on transport.");
+        assert!(summary.key_points.is_empty());
+    }
+
+    #[tokio::test]
+    async fn only_needs_the_sidecar_to_extract_or_generate() {
+        let db = testing::database().await;
+        let state = |extension: &str| TaskState::<SummarizeFileTask> {
+            info: SummarizeFileTaskInfo {
+                file_id: Uuid::new_v4(),
+            },
+            data: Some(SummarizeFileTaskState {
+                file_rel_path: format!("biology.{extension}"),
+                extension: extension.to_string(),
+                file_name: "biology".to_string(),
+                content_hash: None,
+            }),
+        };
+        let task = SummarizeFileTask::new();
+
+        let space = testing::space(&db).await;
+        assert!(task.needs_sidecar(&space, &state("md")));
+
+        let space = testing::with_llm(space, Arc::new(MockProvider::echo()));
+        assert!(!task.needs_sidecar(&space, &state("md")));
+        assert!(task.needs_sidecar(&space, &state("pdf")));
+    }
+}
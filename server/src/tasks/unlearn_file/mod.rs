@@ -0,0 +1,241 @@
+use crate::index::IndexBackend;
+use crate::sidecar::{UnlearnRequest, UnlearnResponse};
+use crate::utils::u2b;
+use crate::{invalidate_query, space::Space};
+use std::hash::Hash;
+use std::path::Path;
+
+use chrono::Utc;
+use custom_prisma::prisma::{file, task};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+
+use anyhow::{Context, Result};
+use tracing::{debug, info, warn};
+
+use uuid::Uuid;
+
+use super::{TaskExec, TaskInfo, TaskState};
+
+pub struct UnlearnFileTask {}
+
+#[derive(Serialize, Deserialize, Clone, Type)]
+pub struct UnlearnFileTaskInfo {
+    pub file_id: Uuid,
+    /// also remove the file from disk and from the space once its chunks are gone
+    pub delete: bool,
+}
+
+impl Hash for UnlearnFileTaskInfo {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.file_id.hash(state);
+        self.delete.hash(state);
+    }
+}
+
+impl TaskInfo for UnlearnFileTaskInfo {
+    type Task = UnlearnFileTask;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnlearnFileTaskState {
+    file_rel_path: String,
+}
+
+/// unlearn removes every chunk of the file from the space's learning backend. Returns the number of chunks removed.
+pub async fn unlearn(space: &Space, file_id: Uuid, file_path: &Path) -> Result<usize> {
+    match IndexBackend::from_env() {
+        IndexBackend::Native => {
+            let index = space.index().await?;
+            let mut index = index.write().await;
+            let removed = index.remove_file(file_id);
+            if removed > 0 {
+                index.save().await?;
+            }
+            Ok(removed)
+        }
+        IndexBackend::Sidecar => {
+            let vector_db_path = space.path().await.join("vector_db");
+            if !vector_db_path.exists() {
+                return Ok(0);
+            }
+
+            let unlearn_request = UnlearnRequest {
+                vector_db_path: vector_db_path.to_string_lossy().into_owned(),
+                file_path: file_path.to_string_lossy().into_owned(),
+            };
+            let res = space
+                .sidecar()
+                .post::<_, UnlearnResponse>("/unlearn", &unlearn_request)
+                .await
+                .context("Failed to unlearn file")?;
+
+            Ok(res.removed.unwrap_or(0) as usize)
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl TaskExec for UnlearnFileTask {
+    type Info = UnlearnFileTaskInfo;
+    type Data = UnlearnFileTaskState;
+    const TYPE: &'static str = "unlearn_file";
+    const NEEDS_SIDECAR: bool = true;
+
+    fn new() -> Self {
+        Self {}
+    }
+
+    fn needs_sidecar(&self, _space: &Space, _task_info: &TaskState<Self>) -> bool {
+        IndexBackend::from_env() == IndexBackend::Sidecar
+    }
+
+    async fn setup(
+        &self,
+        space: &Space,
+        task_id: Uuid,
+        task_info: &mut TaskState<Self>,
+    ) -> Result<()> {
+        debug!("unlearn_file::setup");
+        let info = task_info.info.clone();
+
+        let file = space.file(info.file_id).await?;
+
+        // tasks cascade with their file, so a task that deletes its file must not be attached to it
+        if !info.delete {
+            space
+                .db
+                .task()
+                .update(
+                    task::id::equals(u2b(task_id)),
+                    vec![
+                        task::file::connect(file::id::equals(u2b(info.file_id))),
+                        task::date_modified::set(Utc::now().into()),
+                    ],
+                )
+                .exec()
+                .await?;
+        }
+
+        task_info.data = Some(UnlearnFileTaskState {
+            file_rel_path: file.path,
+        });
+
+        Ok(())
+    }
+
+    async fn run(
+        &self,
+        space: &Space,
+        _task_id: Uuid,
+        task_info: &mut TaskState<Self>,
+    ) -> Result<()> {
+        debug!("unlearn_file::run");
+        let info = task_info.info.clone();
+
+        let data = task_info
+            .data
+            .as_ref()
+            .context("Failed to get unlearn task data")?;
+        let file_path = space.path().await.join(&data.file_rel_path);
+
+        let removed = unlearn(space, info.file_id, &file_path).await?;
+        info!("Removed {} chunks of file {}", removed, info.file_id);
+        if let Err(e) = space.search().remove_file(info.file_id).await {
+            warn!(
+                "Failed to remove file {} from the search index: {:?}",
+                info.file_id, e
+            );
+        }
+        if let Err(e) = space.remove_file_concepts(info.file_id).await {
+            warn!(
+                "Failed to remove file {} from the glossary: {:?}",
+                info.file_id, e
+            );
+        }
+
+        if info.delete {
+            if file_path.exists() {
+                tokio::fs::remove_file(&file_path)
+                    .await
+                    .with_context(|| format!("Failed to remove {:?}", file_path))?;
+            }
+
+            space
+                .db
+                .file()
+                .delete_many(vec![
+                    file::id::equals(u2b(info.file_id)),
+                    file::space_id::equals(u2b(space.id)),
+                ])
+                .exec()
+                .await?;
+        } else {
+            space
+                .db
+                .file()
+                .update_many(
+                    vec![
+                        file::id::equals(u2b(info.file_id)),
+                        file::space_id::equals(u2b(space.id)),
+                    ],
+                    vec![file::learned::set(false)],
+                )
+                .exec()
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn finish(
+        &self,
+        space: &Space,
+        _task_id: Uuid,
+        _task_info: &mut TaskState<Self>,
+    ) -> Result<()> {
+        info!("unlearn_file::finish");
+        invalidate_query!(space, "files.list");
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::space::testing;
+
+    #[tokio::test]
+    async fn rejects_files_of_other_spaces() {
+        let db = testing::database().await;
+        let space = testing::space(&db).await;
+        let other = testing::space(&db).await;
+        let file_id = testing::file(&other, "notes.txt").await;
+
+        let mut state = TaskState::<UnlearnFileTask> {
+            info: UnlearnFileTaskInfo {
+                file_id,
+                delete: true,
+            },
+            data: None,
+        };
+        let res = UnlearnFileTask::new()
+            .setup(&space, Uuid::new_v4(), &mut state)
+            .await;
+        assert!(res.is_err());
+        assert!(state.data.is_none());
+
+        // the file is left alone and its own space can still unlearn it
+        assert!(space.file(file_id).await.is_err());
+        assert!(other.file(file_id).await.is_ok());
+        UnlearnFileTask::new()
+            .setup(&other, Uuid::new_v4(), &mut state)
+            .await
+            .expect("the file belongs to the space");
+        assert_eq!(
+            state.data.as_ref().map(|data| data.file_rel_path.as_str()),
+            Some("notes.txt")
+        );
+    }
+}
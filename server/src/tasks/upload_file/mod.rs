@@ -10,7 +10,7 @@ use anyhow::bail;
 use anyhow::{Context, Result};
 use std::fs::metadata;
 use std::time::{Duration, Instant};
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 use uuid::Uuid;
 
@@ -162,6 +162,11 @@ impl TaskExec for FileUploadTask {
             }
         }
 
+        // an upload to the path of a learned file replaces its content
+        if let Err(e) = space.refresh_stale_files().await {
+            warn!("Failed to check files for changes after upload: {:?}", e);
+        }
+
         Ok(())
     }
     async fn finish(
@@ -0,0 +1,170 @@
+use anyhow::{anyhow, Result};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use thiserror::Error;
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+const MAX_PASSWORD_LENGTH: usize = 256;
+const MAX_NAME_LENGTH: usize = 100;
+/// Hash with the default argon2 parameters that no password matches, verified when a login names
+/// an unknown email so it takes as long as a wrong password.
+const DUMMY_PASSWORD_HASH: &str =
+    "$argon2id$v=19$m=19456,t=2,p=1$c29tZXNhbHRmb3JkdW1teQ$HuTvGIhsbcRY4otjL60Rb7V4v72h+I0BHRfvTwpBKBM";
+
+/// Reasons an account can't be created or logged into, shown to the user as they are.
+#[derive(Debug, Error)]
+pub enum AccountError {
+    #[error("An account with this email already exists")]
+    EmailTaken,
+    #[error("Invalid email or password")]
+    InvalidCredentials,
+    #[error("This user already has an account")]
+    AlreadyAttached,
+    #[error("Session not found")]
+    SessionNotFound,
+    #[error("{0}")]
+    Invalid(String),
+}
+
+impl From<AccountError> for rspc::Error {
+    fn from(err: AccountError) -> Self {
+        let code = match err {
+            AccountError::EmailTaken | AccountError::AlreadyAttached => rspc::ErrorCode::Conflict,
+            AccountError::InvalidCredentials => rspc::ErrorCode::Unauthorized,
+            AccountError::SessionNotFound => rspc::ErrorCode::NotFound,
+            AccountError::Invalid(_) => rspc::ErrorCode::BadRequest,
+        };
+        rspc::Error::new(code, err.to_string())
+    }
+}
+
+/// normalize_email trims and lowercases the email, an email belongs to one account whatever its
+/// case.
+pub fn normalize_email(email: &str) -> Result<String, AccountError> {
+    let email = email.trim().to_lowercase();
+    let valid = match email.split_once('@') {
+        Some((local, domain)) => {
+            !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && !email.contains(char::is_whitespace)
+        }
+        None => false,
+    };
+    if !valid {
+        return Err(AccountError::Invalid(
+            "Please enter a valid email address".to_string(),
+        ));
+    }
+
+    Ok(email)
+}
+
+pub fn validate_name(name: &str) -> Result<String, AccountError> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+        return Err(AccountError::Invalid(format!(
+            "Names must have between 1 and {} characters",
+            MAX_NAME_LENGTH
+        )));
+    }
+
+    Ok(name.to_string())
+}
+
+pub fn validate_password(password: &str) -> Result<(), AccountError> {
+    let length = password.chars().count();
+    if !(MIN_PASSWORD_LENGTH..=MAX_PASSWORD_LENGTH).contains(&length) {
+        return Err(AccountError::Invalid(format!(
+            "Passwords must have between {} and {} characters",
+            MIN_PASSWORD_LENGTH, MAX_PASSWORD_LENGTH
+        )));
+    }
+
+    Ok(())
+}
+
+/// hash_password hashes the password with argon2 and a random salt, in the PHC string format.
+/// Hashing is slow on purpose, so it runs on the blocking pool.
+pub async fn hash_password(password: String) -> Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|err| anyhow!("Failed to hash password: {}", err))
+    })
+    .await?
+}
+
+/// verify_password checks the password against a hash made by [hash_password].
+pub async fn verify_password(password: String, hash: String) -> Result<bool> {
+    tokio::task::spawn_blocking(move || {
+        let hash =
+            PasswordHash::new(&hash).map_err(|err| anyhow!("Invalid password hash: {}", err))?;
+        Ok(Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .is_ok())
+    })
+    .await?
+}
+
+/// verify_dummy_password does the work of [verify_password] without an account to check against.
+pub async fn verify_dummy_password(password: String) -> Result<()> {
+    verify_password(password, DUMMY_PASSWORD_HASH.to_string()).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn verifies_hashed_passwords() {
+        let hash = hash_password("correct horse".to_string())
+            .await
+            .expect("failed to hash password");
+
+        assert!(hash.starts_with("$argon2id$"));
+        assert!(verify_password("correct horse".to_string(), hash.clone())
+            .await
+            .expect("failed to verify password"));
+        assert!(!verify_password("battery staple".to_string(), hash)
+            .await
+            .expect("failed to verify password"));
+    }
+
+    #[tokio::test]
+    async fn dummy_hash_matches_no_password() {
+        assert!(DUMMY_PASSWORD_HASH.starts_with("$argon2id$"));
+        assert!(
+            !verify_password("correct horse".to_string(), DUMMY_PASSWORD_HASH.to_string())
+                .await
+                .expect("the dummy hash is valid")
+        );
+        verify_dummy_password(String::new())
+            .await
+            .expect("the dummy hash is valid");
+    }
+
+    #[test]
+    fn normalizes_emails() {
+        assert_eq!(
+            normalize_email("  Ada@Example.COM ").expect("email is valid"),
+            "ada@example.com"
+        );
+        assert!(normalize_email("ada").is_err());
+        assert!(normalize_email("@example.com").is_err());
+        assert!(normalize_email("ada@example").is_err());
+        assert!(normalize_email("ada lovelace@example.com").is_err());
+    }
+
+    #[test]
+    fn rejects_short_passwords() {
+        assert!(validate_password("short").is_err());
+        assert!(validate_password("long enough").is_ok());
+    }
+}
@@ -0,0 +1,169 @@
+use std::env;
+
+use anyhow::{bail, Context, Result};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey, Header, TokenData, Validation};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use tracing::warn;
+use uuid::Uuid;
+
+/// Secret used when none is configured, only accepted outside of production.
+const DEFAULT_SECRET: &str = "secret";
+/// Key id given to `JWT_SECRET` and to the default secret.
+const DEFAULT_KEY_ID: &str = "default";
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Claims {
+    pub sub: Uuid,
+    pub exp: usize,
+}
+
+struct JwtKey {
+    id: String,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+}
+
+/// JwtKeys signs tokens with the first key and accepts tokens signed with any of them. The key
+/// is named by the `kid` header of the token, so a new key can be put first while tokens signed
+/// with the old one stay valid until the old key is dropped.
+pub struct JwtKeys {
+    keys: Vec<JwtKey>,
+}
+
+impl JwtKeys {
+    /// from_env reads the keys from `JWT_KEYS`, comma separated `kid=secret` pairs with the signing
+    /// key first, or a single key from `JWT_SECRET`. Without either the node uses a default secret,
+    /// which it refuses when `NODE_ENV` is `production`.
+    pub fn from_env() -> Result<Self> {
+        let production = env::var("NODE_ENV").is_ok_and(|e| e == "production");
+        Self::parse(
+            env::var("JWT_KEYS").ok().as_deref(),
+            env::var("JWT_SECRET").ok().as_deref(),
+            production,
+        )
+    }
+
+    pub fn parse(keys: Option<&str>, secret: Option<&str>, production: bool) -> Result<Self> {
+        let mut pairs = vec![];
+        if let Some(keys) = keys.filter(|k| !k.trim().is_empty()) {
+            for pair in keys.split(',') {
+                let (id, secret) = pair.trim().split_once('=').with_context(|| {
+                    format!("'$JWT_KEYS' entries are kid=secret, got {:?}", pair)
+                })?;
+                let id = id.trim();
+                if id.is_empty() || secret.is_empty() {
+                    bail!("'$JWT_KEYS' entries need both a kid and a secret");
+                }
+                if pairs.iter().any(|(existing, _)| existing == id) {
+                    bail!("'$JWT_KEYS' has the kid {:?} twice", id);
+                }
+                pairs.push((id.to_string(), secret.to_string()));
+            }
+        } else if let Some(secret) = secret.filter(|s| !s.is_empty()) {
+            pairs.push((DEFAULT_KEY_ID.to_string(), secret.to_string()));
+        } else {
+            if production {
+                bail!("Refusing to start in production without '$JWT_SECRET' or '$JWT_KEYS'");
+            }
+            warn!("'$JWT_SECRET' is not set, tokens are signed with the default secret");
+            pairs.push((DEFAULT_KEY_ID.to_string(), DEFAULT_SECRET.to_string()));
+        }
+
+        if production && pairs.iter().any(|(_, secret)| secret == DEFAULT_SECRET) {
+            bail!("Refusing to start in production with the default JWT secret");
+        }
+
+        Ok(Self {
+            keys: pairs
+                .into_iter()
+                .map(|(id, secret)| JwtKey {
+                    id,
+                    encoding: EncodingKey::from_secret(secret.as_bytes()),
+                    decoding: DecodingKey::from_secret(secret.as_bytes()),
+                })
+                .collect(),
+        })
+    }
+
+    /// sign signs the claims with the current key and names it in the `kid` header.
+    pub fn sign(&self, claims: &impl Serialize) -> Result<String> {
+        let key = self.keys.first().context("No key to sign tokens with")?;
+        let mut header = Header::new(Algorithm::HS256);
+        header.kid = Some(key.id.clone());
+
+        Ok(jsonwebtoken::encode(&header, claims, &key.encoding)?)
+    }
+
+    /// verify checks the signature and expiry of a session token, see [JwtKeys::decode].
+    pub fn verify(&self, token: &str) -> Result<TokenData<Claims>> {
+        self.decode(token)
+    }
+
+    /// decode checks the signature and expiry of the token and reads its claims. Tokens without a
+    /// `kid` were signed before keys were named and are checked against the current key.
+    pub fn decode<C: DeserializeOwned>(&self, token: &str) -> Result<TokenData<C>> {
+        let header = jsonwebtoken::decode_header(token)?;
+        let key = match &header.kid {
+            Some(kid) => self
+                .keys
+                .iter()
+                .find(|key| &key.id == kid)
+                .with_context(|| format!("Unknown key {:?}", kid))?,
+            None => self.keys.first().context("No key to verify tokens with")?,
+        };
+
+        Ok(jsonwebtoken::decode(
+            token,
+            &key.decoding,
+            &Validation::new(Algorithm::HS256),
+        )?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims() -> Claims {
+        Claims {
+            sub: Uuid::new_v4(),
+            exp: (chrono::Utc::now() + chrono::Duration::days(1)).timestamp() as usize,
+        }
+    }
+
+    #[test]
+    fn accepts_tokens_of_rotated_keys() {
+        let old = JwtKeys::parse(Some("2023=old secret"), None, true).expect("keys are valid");
+        let claims = claims();
+        let token = old.sign(&claims).expect("failed to sign");
+
+        let rotated = JwtKeys::parse(Some("2024=new secret, 2023=old secret"), None, true)
+            .expect("keys are valid");
+        assert_eq!(
+            rotated.verify(&token).expect("token is valid").claims.sub,
+            claims.sub
+        );
+
+        let dropped = JwtKeys::parse(Some("2024=new secret"), None, true).expect("keys are valid");
+        assert!(dropped.verify(&token).is_err());
+    }
+
+    #[test]
+    fn rejects_tokens_of_other_secrets() {
+        let keys = JwtKeys::parse(None, Some("one secret"), false).expect("keys are valid");
+        let forged = JwtKeys::parse(None, None, false)
+            .expect("keys are valid")
+            .sign(&claims())
+            .expect("failed to sign");
+
+        assert!(keys.verify(&forged).is_err());
+    }
+
+    #[test]
+    fn refuses_the_default_secret_in_production() {
+        assert!(JwtKeys::parse(None, None, true).is_err());
+        assert!(JwtKeys::parse(None, Some("secret"), true).is_err());
+        assert!(JwtKeys::parse(Some("a=x,a=y"), None, false).is_err());
+        assert!(JwtKeys::parse(None, None, false).is_ok());
+    }
+}
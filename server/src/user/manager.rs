@@ -1,30 +1,28 @@
-use crate::{space::SpaceManager, utils::u2s, NodeContext};
-
-use jsonwebtoken::{Algorithm, TokenData};
+use crate::{
+    space::SpaceManager,
+    utils::{u2b, u2s},
+    NodeContext,
+};
 
 use serde::{Deserialize, Serialize};
 
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc, time::Instant};
 
 use specta::Type;
 
 use anyhow::{Context, Result};
-use custom_prisma::prisma::user;
-use tokio::sync::RwLock;
+use chrono::Utc;
+use custom_prisma::prisma::{account, user};
+use prisma_client_rust::{prisma_errors::query_engine::UniqueKeyViolation, QueryError};
+use tokio::sync::{Mutex, RwLock};
 use tokio::task;
 use tracing::debug;
 use uuid::Uuid;
 
-use super::User;
-
-// TODO: actual security
-pub const SECRET: &[u8] = b"secret";
-
-#[derive(Debug, Deserialize, Serialize)]
-struct Claims {
-    sub: Uuid,
-    exp: usize,
-}
+use super::{
+    authorize, hash_password, normalize_email, validate_name, validate_password,
+    verify_dummy_password, verify_password, AccountError, Claims, JwtKeys, User,
+};
 
 #[derive(Serialize, Deserialize, Type, Debug)]
 pub struct UserWithToken {
@@ -37,8 +35,12 @@ pub struct UserWithToken {
 pub struct UserManager {
     /// users holds the list of users which are currently loaded into the node.
     users: RwLock<Vec<User>>,
-    node_context: NodeContext,
+    pub(super) node_context: NodeContext,
     space_manager: Arc<SpaceManager>,
+    /// keys that sign and verify the tokens of users
+    pub(super) keys: JwtKeys,
+    /// when the use of each token was last written to the database
+    pub(super) token_uses: Mutex<HashMap<String, Instant>>,
     // db: Arc<PrismaClient>,
 }
 
@@ -46,6 +48,7 @@ impl UserManager {
     pub(crate) async fn new(
         node_context: NodeContext,
         space_manager: Arc<SpaceManager>,
+        keys: JwtKeys,
     ) -> Result<Arc<Self>> {
         let mut users = Vec::new();
 
@@ -71,6 +74,8 @@ impl UserManager {
             users: RwLock::new(users),
             node_context,
             space_manager,
+            keys,
+            token_uses: Mutex::new(HashMap::new()),
         });
 
         debug!("UserManager initialized");
@@ -117,54 +122,245 @@ impl UserManager {
     pub(crate) async fn create_detached(&self) -> Result<UserWithToken> {
         let user_id = Uuid::new_v4();
 
-        // println!("encoding id: {:?}", id);
+        let new_user = self.create_with_uuid(user_id).await?;
+        let token = self.issue_token(user_id).await?;
+
+        debug!("Created user: {:?}", new_user);
+        self.create_demo(user_id).await?;
+
+        Ok(UserWithToken {
+            user: new_user,
+            token,
+        })
+    }
+
+    /// signup creates a user with an account, which can log in from any device.
+    pub(crate) async fn signup(
+        &self,
+        name: String,
+        email: String,
+        password: String,
+    ) -> Result<UserWithToken> {
+        let name = validate_name(&name)?;
+        let email = normalize_email(&email)?;
+        validate_password(&password)?;
+        self.ensure_email_free(&email).await?;
+
+        let hash = hash_password(password).await?;
+        let user_id = Uuid::new_v4();
+        let account_id = Uuid::new_v4();
+        let db = &self.node_context.db;
+        // the user is only created with its account, a concurrent signup with the same email
+        // fails on the unique email
+        let (new_user, _) = db
+            ._batch((
+                db.user().create(
+                    u2b(user_id),
+                    u2s(user_id),
+                    vec![user::account_attached::set(true)],
+                ),
+                db.account().create(
+                    u2b(account_id),
+                    u2s(account_id),
+                    name,
+                    email,
+                    hash,
+                    user::id::equals(u2b(user_id)),
+                    vec![],
+                ),
+            ))
+            .await
+            .map_err(|e| -> anyhow::Error {
+                if is_unique_violation(&e) {
+                    AccountError::EmailTaken.into()
+                } else {
+                    e.into()
+                }
+            })?;
+        let token = self.issue_token(user_id).await?;
+
+        debug!("Signed up user: {:?}", new_user);
+        self.create_demo(user_id).await?;
+
+        Ok(UserWithToken {
+            user: new_user,
+            token,
+        })
+    }
+
+    /// login returns a new token for the user of the account. Wrong emails and wrong passwords
+    /// fail the same way, so accounts can't be found by trying emails.
+    pub(crate) async fn login(&self, email: String, password: String) -> Result<UserWithToken> {
+        let Ok(email) = normalize_email(&email) else {
+            return Err(AccountError::InvalidCredentials.into());
+        };
+        let account = self
+            .node_context
+            .db
+            .account()
+            .find_unique(account::email::equals(email))
+            .include(account::include!({ user }))
+            .exec()
+            .await?;
+        let Some(account) = account else {
+            // hash anyway, so unknown emails don't answer faster than wrong passwords
+            verify_dummy_password(password).await?;
+            return Err(AccountError::InvalidCredentials.into());
+        };
+
+        if !verify_password(password, account.password).await? {
+            return Err(AccountError::InvalidCredentials.into());
+        }
+
+        self.node_context
+            .db
+            .account()
+            .update(
+                account::id::equals(account.id),
+                vec![account::date_used::set(Utc::now().into())],
+            )
+            .exec()
+            .await?;
+
+        let user_id = Uuid::from_slice(&account.user_id)?;
+        let token = self.issue_token(user_id).await?;
+        debug!("Logged in user {}", user_id);
+
+        Ok(UserWithToken {
+            user: account.user,
+            token,
+        })
+    }
+
+    /// attach_account gives an anonymous user an account, the user keeps its id and so its
+    /// spaces and tokens.
+    pub(crate) async fn attach_account(
+        &self,
+        user_id: Uuid,
+        name: String,
+        email: String,
+        password: String,
+    ) -> Result<user::Data> {
+        let name = validate_name(&name)?;
+        let email = normalize_email(&email)?;
+        validate_password(&password)?;
+
+        let existing = self
+            .node_context
+            .db
+            .user()
+            .find_unique(user::id::equals(u2b(user_id)))
+            .exec()
+            .await?
+            .with_context(|| format!("User with id {} not found", user_id))?;
+        if existing.account_attached {
+            return Err(AccountError::AlreadyAttached.into());
+        }
+        self.ensure_email_free(&email).await?;
+
+        let hash = hash_password(password).await?;
+        let account_id = Uuid::new_v4();
+        let db = &self.node_context.db;
+        let res = db
+            ._batch((
+                db.account().create(
+                    u2b(account_id),
+                    u2s(account_id),
+                    name,
+                    email,
+                    hash,
+                    user::id::equals(u2b(user_id)),
+                    vec![],
+                ),
+                db.user().update(
+                    user::id::equals(u2b(user_id)),
+                    vec![user::account_attached::set(true)],
+                ),
+            ))
+            .await;
+        let user = match res {
+            Ok((_, user)) => user,
+            // a concurrent request attached an account to the user or took the email
+            Err(e) if is_unique_violation(&e) => {
+                let attached = db
+                    .user()
+                    .find_unique(user::id::equals(u2b(user_id)))
+                    .exec()
+                    .await?
+                    .is_some_and(|user| user.account_attached);
+                let err = if attached {
+                    AccountError::AlreadyAttached
+                } else {
+                    AccountError::EmailTaken
+                };
+                return Err(err.into());
+            }
+            Err(e) => return Err(e.into()),
+        };
+        self.sync_user_from_db(user_id).await?;
+
+        debug!("Attached an account to user {}", user_id);
+
+        Ok(user)
+    }
+
+    async fn ensure_email_free(&self, email: &str) -> Result<()> {
+        let taken = self
+            .node_context
+            .db
+            .account()
+            .find_unique(account::email::equals(email.to_string()))
+            .exec()
+            .await?
+            .is_some();
+        if taken {
+            return Err(AccountError::EmailTaken.into());
+        }
 
+        Ok(())
+    }
+
+    /// issue_token signs a token for the user that is valid for 30 days, stores it and loads the
+    /// user into the [UserManager].
+    async fn issue_token(&self, user_id: Uuid) -> Result<String> {
         let expires: chrono::DateTime<chrono::Utc> =
             chrono::Utc::now() + chrono::Duration::days(30);
 
         let claims = Claims {
-            sub: user_id, // or whatever the field is named in your payload
-            // set here the other fields
+            sub: user_id,
             exp: expires.timestamp() as usize,
         };
 
-        let token = jsonwebtoken::encode(
-            &jsonwebtoken::Header::new(Algorithm::HS256),
-            &claims,
-            &jsonwebtoken::EncodingKey::from_secret(SECRET),
-        )
-        .unwrap();
-
-        let new_user = self.create_with_uuid(user_id).await?;
+        let token = self.keys.sign(&claims)?;
+        self.prune_tokens(user_id).await?;
 
         let new_jwt = self
             .node_context
             .db
             .jwt()
-            .create(token, user::id::equals(new_user.clone().id), vec![])
+            .create(token, user::id::equals(u2b(user_id)), vec![])
             .exec()
             .await?;
 
-        let user = self.sync_user_from_db(user_id).await?;
+        self.sync_user_from_db(user_id).await?;
 
-        debug!("Created user: {:?}", new_user);
+        Ok(new_jwt.token)
+    }
 
+    /// create_demo creates the demo space of a new user in the background.
+    async fn create_demo(&self, user_id: Uuid) -> Result<()> {
+        let user = self.sync_user_from_db(user_id).await?;
         let space_manager = self.space_manager.clone();
 
         // this is an async func but we want to run it without awaiting
         task::spawn(async move {
-            // create_demo_for_user(&user).await?;
             let res = space_manager.create_demo_for_user(user).await;
             if let Err(err) = res {
                 tracing::error!("Error creating demo for user: {:?}", err);
             }
         });
-        // .await?;
 
-        Ok(UserWithToken {
-            user: new_user,
-            token: new_jwt.token,
-        })
+        Ok(())
     }
 
     pub(crate) async fn create_with_uuid(&self, id: Uuid) -> Result<user::Data> {
@@ -191,31 +387,27 @@ impl UserManager {
             .map(Clone::clone)
     }
 
+    /// user_from_jwt returns the user of a token that is signed by one of the keys, not expired
+    /// and not revoked.
     pub async fn user_from_jwt(&self, token: String) -> Option<User> {
-        let decoded: Result<TokenData<Claims>, jsonwebtoken::errors::Error> = jsonwebtoken::decode(
-            &token,
-            &jsonwebtoken::DecodingKey::from_secret(SECRET),
-            &jsonwebtoken::Validation::new(Algorithm::HS256),
-        );
-
-        // if error, we log it and return None
-        if let Err(err) = decoded {
-            println!("error decoding jwt: {:?} {:?}", err, token);
-            return None;
-        }
-
-        let decoded = decoded.ok()?;
-
-        let decoded = decoded.claims.sub;
-
-        let user = self
-            .users
-            .read()
-            .await
-            .iter()
-            .find(|u| u.id == decoded)
-            .map(Clone::clone);
+        let user = {
+            let users = self.users.read().await;
+            let user_id = authorize(&self.keys, &token, |id| {
+                users
+                    .iter()
+                    .find(|user| user.id == id)
+                    .map(|user| user.jwts.as_slice())
+            })?;
+            users.iter().find(|user| user.id == user_id)?.clone()
+        };
+        self.record_use(&token).await;
 
-        user
+        Some(user)
     }
 }
+
+/// is_unique_violation tells whether the query failed because a row with the same unique value
+/// already exists.
+fn is_unique_violation(err: &QueryError) -> bool {
+    err.is_prisma_error::<UniqueKeyViolation>()
+}
@@ -1,6 +1,12 @@
+mod account;
+mod keys;
 mod manager;
+mod session;
 #[allow(clippy::module_inception)]
 mod user;
 
+pub use account::*;
+pub use keys::*;
 pub use manager::*;
+pub use session::*;
 pub use user::*;
@@ -0,0 +1,328 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+use custom_prisma::prisma::{jwt, SortOrder};
+use serde::{Deserialize, Serialize};
+use specta::Type;
+use tokio::task;
+use tracing::{debug, warn};
+use uuid::Uuid;
+
+use crate::utils::u2b;
+
+use super::{AccountError, JwtKeys, UserManager};
+
+/// How often the last use of a token is written to the database.
+const USE_RECORD_INTERVAL: Duration = Duration::from_secs(60);
+/// How long an export link can be opened after it was issued.
+const EXPORT_TOKEN_MINUTES: i64 = 5;
+
+/// ExportClaims let a link download a single conversation, see [UserManager::export_token]. They
+/// are never stored, so the token can't be used as a session.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ExportClaims {
+    pub sub: Uuid,
+    pub conversation_id: Uuid,
+    pub exp: usize,
+}
+
+/// A token of the user, one for every device it logged in from.
+#[derive(Serialize, Type, Debug)]
+pub struct Session {
+    pub id: i32,
+    pub date_created: DateTime<FixedOffset>,
+    pub date_used: DateTime<FixedOffset>,
+    pub date_expires: Option<DateTime<Utc>>,
+    /// whether this is the token of the request
+    pub current: bool,
+}
+
+impl UserManager {
+    /// sessions lists the tokens of the user that can still be used, most recently used first.
+    pub(crate) async fn sessions(&self, user_id: Uuid, current: &str) -> Result<Vec<Session>> {
+        let tokens = self
+            .node_context
+            .db
+            .jwt()
+            .find_many(vec![jwt::user_id::equals(u2b(user_id))])
+            .order_by(jwt::date_used::order(SortOrder::Desc))
+            .exec()
+            .await?;
+
+        let sessions = tokens
+            .into_iter()
+            .filter_map(|token| {
+                let decoded = self.keys.verify(&token.token).ok()?;
+                Some(Session {
+                    id: token.id,
+                    date_created: token.date_created,
+                    date_used: token.date_used,
+                    date_expires: Utc.timestamp_opt(decoded.claims.exp as i64, 0).single(),
+                    current: token.token == current,
+                })
+            })
+            .collect();
+
+        Ok(sessions)
+    }
+
+    /// prune_tokens deletes the tokens of the user that expired or were signed with a dropped key,
+    /// called whenever the user gets a new one.
+    pub(super) async fn prune_tokens(&self, user_id: Uuid) -> Result<()> {
+        let stale = self
+            .node_context
+            .db
+            .jwt()
+            .find_many(vec![jwt::user_id::equals(u2b(user_id))])
+            .exec()
+            .await?
+            .into_iter()
+            .filter(|token| self.keys.verify(&token.token).is_err())
+            .map(|token| token.id)
+            .collect::<Vec<_>>();
+        if stale.is_empty() {
+            return Ok(());
+        }
+
+        debug!("Deleting {} stale tokens of user {}", stale.len(), user_id);
+        self.node_context
+            .db
+            .jwt()
+            .delete_many(vec![jwt::id::in_vec(stale)])
+            .exec()
+            .await?;
+
+        Ok(())
+    }
+
+    /// revoke_session deletes a token of the user, requests made with it are rejected from now on.
+    pub(crate) async fn revoke_session(&self, user_id: Uuid, session_id: i32) -> Result<()> {
+        let deleted = self
+            .node_context
+            .db
+            .jwt()
+            .delete_many(vec![
+                jwt::id::equals(session_id),
+                jwt::user_id::equals(u2b(user_id)),
+            ])
+            .exec()
+            .await?;
+        if deleted == 0 {
+            return Err(AccountError::SessionNotFound.into());
+        }
+        self.sync_user_from_db(user_id).await?;
+
+        debug!("Revoked session {} of user {}", session_id, user_id);
+
+        Ok(())
+    }
+
+    /// logout revokes the token the request was made with.
+    pub(crate) async fn logout(&self, user_id: Uuid, token: String) -> Result<()> {
+        self.node_context
+            .db
+            .jwt()
+            .delete_many(vec![
+                jwt::token::equals(token.clone()),
+                jwt::user_id::equals(u2b(user_id)),
+            ])
+            .exec()
+            .await?;
+        self.token_uses.lock().await.remove(&token);
+        self.sync_user_from_db(user_id).await?;
+
+        debug!("Logged out user {}", user_id);
+
+        Ok(())
+    }
+
+    /// export_token signs a token that lets the user download the conversation for a few minutes,
+    /// so the session token never ends up in a URL.
+    pub(crate) fn export_token(&self, user_id: Uuid, conversation_id: Uuid) -> Result<String> {
+        let expires = Utc::now() + chrono::Duration::minutes(EXPORT_TOKEN_MINUTES);
+        self.keys.sign(&ExportClaims {
+            sub: user_id,
+            conversation_id,
+            exp: expires.timestamp() as usize,
+        })
+    }
+
+    /// export_user returns the user an export token was issued to, if it is valid for the
+    /// conversation.
+    pub(crate) fn export_user(&self, token: &str, conversation_id: Uuid) -> Option<Uuid> {
+        authorize_export(&self.keys, token, conversation_id)
+    }
+
+    /// record_use updates when the token was last used, at most once per [USE_RECORD_INTERVAL] so
+    /// requests don't all write to the database.
+    pub(super) async fn record_use(&self, token: &str) {
+        let now = Instant::now();
+        {
+            let mut uses = self.token_uses.lock().await;
+            let recent = uses
+                .get(token)
+                .is_some_and(|used| now.duration_since(*used) < USE_RECORD_INTERVAL);
+            if recent {
+                return;
+            }
+            // entries older than the interval would be written anyway, so the map stays small
+            uses.retain(|_, used| now.duration_since(*used) < USE_RECORD_INTERVAL);
+            uses.insert(token.to_string(), now);
+        }
+
+        let db = self.node_context.db.clone();
+        let token = token.to_string();
+        task::spawn(async move {
+            // the token may have been revoked in the meantime
+            let res = db
+                .jwt()
+                .update_many(
+                    vec![jwt::token::equals(token)],
+                    vec![jwt::date_used::set(Utc::now().into())],
+                )
+                .exec()
+                .await;
+            if let Err(err) = res {
+                warn!("Failed to record the use of a token: {:?}", err);
+            }
+        });
+    }
+}
+
+/// authorize returns the user the token was issued to when it is signed by one of the keys, not
+/// expired and still stored for that user. `stored` looks up the tokens of a user, revoking a
+/// session or logging out deletes its token so it is rejected from then on.
+pub(super) fn authorize<'a>(
+    keys: &JwtKeys,
+    token: &str,
+    stored: impl FnOnce(Uuid) -> Option<&'a [String]>,
+) -> Option<Uuid> {
+    let claims = match keys.verify(token) {
+        Ok(decoded) => decoded.claims,
+        Err(err) => {
+            debug!("Rejected token: {:?}", err);
+            return None;
+        }
+    };
+
+    if !stored(claims.sub)?.iter().any(|stored| stored == token) {
+        debug!("Rejected a revoked or unknown token of user {}", claims.sub);
+        return None;
+    }
+
+    Some(claims.sub)
+}
+
+/// authorize_export returns the user an export token was issued to when it is signed by one of the
+/// keys, not expired and issued for the conversation. Session tokens have no conversation and are
+/// rejected.
+pub(super) fn authorize_export(keys: &JwtKeys, token: &str, conversation_id: Uuid) -> Option<Uuid> {
+    let claims = match keys.decode::<ExportClaims>(token) {
+        Ok(decoded) => decoded.claims,
+        Err(err) => {
+            debug!("Rejected export token: {:?}", err);
+            return None;
+        }
+    };
+    if claims.conversation_id != conversation_id {
+        debug!("Rejected an export token of another conversation");
+        return None;
+    }
+
+    Some(claims.sub)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::user::Claims;
+
+    fn keys() -> JwtKeys {
+        JwtKeys::parse(None, Some("test secret"), false).expect("keys are valid")
+    }
+
+    /// token signs a token for the user, `days` apart so tokens of the same user differ.
+    fn token(keys: &JwtKeys, user_id: Uuid, days: i64) -> String {
+        let claims = Claims {
+            sub: user_id,
+            exp: (Utc::now() + chrono::Duration::days(days)).timestamp() as usize,
+        };
+        keys.sign(&claims).expect("failed to sign")
+    }
+
+    #[test]
+    fn rejects_logged_out_tokens() {
+        let keys = keys();
+        let user_id = Uuid::new_v4();
+        let token = token(&keys, user_id, 30);
+        let mut stored = vec![token.clone()];
+
+        assert_eq!(authorize(&keys, &token, |_| Some(&stored)), Some(user_id));
+
+        // logout deletes the token the request was made with
+        stored.retain(|stored| stored != &token);
+        assert_eq!(authorize(&keys, &token, |_| Some(&stored)), None);
+    }
+
+    #[test]
+    fn revoking_another_session_keeps_the_current_one() {
+        let keys = keys();
+        let user_id = Uuid::new_v4();
+        let current = token(&keys, user_id, 30);
+        let other = token(&keys, user_id, 29);
+        let mut stored = vec![current.clone(), other.clone()];
+
+        assert_eq!(authorize(&keys, &other, |_| Some(&stored)), Some(user_id));
+
+        stored.retain(|stored| stored != &other);
+        assert_eq!(authorize(&keys, &other, |_| Some(&stored)), None);
+        assert_eq!(authorize(&keys, &current, |_| Some(&stored)), Some(user_id));
+    }
+
+    #[test]
+    fn rejects_tokens_of_unknown_users() {
+        let keys = keys();
+        let token = token(&keys, Uuid::new_v4(), 30);
+
+        assert_eq!(authorize(&keys, &token, |_| None), None);
+        assert_eq!(authorize(&keys, &token, |_| Some(&[])), None);
+    }
+
+    fn export_token(keys: &JwtKeys, user_id: Uuid, conversation_id: Uuid, minutes: i64) -> String {
+        let claims = ExportClaims {
+            sub: user_id,
+            conversation_id,
+            exp: (Utc::now() + chrono::Duration::minutes(minutes)).timestamp() as usize,
+        };
+        keys.sign(&claims).expect("failed to sign")
+    }
+
+    #[test]
+    fn export_tokens_only_open_their_conversation() {
+        let keys = keys();
+        let (user_id, conversation_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let token = export_token(&keys, user_id, conversation_id, EXPORT_TOKEN_MINUTES);
+
+        assert_eq!(
+            authorize_export(&keys, &token, conversation_id),
+            Some(user_id)
+        );
+        assert_eq!(authorize_export(&keys, &token, Uuid::new_v4()), None);
+        // past the leeway of the expiry check
+        let expired = export_token(&keys, user_id, conversation_id, -5);
+        assert_eq!(authorize_export(&keys, &expired, conversation_id), None);
+    }
+
+    #[test]
+    fn session_and_export_tokens_are_not_interchangeable() {
+        let keys = keys();
+        let (user_id, conversation_id) = (Uuid::new_v4(), Uuid::new_v4());
+        let session = token(&keys, user_id, 30);
+        let export = export_token(&keys, user_id, conversation_id, EXPORT_TOKEN_MINUTES);
+        let stored = vec![session.clone()];
+
+        assert_eq!(authorize_export(&keys, &session, conversation_id), None);
+        assert_eq!(authorize(&keys, &export, |_| Some(&stored)), None);
+    }
+}
@@ -1,7 +1,8 @@
 use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
 use std::sync::Arc;
 
-use std::{env, net::SocketAddr};
+use std::{env, net::SocketAddr, path::Path};
 
 use super::Node;
 use custom_prisma::prisma::{self, PrismaClient};
@@ -51,9 +52,115 @@ pub async fn load_and_migrate(db_url: &str) -> Result<PrismaClient> {
     Ok(client)
 }
 
+/// hash_file returns the hex encoded blake3 hash of the file's content.
+pub async fn hash_file(path: impl AsRef<Path>) -> Result<String> {
+    let path = path.as_ref();
+    let bytes = tokio::fs::read(path)
+        .await
+        .with_context(|| format!("Failed to read {:?} for hashing", path))?;
+    Ok(blake3::hash(&bytes).to_hex().to_string())
+}
+
+/// How a learned file on disk compares to the content it was learned from.
+#[derive(Debug)]
+pub enum FileChange {
+    /// not modified since it was indexed
+    Unchanged,
+    /// modified since it was indexed, but the content is the same
+    Touched,
+    Changed,
+    Unreadable(anyhow::Error),
+}
+
+/// check_file compares the file with the hash of the content it was indexed with. The modification
+/// time is only a cheap pre-check, the hash decides.
+pub async fn check_file(
+    path: impl AsRef<Path>,
+    date_indexed: DateTime<Utc>,
+    content_hash: Option<&str>,
+) -> FileChange {
+    let path = path.as_ref();
+    let modified = match tokio::fs::metadata(path).await.and_then(|m| m.modified()) {
+        Ok(modified) => DateTime::<Utc>::from(modified),
+        Err(e) => {
+            return FileChange::Unreadable(
+                anyhow::Error::new(e).context(format!("Failed to read metadata of {:?}", path)),
+            )
+        }
+    };
+    if modified <= date_indexed {
+        return FileChange::Unchanged;
+    }
+
+    match hash_file(path).await {
+        Ok(hash) if content_hash == Some(hash.as_str()) => FileChange::Touched,
+        Ok(_) => FileChange::Changed,
+        Err(e) => FileChange::Unreadable(e),
+    }
+}
+
 // pub static   port = env::var("PORT")
 // .map(|port| port.parse::<u16>().unwrap_or(8080));
 
 pub fn python_server_root() -> String {
     env::var("PYTHON_SERVER_ROOT").unwrap_or("http://localhost:5001".to_string())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn write_temp(content: &str) -> std::path::PathBuf {
+        let path = env::temp_dir().join(format!("yerb-utils-{}.txt", Uuid::new_v4()));
+        tokio::fs::write(&path, content)
+            .await
+            .expect("failed to write temp file");
+        path
+    }
+
+    #[tokio::test]
+    async fn hashes_the_content() {
+        let path = write_temp("Osmosis is the diffusion of water.").await;
+
+        let hash = hash_file(&path).await.expect("failed to hash file");
+        assert_eq!(
+            hash,
+            blake3::hash(b"Osmosis is the diffusion of water.")
+                .to_hex()
+                .to_string()
+        );
+
+        tokio::fs::write(&path, "Osmosis is passive.")
+            .await
+            .expect("failed to write temp file");
+        assert_ne!(hash_file(&path).await.expect("failed to hash file"), hash);
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn tells_touched_files_from_changed_ones() {
+        let path = write_temp("Osmosis is the diffusion of water.").await;
+        let hash = hash_file(&path).await.expect("failed to hash file");
+        let before = Utc::now() - chrono::Duration::hours(1);
+
+        assert!(matches!(
+            check_file(&path, Utc::now() + chrono::Duration::hours(1), Some(&hash)).await,
+            FileChange::Unchanged
+        ));
+        assert!(matches!(
+            check_file(&path, before, Some(&hash)).await,
+            FileChange::Touched
+        ));
+        assert!(matches!(
+            check_file(&path, before, Some("an older hash")).await,
+            FileChange::Changed
+        ));
+        assert!(matches!(
+            check_file(path.with_extension("missing"), before, Some(&hash)).await,
+            FileChange::Unreadable(_)
+        ));
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+}